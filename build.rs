@@ -0,0 +1,57 @@
+#[path = "src/bindgen.rs"]
+#[allow(dead_code)]
+mod bindgen;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// (abi file, contract name, output file)
+const BUNDLED_ABIS: &[(&str, &str, &str)] = &[
+    ("abi/TokenAbi.json", "Token", "token.rs"),
+    (
+        "abi/standartToken.json",
+        "StandardToken",
+        "standard_token.rs",
+    ),
+    (
+        "abi/PancakeRouterAbi.json",
+        "PancakeRouter",
+        "pancake_router.rs",
+    ),
+    (
+        "abi/PancakeFactoryAbi.json",
+        "PancakeFactory",
+        "pancake_factory.rs",
+    ),
+    (
+        "abi/PancakeLPTokenAbi.json",
+        "PancakePair",
+        "pancake_pair.rs",
+    ),
+    (
+        "abi/EACAggregatorProxy.json",
+        "AggregatorProxy",
+        "aggregator_proxy.rs",
+    ),
+    ("abi/DisperseAbi.json", "Disperse", "disperse.rs"),
+    (
+        "abi/WrappedNativeAbi.json",
+        "WrappedNative",
+        "wrapped_native.rs",
+    ),
+];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/bindgen.rs");
+
+    for (abi_path, contract_name, file_name) in BUNDLED_ABIS {
+        println!("cargo:rerun-if-changed={}", abi_path);
+
+        let abi_json = fs::read_to_string(abi_path).unwrap();
+        let code = bindgen::generate_bindings_with_crate_path(contract_name, &abi_json, "crate")
+            .unwrap_or_else(|e| panic!("can't generate bindings for {}: {}", abi_path, e));
+        fs::write(Path::new(&out_dir).join(file_name), code).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Allowance checks before the router (or any spender) pulls tokens with
+//! `transferFrom`.
+
+use crate::Web3Manager;
+use hex_literal::hex;
+use std::collections::HashSet;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+use web3::types::{H160, H256, U256};
+
+const APPROVAL_RECEIPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Tokens that revert `approve` from one non-zero allowance to another, so
+/// the allowance has to be reset to zero first (USDT on Ethereum).
+pub const RESET_FIRST_TOKENS: [H160; 1] = [H160(hex!("dAC17F958D2ee523a2206206994597C13D831ec7"))];
+
+/// How much to approve when the allowance is too low.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    /// Only the amount about to be spent.
+    Exact,
+    /// `U256::MAX`, so later spends don't need a new approval.
+    Unlimited,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllowanceSettings {
+    pub policy: ApprovalPolicy,
+    /// Tokens whose allowance is set to zero before being changed.
+    pub reset_first: HashSet<H160>,
+    pub receipt_timeout: Duration,
+}
+
+impl Default for AllowanceSettings {
+    fn default() -> AllowanceSettings {
+        AllowanceSettings {
+            policy: ApprovalPolicy::Exact,
+            reset_first: RESET_FIRST_TOKENS.iter().copied().collect(),
+            receipt_timeout: APPROVAL_RECEIPT_TIMEOUT,
+        }
+    }
+}
+
+/// `approve` values to send, in order, to spend `needed` with `current`
+/// allowance. Empty if the allowance is already enough.
+pub fn approval_steps(
+    current: U256,
+    needed: U256,
+    policy: ApprovalPolicy,
+    reset_first: bool,
+) -> Vec<U256> {
+    if current >= needed {
+        return Vec::new();
+    }
+    let value = match policy {
+        ApprovalPolicy::Exact => needed,
+        ApprovalPolicy::Unlimited => U256::MAX,
+    };
+    if reset_first && !current.is_zero() {
+        vec![U256::zero(), value]
+    } else {
+        vec![value]
+    }
+}
+
+/// Whether `err` is the node refusing a call because it reverts, as opposed
+/// to a transport, fee or nonce failure.
+pub fn is_execution_revert(err: &(dyn Error + 'static)) -> bool {
+    let rpc_error = match err.downcast_ref::<web3::contract::Error>() {
+        Some(web3::contract::Error::Api(web3::Error::Rpc(rpc_error))) => rpc_error,
+        _ => match err.downcast_ref::<web3::Error>() {
+            Some(web3::Error::Rpc(rpc_error)) => rpc_error,
+            _ => return false,
+        },
+    };
+    // geth answers code 3 with the revert data, other nodes only say it
+    rpc_error.code.code() == 3 || rpc_error.message.contains("revert")
+}
+
+impl Web3Manager {
+    pub fn allowance_settings(&self) -> &AllowanceSettings {
+        &self.allowance_settings
+    }
+
+    pub fn set_approval_policy(&mut self, policy: ApprovalPolicy) {
+        self.allowance_settings.policy = policy;
+    }
+
+    /// Marks `token` as needing a reset to zero before a new approval.
+    pub fn require_allowance_reset(&mut self, token: H160) {
+        self.allowance_settings.reset_first.insert(token);
+    }
+
+    /// Makes sure `spender` can pull `amount` of `token_address` from
+    /// `account`, approving per the approval policy and waiting for every
+    /// approval to be mined. Returns the last approval sent, if any.
+    ///
+    /// A token whose approval reverts while an allowance is set is reset to
+    /// zero and approved again, even if it wasn't marked. Other errors are
+    /// returned as they are.
+    pub async fn ensure_allowance(
+        &mut self,
+        account: H160,
+        token_address: &str,
+        spender: &str,
+        amount: U256,
+    ) -> Result<Option<H256>, Box<dyn Error>> {
+        let token = H160::from_str(token_address)?;
+        let current = self
+            .token_bindings(token)
+            .allowance(account, H160::from_str(spender)?)
+            .await?;
+
+        let reset_first = self.allowance_settings.reset_first.contains(&token);
+        let steps = approval_steps(current, amount, self.allowance_settings.policy, reset_first);
+
+        let mut last_tx = None;
+        for value in steps {
+            let tx_hash = match self
+                .approve_erc20_token(account, token_address, spender, &value.to_string())
+                .await
+            {
+                Ok(tx_hash) => tx_hash,
+                Err(err)
+                    if !reset_first && !current.is_zero() && is_execution_revert(err.as_ref()) =>
+                {
+                    self.approve_and_wait(account, token_address, spender, U256::zero())
+                        .await?;
+                    self.allowance_settings.reset_first.insert(token);
+                    self.approve_erc20_token(account, token_address, spender, &value.to_string())
+                        .await?
+                }
+                Err(err) => return Err(err),
+            };
+            self.wait_for_receipt(tx_hash, self.allowance_settings.receipt_timeout)
+                .await?;
+            last_tx = Some(tx_hash);
+        }
+        Ok(last_tx)
+    }
+
+    async fn approve_and_wait(
+        &mut self,
+        account: H160,
+        token_address: &str,
+        spender: &str,
+        value: U256,
+    ) -> Result<H256, Box<dyn Error>> {
+        let tx_hash = self
+            .approve_erc20_token(account, token_address, spender, &value.to_string())
+            .await?;
+        self.wait_for_receipt(tx_hash, self.allowance_settings.receipt_timeout)
+            .await?;
+        Ok(tx_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::mock_manager;
+    use jsonrpc_core::types::error::{Error as RpcError, ErrorCode};
+
+    #[test]
+    fn skips_enough_allowance() {
+        let steps = approval_steps(U256::from(10), U256::from(10), ApprovalPolicy::Exact, true);
+        assert!(steps.is_empty());
+    }
+
+    #[test]
+    fn approves_per_policy() {
+        assert_eq!(
+            approval_steps(U256::zero(), U256::from(10), ApprovalPolicy::Exact, false),
+            vec![U256::from(10)]
+        );
+        assert_eq!(
+            approval_steps(
+                U256::from(5),
+                U256::from(10),
+                ApprovalPolicy::Unlimited,
+                false
+            ),
+            vec![U256::MAX]
+        );
+    }
+
+    #[test]
+    fn resets_to_zero_first() {
+        assert_eq!(
+            approval_steps(U256::from(5), U256::from(10), ApprovalPolicy::Exact, true),
+            vec![U256::zero(), U256::from(10)]
+        );
+        // nothing to reset
+        assert_eq!(
+            approval_steps(U256::zero(), U256::from(10), ApprovalPolicy::Exact, true),
+            vec![U256::from(10)]
+        );
+        assert!(AllowanceSettings::default()
+            .reset_first
+            .contains(&H160::from_str("0xdAC17F958D2ee523a2206206994597C13D831ec7").unwrap()));
+    }
+
+    #[test]
+    fn only_reverts_need_a_reset() {
+        let rpc_error = |code: i64, message: &str| RpcError {
+            code: ErrorCode::ServerError(code),
+            message: message.to_string(),
+            data: None,
+        };
+        let estimation: Box<dyn Error> = Box::new(web3::contract::Error::Api(web3::Error::Rpc(
+            rpc_error(3, "execution reverted: approve from non-zero"),
+        )));
+        assert!(is_execution_revert(estimation.as_ref()));
+        let reverted: Box<dyn Error> =
+            Box::new(web3::Error::Rpc(rpc_error(-32000, "execution reverted")));
+        assert!(is_execution_revert(reverted.as_ref()));
+
+        let underpriced: Box<dyn Error> = Box::new(web3::contract::Error::Api(web3::Error::Rpc(
+            rpc_error(-32000, "transaction underpriced"),
+        )));
+        assert!(!is_execution_revert(underpriced.as_ref()));
+        let timeout: Box<dyn Error> = Box::new(web3::Error::Transport(
+            web3::error::TransportError::Message("timed out".to_string()),
+        ));
+        assert!(!is_execution_revert(timeout.as_ref()));
+        let budget: Box<dyn Error> = "gas budget exceeded".into();
+        assert!(!is_execution_revert(budget.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn returns_allowance_read_errors() {
+        let (mut web3m, _) = mock_manager(|_, _| Err("header not found".to_string())).await;
+        let token = format!("{:?}", H160::repeat_byte(1));
+        let spender = format!("{:?}", H160::repeat_byte(2));
+        let result = web3m
+            .ensure_allowance(H160::repeat_byte(3), &token, &spender, U256::one())
+            .await;
+        assert!(result.unwrap_err().to_string().contains("header not found"));
+    }
+}
@@ -0,0 +1,263 @@
+//! Constant product (Uniswap V2 style) pool math, the same formulas the
+//! router uses in `getAmountsOut`/`getAmountsIn`, computed locally.
+
+use web3::types::U256;
+
+/// Swap fee of Uniswap V2 pairs, in basis points.
+pub const UNISWAP_V2_FEE_BPS: u32 = 30;
+/// Swap fee of PancakeSwap V2 pairs, in basis points.
+pub const PANCAKE_V2_FEE_BPS: u32 = 25;
+
+/// LP tokens locked forever by the first mint of a pair.
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+
+const BPS: u32 = 10_000;
+
+/// Output of swapping `amount_in` against the reserves, after the pool fee.
+/// Returns zero if the pool is empty.
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> U256 {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return U256::zero();
+    }
+    let amount_in_with_fee = amount_in * (BPS - fee_bps);
+    let numerator = amount_in_with_fee * reserve_out;
+    let denominator = reserve_in * BPS + amount_in_with_fee;
+    numerator / denominator
+}
+
+/// Input needed to receive exactly `amount_out`, after the pool fee.
+/// Returns `None` if the pool can't provide that much.
+pub fn get_amount_in(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee_bps: u32,
+) -> Option<U256> {
+    if amount_out.is_zero() || reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+    let numerator = reserve_in * amount_out * BPS;
+    let denominator = (reserve_out - amount_out) * (BPS - fee_bps);
+    Some(numerator / denominator + 1)
+}
+
+/// Amount of the other token worth `amount` at the pool price, without fee
+/// or price impact (the router `quote`).
+pub fn quote(amount: U256, reserve_a: U256, reserve_b: U256) -> U256 {
+    if reserve_a.is_zero() {
+        return U256::zero();
+    }
+    amount * reserve_b / reserve_a
+}
+
+/// Price impact of a swap in basis points: how far the execution price is
+/// from the pool price before the swap, fee excluded.
+pub fn price_impact_bps(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> u32 {
+    let spot_out = quote(amount_in, reserve_in, reserve_out);
+    if spot_out.is_zero() {
+        return 0;
+    }
+    let amount_in_after_fee = amount_in * (BPS - fee_bps) / BPS;
+    let no_impact_out = quote(amount_in_after_fee, reserve_in, reserve_out);
+    let amount_out = get_amount_out(amount_in, reserve_in, reserve_out, fee_bps);
+    // both are rounded down, a tiny swap can get more than the unrounded quote
+    if no_impact_out.is_zero() || amount_out >= no_impact_out {
+        return 0;
+    }
+    ((no_impact_out - amount_out) * BPS / no_impact_out).as_u32()
+}
+
+/// Price impact in basis points of a multi-hop swap that turned `amount_in`
+/// into `amount_out`, given the `(reserve_in, reserve_out)` of every hop.
+pub fn route_price_impact_bps(
+    amount_in: U256,
+    amount_out: U256,
+    reserves: &[(U256, U256)],
+    fee_bps: u32,
+) -> u32 {
+    let mut no_impact_out = amount_in;
+    for (reserve_in, reserve_out) in reserves {
+        no_impact_out = quote(
+            no_impact_out * (BPS - fee_bps) / BPS,
+            *reserve_in,
+            *reserve_out,
+        );
+    }
+    if no_impact_out.is_zero() || amount_out >= no_impact_out {
+        return 0;
+    }
+    ((no_impact_out - amount_out) * BPS / no_impact_out).as_u32()
+}
+
+/// Amounts the router actually deposits in `addLiquidity`: the desired
+/// amount of one side and the matching amount of the other at the pool
+/// price. `None` if neither desired amount is enough for the other side.
+pub fn optimal_liquidity_amounts(
+    amount_a_desired: U256,
+    amount_b_desired: U256,
+    reserve_a: U256,
+    reserve_b: U256,
+) -> Option<(U256, U256)> {
+    if reserve_a.is_zero() && reserve_b.is_zero() {
+        return Some((amount_a_desired, amount_b_desired));
+    }
+    let amount_b_optimal = quote(amount_a_desired, reserve_a, reserve_b);
+    if amount_b_optimal <= amount_b_desired {
+        return Some((amount_a_desired, amount_b_optimal));
+    }
+    let amount_a_optimal = quote(amount_b_desired, reserve_b, reserve_a);
+    if amount_a_optimal <= amount_a_desired {
+        return Some((amount_a_optimal, amount_b_desired));
+    }
+    None
+}
+
+/// LP tokens minted for depositing `amount0`/`amount1`. The protocol fee
+/// mint (`kLast`) is ignored, so the result can be slightly high when the
+/// fee is on.
+pub fn liquidity_minted(
+    amount0: U256,
+    amount1: U256,
+    reserve0: U256,
+    reserve1: U256,
+    total_supply: U256,
+) -> U256 {
+    if total_supply.is_zero() {
+        return (amount0 * amount1)
+            .integer_sqrt()
+            .saturating_sub(U256::from(MINIMUM_LIQUIDITY));
+    }
+    if reserve0.is_zero() || reserve1.is_zero() {
+        return U256::zero();
+    }
+    std::cmp::min(
+        amount0 * total_supply / reserve0,
+        amount1 * total_supply / reserve1,
+    )
+}
+
+/// `(amount0, amount1)` returned by burning `liquidity` LP tokens.
+pub fn liquidity_underlying(
+    liquidity: U256,
+    reserve0: U256,
+    reserve1: U256,
+    total_supply: U256,
+) -> (U256, U256) {
+    if total_supply.is_zero() {
+        return (U256::zero(), U256::zero());
+    }
+    (
+        liquidity * reserve0 / total_supply,
+        liquidity * reserve1 / total_supply,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amount_out_matches_router() {
+        // 1 token in against a 1000/1000 pool with the 0.3% fee
+        let out = get_amount_out(
+            U256::exp10(18),
+            U256::exp10(21),
+            U256::exp10(21),
+            UNISWAP_V2_FEE_BPS,
+        );
+        assert_eq!(out, U256::from_dec_str("996006981039903216").unwrap());
+        assert!(get_amount_out(U256::one(), U256::zero(), U256::exp10(18), 30).is_zero());
+    }
+
+    #[test]
+    fn amount_in_round_trips() {
+        let (reserve_in, reserve_out) = (U256::exp10(21), U256::exp10(20));
+        let amount_out = U256::exp10(18);
+        let amount_in =
+            get_amount_in(amount_out, reserve_in, reserve_out, PANCAKE_V2_FEE_BPS).unwrap();
+
+        assert!(
+            get_amount_out(amount_in, reserve_in, reserve_out, PANCAKE_V2_FEE_BPS) >= amount_out
+        );
+        assert!(
+            get_amount_out(amount_in - 1, reserve_in, reserve_out, PANCAKE_V2_FEE_BPS) < amount_out
+        );
+        assert_eq!(
+            get_amount_in(reserve_out, reserve_in, reserve_out, 25),
+            None
+        );
+    }
+
+    #[test]
+    fn price_impact_grows_with_size() {
+        let reserve = U256::exp10(21);
+        let small = price_impact_bps(U256::exp10(15), reserve, reserve, 30);
+        let large = price_impact_bps(U256::exp10(20), reserve, reserve, 30);
+        assert_eq!(small, 0);
+        assert!(large > 800 && large < 1000, "{}", large);
+        // the fee rounds the quote down to 2000 while the swap gets 2983
+        assert_eq!(
+            price_impact_bps(U256::from(3), U256::from(1000), U256::exp10(6), 25),
+            0
+        );
+    }
+
+    #[test]
+    fn route_impact_matches_single_hop() {
+        let (reserve_in, reserve_out) = (U256::exp10(21), U256::exp10(21));
+        let amount_in = U256::exp10(20);
+        let amount_out = get_amount_out(amount_in, reserve_in, reserve_out, 30);
+
+        assert_eq!(
+            route_price_impact_bps(amount_in, amount_out, &[(reserve_in, reserve_out)], 30),
+            price_impact_bps(amount_in, reserve_in, reserve_out, 30)
+        );
+    }
+
+    #[test]
+    fn optimal_amounts_follow_pool_price() {
+        let (reserve_a, reserve_b) = (U256::from(1000), U256::from(2000));
+
+        assert_eq!(
+            optimal_liquidity_amounts(U256::from(10), U256::from(50), reserve_a, reserve_b),
+            Some((U256::from(10), U256::from(20)))
+        );
+        assert_eq!(
+            optimal_liquidity_amounts(U256::from(10), U256::from(10), reserve_a, reserve_b),
+            Some((U256::from(5), U256::from(10)))
+        );
+        assert_eq!(
+            optimal_liquidity_amounts(U256::from(7), U256::from(9), U256::zero(), U256::zero()),
+            Some((U256::from(7), U256::from(9)))
+        );
+    }
+
+    #[test]
+    fn mint_and_burn_liquidity() {
+        // first deposit locks MINIMUM_LIQUIDITY
+        let first = liquidity_minted(
+            U256::from(4_000_000),
+            U256::from(1_000_000),
+            U256::zero(),
+            U256::zero(),
+            U256::zero(),
+        );
+        assert_eq!(first, U256::from(2_000_000 - MINIMUM_LIQUIDITY));
+
+        let (reserve0, reserve1, total_supply) =
+            (U256::from(4000), U256::from(1000), U256::from(2000));
+        let minted = liquidity_minted(
+            U256::from(400),
+            U256::from(200),
+            reserve0,
+            reserve1,
+            total_supply,
+        );
+        assert_eq!(minted, U256::from(200));
+
+        assert_eq!(
+            liquidity_underlying(minted, reserve0, reserve1, total_supply),
+            (U256::from(400), U256::from(100))
+        );
+    }
+}
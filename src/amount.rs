@@ -0,0 +1,128 @@
+//! Token amounts in their smallest unit, parsed from and formatted to
+//! decimal strings without going through floats.
+
+use std::error::Error;
+use std::fmt;
+use web3::types::U256;
+
+/// Decimals of the native token of every EVM chain.
+pub const NATIVE_DECIMALS: u8 = 18;
+
+/// An amount in the smallest unit of its token, wei for the native token.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(pub U256);
+
+impl Amount {
+    pub fn wei(wei: U256) -> Amount {
+        Amount(wei)
+    }
+
+    /// Native token amount from a decimal string, e.g. `"0.1"`.
+    pub fn ether(amount: &str) -> Result<Amount, Box<dyn Error>> {
+        Amount::from_units(amount, NATIVE_DECIMALS)
+    }
+
+    /// Amount of a token with `decimals` from a decimal string.
+    pub fn from_units(amount: &str, decimals: u8) -> Result<Amount, Box<dyn Error>> {
+        let amount = amount.trim();
+        let (integer, fraction) = match amount.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (amount, ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (integer.is_empty() && fraction.is_empty())
+            || !is_digits(integer)
+            || !is_digits(fraction)
+        {
+            return Err(format!("invalid amount {:?}", amount).into());
+        }
+        if fraction.len() > decimals as usize {
+            return Err(format!("{} has more than {} decimals", amount, decimals).into());
+        }
+
+        let unit = U256::exp10(decimals as usize);
+        let integer = match integer {
+            "" => U256::zero(),
+            integer => U256::from_dec_str(integer)?,
+        };
+        let fraction = match fraction {
+            "" => U256::zero(),
+            fraction => {
+                U256::from_dec_str(fraction)? * U256::exp10(decimals as usize - fraction.len())
+            }
+        };
+        integer
+            .checked_mul(unit)
+            .and_then(|wei| wei.checked_add(fraction))
+            .map(Amount)
+            .ok_or_else(|| format!("{} overflows", amount).into())
+    }
+
+    pub fn as_wei(&self) -> U256 {
+        self.0
+    }
+
+    /// Decimal string of the amount of a token with `decimals`, without
+    /// trailing zeros.
+    pub fn to_units(&self, decimals: u8) -> String {
+        let unit = U256::exp10(decimals as usize);
+        let integer = self.0 / unit;
+        let fraction = self.0 % unit;
+        if fraction.is_zero() {
+            return integer.to_string();
+        }
+        let fraction = format!("{:0>width$}", fraction, width = decimals as usize);
+        format!("{}.{}", integer, fraction.trim_end_matches('0'))
+    }
+}
+
+impl From<U256> for Amount {
+    fn from(wei: U256) -> Amount {
+        Amount(wei)
+    }
+}
+
+/// The amount as native token, e.g. `0.1`.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_units(NATIVE_DECIMALS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_amounts() {
+        assert_eq!(
+            Amount::ether("0.1").unwrap().as_wei(),
+            U256::from(100_000_000_000_000_000u64)
+        );
+        assert_eq!(
+            Amount::ether("2").unwrap().as_wei(),
+            U256::from(2_000_000_000_000_000_000u64)
+        );
+        assert_eq!(
+            Amount::from_units("1.5", 6).unwrap(),
+            Amount(U256::from(1_500_000))
+        );
+        assert_eq!(Amount::from_units(".5", 1).unwrap(), Amount(U256::from(5)));
+
+        assert!(Amount::from_units("1.0000001", 6).is_err());
+        assert!(Amount::ether("-1").is_err());
+        assert!(Amount::ether("1e18").is_err());
+        assert!(Amount::ether(".").is_err());
+    }
+
+    #[test]
+    fn formats_amounts() {
+        assert_eq!(Amount(U256::from(1_500_000)).to_units(6), "1.5");
+        assert_eq!(Amount(U256::from(1)).to_units(6), "0.000001");
+        assert_eq!(Amount::ether("3").unwrap().to_string(), "3");
+        assert_eq!(
+            Amount::ether("0.123456789").unwrap().to_string(),
+            "0.123456789"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Audit of the ERC20 allowances granted by the loaded accounts.
+//!
+//! `Approval` logs are backfilled to find every (token, spender) pair an
+//! account approved in the scanned blocks, the current allowance is read
+//! back with `allowance(owner, spender)` and only the non-zero ones are
+//! reported. Emitters that can't answer `allowance` aren't ERC20 tokens and
+//! are skipped. Revoking sends `approve(spender, 0)` through
+//! `approve_erc20_token`.
+
+use crate::allowance::is_execution_revert;
+use crate::Web3Manager;
+use hex_literal::hex;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use web3::types::{BlockNumber, FilterBuilder, Log, H160, H256, U256, U64};
+
+/// `keccak256("Approval(address,address,uint256)")`
+pub const APPROVAL_TOPIC: H256 = H256(hex!(
+    "8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925"
+));
+
+/// Allowances at or above this are reported as unlimited, wallets and
+/// dapps approve `U256::MAX` or values close to it.
+pub const UNLIMITED_THRESHOLD: U256 = U256([0, 0, 0, 1 << 63]);
+
+/// Blocks scanned back from `to_block` when no `from_block` is set, about a
+/// week of BSC blocks.
+pub const DEFAULT_SCAN_BLOCKS: u64 = 200_000;
+
+/// Block range to backfill and how many blocks to ask the node for at once,
+/// most providers cap the range of `eth_getLogs`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApprovalScan {
+    /// `DEFAULT_SCAN_BLOCKS` before `to_block` when `None`, set it to the
+    /// first block of the account to audit its whole history.
+    pub from_block: Option<u64>,
+    /// Latest block when `None`.
+    pub to_block: Option<u64>,
+    pub block_chunk: u64,
+}
+
+impl Default for ApprovalScan {
+    fn default() -> ApprovalScan {
+        ApprovalScan {
+            from_block: None,
+            to_block: None,
+            block_chunk: 5_000,
+        }
+    }
+}
+
+/// An ERC20 `Approval` log. ERC721 logs share the topic but index the token
+/// id as a fourth topic, those are not decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApprovalLog {
+    pub token: H160,
+    pub owner: H160,
+    pub spender: H160,
+    pub value: U256,
+    pub block_number: Option<U64>,
+    pub transaction_hash: Option<H256>,
+}
+
+impl ApprovalLog {
+    pub fn from_log(log: &Log) -> Option<ApprovalLog> {
+        if log.topics.len() != 3 || log.topics[0] != APPROVAL_TOPIC || log.data.0.len() != 32 {
+            return None;
+        }
+        Some(ApprovalLog {
+            token: log.address,
+            owner: H160::from(log.topics[1]),
+            spender: H160::from(log.topics[2]),
+            value: U256::from_big_endian(&log.data.0),
+            block_number: log.block_number,
+            transaction_hash: log.transaction_hash,
+        })
+    }
+}
+
+/// Latest approval log of every (owner, token, spender), logs are expected
+/// in chain order.
+pub fn latest_approvals(logs: &[ApprovalLog]) -> BTreeMap<(H160, H160, H160), ApprovalLog> {
+    let mut latest = BTreeMap::new();
+    for log in logs {
+        latest.insert((log.owner, log.token, log.spender), *log);
+    }
+    latest
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TokenMetadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+/// A non-zero allowance found by the audit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AllowanceEntry {
+    pub owner: H160,
+    pub token: H160,
+    pub token_metadata: TokenMetadata,
+    pub spender: H160,
+    /// `false` for an externally owned account.
+    pub spender_is_contract: bool,
+    pub allowance: U256,
+    /// Block and transaction of the last `Approval` seen.
+    pub approved_at: Option<U64>,
+    pub approval_transaction: Option<H256>,
+}
+
+impl AllowanceEntry {
+    pub fn is_unlimited(&self) -> bool {
+        self.allowance >= UNLIMITED_THRESHOLD
+    }
+}
+
+// Whether an `allowance` call failed because the contract doesn't implement
+// it rather than because of the node
+fn is_allowance_unavailable(err: &web3::contract::Error) -> bool {
+    match err {
+        web3::contract::Error::InvalidOutputType(_) | web3::contract::Error::Abi(_) => true,
+        web3::contract::Error::Api(_) => is_execution_revert(err),
+        _ => false,
+    }
+}
+
+impl Web3Manager {
+    /// Backfills the `Approval` logs emitted for `owner` by any token.
+    pub async fn approval_logs(
+        &self,
+        owner: H160,
+        scan: ApprovalScan,
+    ) -> Result<Vec<ApprovalLog>, Box<dyn Error>> {
+        let to_block = match scan.to_block {
+            Some(block) => block,
+            None => self.web3http.eth().block_number().await?.as_u64(),
+        };
+        let block_chunk = scan.block_chunk.max(1);
+
+        let mut approvals = Vec::new();
+        let mut from_block = scan
+            .from_block
+            .unwrap_or_else(|| to_block.saturating_sub(DEFAULT_SCAN_BLOCKS));
+        while from_block <= to_block {
+            let chunk_end = to_block.min(from_block.saturating_add(block_chunk - 1));
+            let filter = FilterBuilder::default()
+                .from_block(BlockNumber::Number(from_block.into()))
+                .to_block(BlockNumber::Number(chunk_end.into()))
+                .topics(
+                    Some(vec![APPROVAL_TOPIC]),
+                    Some(vec![H256::from(owner)]),
+                    None,
+                    None,
+                )
+                .build();
+            let logs = self.web3http.eth().logs(filter).await?;
+            approvals.extend(logs.iter().filter_map(ApprovalLog::from_log));
+            from_block = chunk_end + 1;
+        }
+        Ok(approvals)
+    }
+
+    /// Non-zero allowances of every loaded account, ordered by owner, token
+    /// and spender. Approvals of contracts without an ERC20 `allowance` are
+    /// skipped, node errors are returned.
+    pub async fn audit_allowances(
+        &self,
+        scan: ApprovalScan,
+    ) -> Result<Vec<AllowanceEntry>, Box<dyn Error>> {
+        let mut logs = Vec::new();
+        for owner in &self.accounts {
+            logs.extend(self.approval_logs(*owner, scan).await?);
+        }
+
+        let mut token_metadata: HashMap<H160, TokenMetadata> = HashMap::new();
+        let mut spender_is_contract: HashMap<H160, bool> = HashMap::new();
+        let mut entries = Vec::new();
+        for ((owner, token, spender), log) in latest_approvals(&logs) {
+            let allowance = match self.token_bindings(token).allowance(owner, spender).await {
+                Ok(allowance) => allowance,
+                // no `allowance(address,address)`: it reverts, or the
+                // output isn't a uint256
+                Err(err) if !is_allowance_unavailable(&err) => return Err(err.into()),
+                Err(_) => continue,
+            };
+            if allowance.is_zero() {
+                continue;
+            }
+
+            if let Entry::Vacant(slot) = token_metadata.entry(token) {
+                slot.insert(self.token_metadata(token).await);
+            }
+            if let Entry::Vacant(slot) = spender_is_contract.entry(spender) {
+                let code = self.web3http.eth().code(spender, None).await?;
+                slot.insert(!code.0.is_empty());
+            }
+
+            entries.push(AllowanceEntry {
+                owner,
+                token,
+                token_metadata: token_metadata[&token].clone(),
+                spender,
+                spender_is_contract: spender_is_contract[&spender],
+                allowance,
+                approved_at: log.block_number,
+                approval_transaction: log.transaction_hash,
+            });
+        }
+        Ok(entries)
+    }
+
+    // Name, symbol and decimals are optional in ERC20, missing ones are left empty
+    async fn token_metadata(&self, token: H160) -> TokenMetadata {
+        let token = self.token_bindings(token);
+        TokenMetadata {
+            name: token.name().await.ok(),
+            symbol: token.symbol().await.ok(),
+            decimals: token
+                .decimals()
+                .await
+                .ok()
+                .filter(|decimals| *decimals <= U256::from(u8::MAX))
+                .map(|decimals| decimals.as_u32() as u8),
+        }
+    }
+
+    /// Sets each allowance back to zero, one transaction per entry. Entries
+    /// of accounts that aren't loaded fail without sending anything.
+    pub async fn revoke_allowances(
+        &mut self,
+        entries: &[AllowanceEntry],
+    ) -> Vec<Result<H256, Box<dyn Error>>> {
+        let mut results = Vec::with_capacity(entries.len());
+        let mut nonce_owner = None;
+        for entry in entries {
+            if !self.has_signer(entry.owner) {
+                results.push(Err(
+                    format!("account {:?} is not loaded", entry.owner).into()
+                ));
+                continue;
+            }
+            // the nonce is shared by all accounts, resync it when the owner changes
+            if nonce_owner != Some(entry.owner) {
+                let nonce = self.last_nonce(entry.owner).await;
+                self.set_current_nonce(nonce);
+                nonce_owner = Some(entry.owner);
+            }
+            results.push(
+                self.approve_erc20_token(
+                    entry.owner,
+                    &format!("{:?}", entry.token),
+                    &format!("{:?}", entry.spender),
+                    "0",
+                )
+                .await,
+            );
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::{mock_manager, Requests};
+    use crate::signer::{LocalSigner, Signer};
+    use serde_json::json;
+    use std::str::FromStr;
+    use web3::types::Bytes;
+
+    fn approval(topics: Vec<H256>, value: U256) -> Log {
+        let mut data = [0u8; 32];
+        value.to_big_endian(&mut data);
+        Log {
+            address: H160::from_low_u64_be(1),
+            topics,
+            data: Bytes(data.to_vec()),
+            block_hash: None,
+            block_number: Some(U64::from(7)),
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    #[test]
+    fn decodes_erc20_approvals_only() {
+        let owner = H160::from_low_u64_be(2);
+        let spender = H160::from_low_u64_be(3);
+        let topics = vec![APPROVAL_TOPIC, H256::from(owner), H256::from(spender)];
+
+        let decoded = ApprovalLog::from_log(&approval(topics.clone(), U256::from(5))).unwrap();
+        assert_eq!(decoded.token, H160::from_low_u64_be(1));
+        assert_eq!(decoded.owner, owner);
+        assert_eq!(decoded.spender, spender);
+        assert_eq!(decoded.value, U256::from(5));
+
+        // ERC721 approval, token id indexed
+        let mut erc721 = topics;
+        erc721.push(H256::from_low_u64_be(9));
+        assert_eq!(ApprovalLog::from_log(&approval(erc721, U256::zero())), None);
+    }
+
+    #[test]
+    fn keeps_latest_approval() {
+        let log = ApprovalLog {
+            token: H160::from_low_u64_be(1),
+            owner: H160::from_low_u64_be(2),
+            spender: H160::from_low_u64_be(3),
+            value: U256::MAX,
+            block_number: Some(U64::from(1)),
+            transaction_hash: None,
+        };
+        let later = ApprovalLog {
+            value: U256::zero(),
+            block_number: Some(U64::from(2)),
+            ..log
+        };
+        let other_spender = ApprovalLog {
+            spender: H160::from_low_u64_be(4),
+            ..log
+        };
+
+        let latest = latest_approvals(&[log, other_spender, later]);
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[&(log.owner, log.token, log.spender)], later);
+    }
+
+    #[test]
+    fn flags_unlimited_allowances() {
+        let mut entry = AllowanceEntry {
+            owner: H160::zero(),
+            token: H160::zero(),
+            token_metadata: TokenMetadata::default(),
+            spender: H160::zero(),
+            spender_is_contract: true,
+            allowance: U256::MAX,
+            approved_at: None,
+            approval_transaction: None,
+        };
+        assert!(entry.is_unlimited());
+        entry.allowance = U256::from_dec_str("1000000000000000000000").unwrap();
+        assert!(!entry.is_unlimited());
+        assert_eq!(
+            APPROVAL_TOPIC,
+            H256::from_str("0x8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925")
+                .unwrap()
+        );
+    }
+
+    // A node where `token` 1 is an ERC20 with an allowance of 5 and `token` 2
+    // reverts `allowance`, `call_error` replaces every call result
+    async fn audited_manager(call_error: Option<&'static str>) -> (Web3Manager, Requests) {
+        let key = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        let owner = LocalSigner::from_str(key).unwrap().address();
+        let log = move |token: u64| {
+            json!({
+                "address": H160::from_low_u64_be(token),
+                "topics": [APPROVAL_TOPIC, H256::from(owner), H256::from(H160::from_low_u64_be(3))],
+                "data": format!("{:#066x}", 5),
+                "blockNumber": "0x7",
+            })
+        };
+        let (mut web3m, requests) = mock_manager(move |method, params| match method {
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_blockNumber" => Ok(json!("0x40000")),
+            "eth_getLogs" => Ok(json!([log(1), log(2)])),
+            "eth_getCode" => Ok(json!("0x")),
+            "eth_call" => match call_error {
+                Some(message) => Err(message.to_string()),
+                None if params[0]["to"] == json!(H160::from_low_u64_be(2)) => {
+                    Err("execution reverted".to_string())
+                }
+                None => Ok(json!(format!("{:#066x}", 5))),
+            },
+            _ => Err(format!("unexpected {}", method)),
+        })
+        .await;
+        web3m.load_account(key).await;
+        (web3m, requests)
+    }
+
+    #[tokio::test]
+    async fn skips_contracts_without_allowance() {
+        let (web3m, _) = audited_manager(None).await;
+        let entries = web3m
+            .audit_allowances(ApprovalScan::default())
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].token, H160::from_low_u64_be(1));
+        assert_eq!(entries[0].allowance, U256::from(5));
+    }
+
+    #[tokio::test]
+    async fn returns_node_errors_and_scans_recent_blocks() {
+        let (web3m, _) = audited_manager(Some("header not found")).await;
+        let err = web3m
+            .audit_allowances(ApprovalScan::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("header not found"));
+
+        let (web3m, requests) = audited_manager(None).await;
+        let account = web3m.first_account();
+        web3m
+            .approval_logs(account, ApprovalScan::default())
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        let first_scan = requests
+            .iter()
+            .find(|request| request["method"] == "eth_getLogs")
+            .unwrap();
+        // 0x40000 - DEFAULT_SCAN_BLOCKS, not the genesis block
+        assert_eq!(first_scan["params"][0]["fromBlock"], "0xf2c0");
+    }
+}
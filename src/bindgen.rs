@@ -0,0 +1,673 @@
+//! Rust bindings generator for contract ABIs.
+//!
+//! The crate runs this generator from its build script for every file in
+//! `abi/`. It only depends on `std` and `serde_json`, so it can also be used
+//! from the build script of a downstream crate:
+//!
+//! ```no_run
+//! // build.rs
+//! let json = std::fs::read_to_string("abi/MyToken.json").unwrap();
+//! let code = web3_rust_wrapper::bindgen::generate_bindings("MyToken", &json).unwrap();
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! std::fs::write(format!("{}/my_token.rs", out_dir), code).unwrap();
+//! ```
+//!
+//! and then, in the crate itself:
+//!
+//! ```ignore
+//! pub mod my_token {
+//!     include!(concat!(env!("OUT_DIR"), "/my_token.rs"));
+//! }
+//! ```
+
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+    "abstract", "become", "box", "do", "final", "macro", "override", "priv", "try", "typeof",
+    "unsized", "virtual", "yield",
+];
+
+// names that can't be raw identifiers
+const RESERVED: &[&str] = &["self", "Self", "super", "crate"];
+
+// methods every generated binding already has
+const BINDING_METHODS: &[&str] = &["new", "address", "contract"];
+
+// arguments every generated transaction method already has
+const TRANSACTION_ARGS: &[&str] = &["web3m", "account", "value"];
+
+// argument of the calls of an overload other than the first
+const OVERLOAD_ARGS: &[&str] = &["web3m"];
+
+/// Generates the bindings of `contract_name` from its ABI JSON.
+///
+/// Accepts a plain ABI array as well as Hardhat/Foundry/Truffle artifacts
+/// holding it under an `abi` key. The generated code refers to the wrapper
+/// as `web3_rust_wrapper`.
+pub fn generate_bindings(contract_name: &str, abi_json: &str) -> Result<String, String> {
+    generate_bindings_with_crate_path(contract_name, abi_json, "web3_rust_wrapper")
+}
+
+/// Same as `generate_bindings` but refers to the wrapper through `crate_path`.
+pub fn generate_bindings_with_crate_path(
+    contract_name: &str,
+    abi_json: &str,
+    crate_path: &str,
+) -> Result<String, String> {
+    let value: Value = serde_json::from_str(abi_json).map_err(|e| e.to_string())?;
+    let entries = match &value {
+        Value::Array(entries) => entries,
+        Value::Object(artifact) => match artifact.get("abi") {
+            Some(Value::Array(entries)) => entries,
+            _ => return Err("artifact has no `abi` array".to_string()),
+        },
+        _ => return Err("abi must be a json array".to_string()),
+    };
+
+    let struct_name = to_pascal_case(contract_name);
+    let mut items = String::new();
+    let mut methods = String::new();
+    let mut method_names: HashSet<String> = BINDING_METHODS.iter().map(|m| m.to_string()).collect();
+    let mut overloads: HashMap<String, usize> = HashMap::new();
+
+    for entry in entries {
+        match entry["type"].as_str() {
+            Some("function") => generate_function(
+                entry,
+                crate_path,
+                &mut method_names,
+                &mut overloads,
+                &mut items,
+                &mut methods,
+            )?,
+            Some("event") => generate_event(entry, &mut method_names, &mut items, &mut methods)?,
+            _ => {}
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// generated by web3_rust_wrapper::bindgen, do not edit"
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "/// Typed bindings for the `{}` contract.",
+        contract_name
+    )
+    .unwrap();
+    writeln!(out, "#[derive(Clone, Debug)]").unwrap();
+    writeln!(out, "pub struct {} {{", struct_name).unwrap();
+    writeln!(
+        out,
+        "    contract: std::sync::Arc<web3::contract::Contract<web3::transports::Http>>,"
+    )
+    .unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "impl {} {{", struct_name).unwrap();
+    writeln!(
+        out,
+        "    pub fn new(contract: std::sync::Arc<web3::contract::Contract<web3::transports::Http>>) -> Self {{"
+    )
+    .unwrap();
+    writeln!(out, "        {} {{ contract }}", struct_name).unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "    pub fn address(&self) -> web3::types::Address {{").unwrap();
+    writeln!(out, "        self.contract.address()").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "    pub fn contract(&self) -> &web3::contract::Contract<web3::transports::Http> {{"
+    )
+    .unwrap();
+    writeln!(out, "        &self.contract").unwrap();
+    writeln!(out, "    }}").unwrap();
+    out.push_str(&methods);
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "impl From<web3::contract::Contract<web3::transports::Http>> for {} {{",
+        struct_name
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    fn from(contract: web3::contract::Contract<web3::transports::Http>) -> Self {{"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "        {}::new(std::sync::Arc::new(contract))",
+        struct_name
+    )
+    .unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out.push_str(&items);
+
+    Ok(out)
+}
+
+struct Param {
+    name: String,
+    rust_type: String,
+    indexed: bool,
+}
+
+fn params(
+    entry: &Value,
+    key: &str,
+    fallback: &str,
+    reserved: &[&str],
+) -> Result<Vec<Param>, String> {
+    let mut result: Vec<Param> = Vec::new();
+    let mut names: HashSet<String> = HashSet::new();
+    if let Some(list) = entry[key].as_array() {
+        for (i, param) in list.iter().enumerate() {
+            let solidity_type = param["type"]
+                .as_str()
+                .ok_or_else(|| format!("parameter without type in {}", entry["name"]))?;
+            let raw_name = to_snake_case(param["name"].as_str().unwrap_or(""));
+            let raw_name = if raw_name.is_empty() {
+                format!("{}{}", fallback, i)
+            } else if reserved.contains(&raw_name.as_str()) {
+                format!("{}_", raw_name)
+            } else {
+                raw_name
+            };
+            let name = unique_name(&escape_ident(&raw_name), &mut names);
+            let indexed = param["indexed"].as_bool().unwrap_or(false);
+            // indexed strings, bytes, arrays and structs are only their hash
+            let rust_type = if indexed && is_hashed_when_indexed(solidity_type) {
+                "web3::types::H256".to_string()
+            } else {
+                rust_type(solidity_type, false)
+            };
+            result.push(Param {
+                name,
+                rust_type,
+                indexed,
+            });
+        }
+    }
+    Ok(result)
+}
+
+fn is_hashed_when_indexed(solidity_type: &str) -> bool {
+    solidity_type == "string"
+        || solidity_type == "bytes"
+        || solidity_type.ends_with(']')
+        || solidity_type.starts_with("tuple")
+}
+
+fn signature(entry: &Value, name: &str) -> String {
+    let types: Vec<&str> = entry["inputs"]
+        .as_array()
+        .map(|list| list.iter().filter_map(|p| p["type"].as_str()).collect())
+        .unwrap_or_default();
+    format!("{}({})", name, types.join(","))
+}
+
+fn generate_function(
+    entry: &Value,
+    crate_path: &str,
+    method_names: &mut HashSet<String>,
+    overloads: &mut HashMap<String, usize>,
+    items: &mut String,
+    methods: &mut String,
+) -> Result<(), String> {
+    let name = entry["name"]
+        .as_str()
+        .ok_or("function without name")?
+        .to_string();
+    // `Contract` resolves a name to its first overload, the others are
+    // called through a contract holding only them
+    let overload = overloads.entry(name.clone()).or_insert(0);
+    let overload_index = *overload;
+    *overload += 1;
+    let state_mutability = entry["stateMutability"].as_str().unwrap_or("");
+    // a call without return values can't be detokenized, so it is bound as a transaction
+    let has_outputs = entry["outputs"].as_array().is_some_and(|o| !o.is_empty());
+    let is_view = has_outputs
+        && (state_mutability == "view"
+            || state_mutability == "pure"
+            || entry["constant"].as_bool() == Some(true));
+    let is_payable = state_mutability == "payable" || entry["payable"].as_bool() == Some(true);
+
+    let reserved: &[&str] = match (is_view, overload_index) {
+        (false, _) => TRANSACTION_ARGS,
+        (true, 0) => &[],
+        (true, _) => OVERLOAD_ARGS,
+    };
+    let inputs = params(entry, "inputs", "arg", reserved)?;
+    let method_name = unique_name(&escape_ident(&to_snake_case(&name)), method_names);
+
+    let mut args: String = inputs
+        .iter()
+        .map(|p| format!(", {}: {}", p.name, p.rust_type))
+        .collect();
+    let tuple = params_tuple(&inputs);
+    let mut argument_count = inputs.len() + if is_view { 1 } else { 4 };
+    let contract = if overload_index == 0 {
+        "self.contract".to_string()
+    } else {
+        if is_view {
+            args = format!(", web3m: &{}::Web3Manager{}", crate_path, args);
+            argument_count += 1;
+        }
+        "contract".to_string()
+    };
+    let overload_contract = format!(
+        "        let contract = web3m.overload_contract(&self.contract, \"{}\", {})?;",
+        name, overload_index
+    );
+
+    writeln!(methods).unwrap();
+    writeln!(methods, "    /// `{}`", signature(entry, &name)).unwrap();
+    if argument_count > 7 {
+        writeln!(methods, "    #[allow(clippy::too_many_arguments)]").unwrap();
+    }
+
+    if is_view {
+        let outputs = params(entry, "outputs", "value", &[])?;
+        let return_type = match (outputs.len(), overload_index) {
+            (1, _) => outputs[0].rust_type.clone(),
+            (_, 0) => format!("{}Output", to_pascal_case(&name)),
+            (_, _) => format!("{}Output", to_pascal_case(&method_name)),
+        };
+        writeln!(
+            methods,
+            "    pub async fn {}(&self{}) -> Result<{}, web3::contract::Error> {{",
+            method_name, args, return_type
+        )
+        .unwrap();
+        if overload_index > 0 {
+            writeln!(methods, "{}", overload_contract).unwrap();
+        }
+
+        if outputs.len() > 1 {
+            let output_tuple_type: Vec<String> =
+                outputs.iter().map(|o| o.rust_type.clone()).collect();
+            let output_names: Vec<String> = outputs.iter().map(|o| o.name.clone()).collect();
+            writeln!(
+                methods,
+                "        let ({},): ({},) = {}",
+                output_names.join(", "),
+                output_tuple_type.join(", "),
+                contract
+            )
+            .unwrap();
+            writeln!(
+                methods,
+                "            .query(\"{}\", {}, None, web3::contract::Options::default(), None)",
+                name, tuple
+            )
+            .unwrap();
+            writeln!(methods, "            .await?;").unwrap();
+            writeln!(
+                methods,
+                "        Ok({} {{ {} }})",
+                return_type,
+                output_names.join(", ")
+            )
+            .unwrap();
+
+            writeln!(items).unwrap();
+            writeln!(items, "/// Return values of `{}`.", signature(entry, &name)).unwrap();
+            writeln!(items, "#[derive(Clone, Debug, PartialEq)]").unwrap();
+            writeln!(items, "pub struct {} {{", return_type).unwrap();
+            for output in &outputs {
+                writeln!(items, "    pub {}: {},", output.name, output.rust_type).unwrap();
+            }
+            writeln!(items, "}}").unwrap();
+        } else {
+            writeln!(methods, "        {}", contract).unwrap();
+            writeln!(
+                methods,
+                "            .query(\"{}\", {}, None, web3::contract::Options::default(), None)",
+                name, tuple
+            )
+            .unwrap();
+            writeln!(methods, "            .await").unwrap();
+        }
+        writeln!(methods, "    }}").unwrap();
+    } else {
+        let value_arg = if is_payable {
+            ", value: web3::types::U256"
+        } else {
+            ""
+        };
+        let value = if is_payable {
+            "value"
+        } else {
+            "web3::types::U256::zero()"
+        };
+        writeln!(methods, "    pub async fn {}(", method_name).unwrap();
+        writeln!(methods, "        &self,").unwrap();
+        writeln!(methods, "        web3m: &mut {}::Web3Manager,", crate_path).unwrap();
+        writeln!(
+            methods,
+            "        account: web3::types::H160{}{}",
+            value_arg, args
+        )
+        .unwrap();
+        writeln!(
+            methods,
+            "    ) -> Result<web3::types::H256, Box<dyn std::error::Error>> {{"
+        )
+        .unwrap();
+        if overload_index > 0 {
+            writeln!(methods, "{}", overload_contract).unwrap();
+        }
+        writeln!(methods, "        web3m").unwrap();
+        writeln!(
+            methods,
+            "            .sign_and_send_tx(account, &{}, \"{}\", &{}, {})",
+            contract, name, tuple, value
+        )
+        .unwrap();
+        writeln!(methods, "            .await").unwrap();
+        writeln!(methods, "    }}").unwrap();
+    }
+    Ok(())
+}
+
+fn generate_event(
+    entry: &Value,
+    method_names: &mut HashSet<String>,
+    items: &mut String,
+    methods: &mut String,
+) -> Result<(), String> {
+    let name = entry["name"].as_str().ok_or("event without name")?;
+    let struct_name = format!("{}Event", to_pascal_case(name));
+    let fields = params(entry, "inputs", "param", &[])?;
+    let snake = to_snake_case(name);
+    let decode_method = unique_name(&format!("decode_{}", snake), method_names);
+    let topic_method = unique_name(&format!("{}_topic", snake), method_names);
+
+    writeln!(items).unwrap();
+    writeln!(items, "/// `{}` event.", signature(entry, name)).unwrap();
+    writeln!(items, "#[derive(Clone, Debug, PartialEq)]").unwrap();
+    writeln!(items, "pub struct {} {{", struct_name).unwrap();
+    for field in &fields {
+        if field.indexed {
+            writeln!(items, "    /// indexed").unwrap();
+        }
+        writeln!(items, "    pub {}: {},", field.name, field.rust_type).unwrap();
+    }
+    writeln!(items, "}}").unwrap();
+
+    writeln!(methods).unwrap();
+    writeln!(methods, "    /// Topic hash of the `{}` event.", name).unwrap();
+    writeln!(
+        methods,
+        "    pub fn {}(&self) -> web3::types::H256 {{",
+        topic_method
+    )
+    .unwrap();
+    writeln!(
+        methods,
+        "        self.contract.abi().event(\"{}\").unwrap().signature()",
+        name
+    )
+    .unwrap();
+    writeln!(methods, "    }}").unwrap();
+    writeln!(methods).unwrap();
+    writeln!(
+        methods,
+        "    /// Decodes a `{}` log of this contract.",
+        name
+    )
+    .unwrap();
+    writeln!(
+        methods,
+        "    pub fn {}(&self, log: &web3::types::Log) -> Result<{}, web3::contract::Error> {{",
+        decode_method, struct_name
+    )
+    .unwrap();
+    writeln!(methods, "        use web3::contract::tokens::Tokenizable;").unwrap();
+    writeln!(methods).unwrap();
+    writeln!(
+        methods,
+        "        let event = self.contract.abi().event(\"{}\")?;",
+        name
+    )
+    .unwrap();
+    writeln!(
+        methods,
+        "        let {}parsed = event.parse_log(web3::ethabi::RawLog {{",
+        if fields.is_empty() { "_" } else { "" }
+    )
+    .unwrap();
+    writeln!(methods, "            topics: log.topics.clone(),").unwrap();
+    writeln!(methods, "            data: log.data.0.clone(),").unwrap();
+    writeln!(methods, "        }})?;").unwrap();
+    if fields.is_empty() {
+        writeln!(methods, "        Ok({} {{}})", struct_name).unwrap();
+    } else {
+        writeln!(
+            methods,
+            "        let mut values = parsed.params.into_iter();"
+        )
+        .unwrap();
+        writeln!(methods, "        Ok({} {{", struct_name).unwrap();
+        for field in &fields {
+            writeln!(
+                methods,
+                "            {}: Tokenizable::from_token(values.next().unwrap().value)?,",
+                field.name
+            )
+            .unwrap();
+        }
+        writeln!(methods, "        }})").unwrap();
+    }
+    writeln!(methods, "    }}").unwrap();
+    Ok(())
+}
+
+fn params_tuple(params: &[Param]) -> String {
+    match params.len() {
+        0 => "()".to_string(),
+        _ => {
+            let names: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+            format!("({},)", names.join(", "))
+        }
+    }
+}
+
+/// Maps a solidity type to the rust type used by the bindings.
+pub fn rust_type(solidity_type: &str, array_item: bool) -> String {
+    if let Some(inner) = solidity_type.strip_suffix("[]") {
+        return format!("Vec<{}>", rust_type(inner, true));
+    }
+    if solidity_type.ends_with(']') {
+        if let Some(open) = solidity_type.rfind('[') {
+            let inner = &solidity_type[..open];
+            let size = &solidity_type[open + 1..solidity_type.len() - 1];
+            return format!("[{}; {}]", rust_type(inner, true), size);
+        }
+    }
+    match solidity_type {
+        "address" => "web3::types::Address".to_string(),
+        "bool" => "bool".to_string(),
+        "string" => "String".to_string(),
+        "bytes" if array_item => "Vec<u8>".to_string(),
+        "bytes" => "web3::types::Bytes".to_string(),
+        "bytes32" => "web3::types::H256".to_string(),
+        t if t.starts_with("bytes") => format!("[u8; {}]", &t[5..]),
+        t if t.starts_with("uint") || t.starts_with("int") => "web3::types::U256".to_string(),
+        _ => "web3::ethabi::Token".to_string(),
+    }
+}
+
+pub fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.trim_start_matches('_').chars().collect();
+    let mut out = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev != '_' && (prev.is_lowercase() || prev.is_ascii_digit() || next_is_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+pub fn to_pascal_case(name: &str) -> String {
+    to_snake_case(name)
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn escape_ident(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn unique_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let mut candidate = name.to_string();
+    let mut i = 1;
+    while taken.contains(&candidate) {
+        candidate = format!("{}_{}", name, i);
+        i += 1;
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_case_names() {
+        assert_eq!(to_snake_case("getAmountsOut"), "get_amounts_out");
+        assert_eq!(
+            to_snake_case("swapExactETHForTokens"),
+            "swap_exact_eth_for_tokens"
+        );
+        assert_eq!(to_snake_case("WETH"), "weth");
+        assert_eq!(to_snake_case("INIT_CODE_PAIR_HASH"), "init_code_pair_hash");
+        assert_eq!(
+            to_snake_case("price0CumulativeLast"),
+            "price0_cumulative_last"
+        );
+        assert_eq!(to_snake_case("_isBlacklisted"), "is_blacklisted");
+        assert_eq!(to_pascal_case("getReserves"), "GetReserves");
+    }
+
+    #[test]
+    fn solidity_types() {
+        assert_eq!(rust_type("uint112", false), "web3::types::U256");
+        assert_eq!(rust_type("address[]", false), "Vec<web3::types::Address>");
+        assert_eq!(rust_type("bytes32", false), "web3::types::H256");
+        assert_eq!(rust_type("bytes4", false), "[u8; 4]");
+        assert_eq!(rust_type("bytes[]", false), "Vec<Vec<u8>>");
+        assert_eq!(rust_type("uint256[2]", false), "[web3::types::U256; 2]");
+    }
+
+    #[test]
+    fn generates_methods_and_events() {
+        let abi = r#"[
+            {"type":"function","name":"balanceOf","stateMutability":"view",
+             "inputs":[{"name":"account","type":"address"}],
+             "outputs":[{"name":"","type":"uint256"}]},
+            {"type":"function","name":"transfer","stateMutability":"nonpayable",
+             "inputs":[{"name":"to","type":"address"},{"name":"value","type":"uint256"}],
+             "outputs":[{"name":"","type":"bool"}]},
+            {"type":"event","name":"Transfer","anonymous":false,
+             "inputs":[{"name":"from","type":"address","indexed":true},
+                       {"name":"to","type":"address","indexed":true},
+                       {"name":"value","type":"uint256","indexed":false}]}
+        ]"#;
+        let code = generate_bindings("MyToken", abi).unwrap();
+
+        assert!(code.contains("pub struct MyToken"));
+        assert!(code.contains("pub async fn balance_of(&self, account: web3::types::Address)"));
+        // `value` is taken by payable transactions
+        assert!(code.contains("to: web3::types::Address, value_: web3::types::U256"));
+        assert!(code.contains("web3m: &mut web3_rust_wrapper::Web3Manager"));
+        assert!(code.contains("pub struct TransferEvent"));
+        assert!(code.contains("pub fn decode_transfer"));
+    }
+
+    #[test]
+    fn binds_every_overload() {
+        let abi = r#"[
+            {"type":"function","name":"safeTransferFrom","stateMutability":"nonpayable",
+             "inputs":[{"name":"from","type":"address"},{"name":"to","type":"address"},
+                       {"name":"id","type":"uint256"}],
+             "outputs":[]},
+            {"type":"function","name":"safeTransferFrom","stateMutability":"nonpayable",
+             "inputs":[{"name":"from","type":"address"},{"name":"to","type":"address"},
+                       {"name":"id","type":"uint256"},{"name":"data","type":"bytes"}],
+             "outputs":[]},
+            {"type":"function","name":"get","stateMutability":"view","inputs":[],
+             "outputs":[{"name":"","type":"uint256"}]},
+            {"type":"function","name":"get","stateMutability":"view",
+             "inputs":[{"name":"key","type":"uint256"}],
+             "outputs":[{"name":"","type":"uint256"}]}
+        ]"#;
+        let code = generate_bindings("Nft", abi).unwrap();
+
+        // the first overload is called directly, the others by index
+        assert!(code.contains("pub async fn safe_transfer_from("));
+        assert!(code.contains("pub async fn safe_transfer_from_1("));
+        assert!(code.contains("web3m.overload_contract(&self.contract, \"safeTransferFrom\", 1)?"));
+        assert!(code.contains(
+            "pub async fn get_1(&self, web3m: &web3_rust_wrapper::Web3Manager, key: web3::types::U256)"
+        ));
+        assert!(!code.contains("overload_contract(&self.contract, \"get\", 0)"));
+    }
+
+    #[test]
+    fn indexed_dynamic_params_are_hashes() {
+        let abi = r#"[
+            {"type":"event","name":"Registered","anonymous":false,
+             "inputs":[{"name":"name","type":"string","indexed":true},
+                       {"name":"ids","type":"uint256[]","indexed":true},
+                       {"name":"owner","type":"address","indexed":true},
+                       {"name":"note","type":"string","indexed":false}]}
+        ]"#;
+        let code = generate_bindings("Registry", abi).unwrap();
+
+        assert!(code.contains("pub name: web3::types::H256,"));
+        assert!(code.contains("pub ids: web3::types::H256,"));
+        assert!(code.contains("pub owner: web3::types::Address,"));
+        assert!(code.contains("pub note: String,"));
+    }
+
+    #[test]
+    fn reads_artifacts() {
+        let artifact = r#"{"contractName":"Empty","abi":[],"bytecode":"0x"}"#;
+        assert!(generate_bindings("Empty", artifact).is_ok());
+        assert!(generate_bindings("Empty", "{}").is_err());
+    }
+}
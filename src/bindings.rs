@@ -0,0 +1,76 @@
+//! Typed bindings for the ABIs bundled in `abi/`, generated at build time by
+//! `crate::bindgen`.
+
+use crate::{AbiKind, Web3Manager};
+use web3::types::H160;
+
+pub mod token {
+    include!(concat!(env!("OUT_DIR"), "/token.rs"));
+}
+
+pub mod standard_token {
+    include!(concat!(env!("OUT_DIR"), "/standard_token.rs"));
+}
+
+pub mod pancake_router {
+    include!(concat!(env!("OUT_DIR"), "/pancake_router.rs"));
+}
+
+pub mod pancake_factory {
+    include!(concat!(env!("OUT_DIR"), "/pancake_factory.rs"));
+}
+
+pub mod pancake_pair {
+    include!(concat!(env!("OUT_DIR"), "/pancake_pair.rs"));
+}
+
+pub mod aggregator_proxy {
+    include!(concat!(env!("OUT_DIR"), "/aggregator_proxy.rs"));
+}
+
+pub mod disperse {
+    include!(concat!(env!("OUT_DIR"), "/disperse.rs"));
+}
+
+pub mod wrapped_native {
+    include!(concat!(env!("OUT_DIR"), "/wrapped_native.rs"));
+}
+
+pub use aggregator_proxy::AggregatorProxy;
+pub use disperse::Disperse;
+pub use pancake_factory::PancakeFactory;
+pub use pancake_pair::PancakePair;
+pub use pancake_router::PancakeRouter;
+pub use standard_token::StandardToken;
+pub use token::Token;
+pub use wrapped_native::WrappedNative;
+
+impl Web3Manager {
+    pub fn token_bindings(&self, address: H160) -> Token {
+        Token::new(self.cached_contract(address, AbiKind::Token))
+    }
+
+    pub fn router_bindings(&self, address: H160) -> PancakeRouter {
+        PancakeRouter::new(self.cached_contract(address, AbiKind::Router))
+    }
+
+    pub fn factory_bindings(&self, address: H160) -> PancakeFactory {
+        PancakeFactory::new(self.cached_contract(address, AbiKind::Factory))
+    }
+
+    pub fn pair_bindings(&self, address: H160) -> PancakePair {
+        PancakePair::new(self.cached_contract(address, AbiKind::Pair))
+    }
+
+    pub fn aggregator_proxy_bindings(&self, address: H160) -> AggregatorProxy {
+        AggregatorProxy::new(self.cached_contract(address, AbiKind::AggregatorProxy))
+    }
+
+    pub fn disperse_bindings(&self, address: H160) -> Disperse {
+        Disperse::new(self.cached_contract(address, AbiKind::Disperse))
+    }
+
+    pub fn wrapped_native_bindings(&self, address: H160) -> WrappedNative {
+        WrappedNative::new(self.cached_contract(address, AbiKind::WrappedNative))
+    }
+}
@@ -0,0 +1,180 @@
+use crate::Web3Manager;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use web3::api::Eth;
+use web3::contract::Contract;
+use web3::ethabi;
+use web3::transports::Http;
+use web3::types::{Address, H160};
+
+lazy_static! {
+    // the bundled abis are parsed only once per process
+    static ref TOKEN_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/TokenAbi.json"));
+    static ref ROUTER_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/PancakeRouterAbi.json"));
+    static ref FACTORY_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/PancakeFactoryAbi.json"));
+    static ref PAIR_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/PancakeLPTokenAbi.json"));
+    static ref AGGREGATOR_PROXY_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/EACAggregatorProxy.json"));
+    static ref DISPERSE_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/DisperseAbi.json"));
+    static ref WRAPPED_NATIVE_ABI: Arc<ethabi::Contract> =
+        parse_abi(include_bytes!("../abi/WrappedNativeAbi.json"));
+}
+
+fn parse_abi(json: &[u8]) -> Arc<ethabi::Contract> {
+    Arc::new(ethabi::Contract::load(json).expect("bundled abi is valid json"))
+}
+
+/// The ABIs bundled in the `abi/` folder of the crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AbiKind {
+    Token,
+    Router,
+    Factory,
+    Pair,
+    AggregatorProxy,
+    /// Disperse multisend contract (disperse.app).
+    Disperse,
+    /// WETH9 style wrapped native token (WETH, WBNB...).
+    WrappedNative,
+}
+
+impl AbiKind {
+    /// Returns the parsed ABI, shared between every contract instance of this kind.
+    pub fn abi(&self) -> Arc<ethabi::Contract> {
+        match self {
+            AbiKind::Token => TOKEN_ABI.clone(),
+            AbiKind::Router => ROUTER_ABI.clone(),
+            AbiKind::Factory => FACTORY_ABI.clone(),
+            AbiKind::Pair => PAIR_ABI.clone(),
+            AbiKind::AggregatorProxy => AGGREGATOR_PROXY_ABI.clone(),
+            AbiKind::Disperse => DISPERSE_ABI.clone(),
+            AbiKind::WrappedNative => WRAPPED_NATIVE_ABI.clone(),
+        }
+    }
+}
+
+type ContractMap = HashMap<(H160, AbiKind), Arc<Contract<Http>>>;
+
+/// Contract instances keyed by (address, ABI kind).
+///
+/// The cache is shared between clones of the `Web3Manager` that owns it, so
+/// an instance built once is reused by every later quote or swap.
+#[derive(Clone, Debug, Default)]
+pub struct ContractCache {
+    instances: Arc<RwLock<ContractMap>>,
+}
+
+impl ContractCache {
+    pub fn new() -> ContractCache {
+        ContractCache::default()
+    }
+
+    pub fn get(&self, address: H160, kind: AbiKind) -> Option<Arc<Contract<Http>>> {
+        self.instances
+            .read()
+            .unwrap()
+            .get(&(address, kind))
+            .cloned()
+    }
+
+    pub fn get_or_insert(
+        &self,
+        eth: Eth<Http>,
+        address: H160,
+        kind: AbiKind,
+    ) -> Arc<Contract<Http>> {
+        if let Some(instance) = self.get(address, kind) {
+            return instance;
+        }
+
+        let mut instances = self.instances.write().unwrap();
+        instances
+            .entry((address, kind))
+            .or_insert_with(|| Arc::new(Contract::new(eth, address, (*kind.abi()).clone())))
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.instances.write().unwrap().clear();
+    }
+}
+
+impl Web3Manager {
+    /// Returns the cached instance for `address`, building it on first use.
+    pub fn cached_contract(&self, address: H160, kind: AbiKind) -> Arc<Contract<Http>> {
+        self.contract_cache
+            .get_or_insert(self.web3http.eth(), address, kind)
+    }
+
+    pub fn cached_contract_from_str(
+        &self,
+        plain_contract_address: &str,
+        kind: AbiKind,
+    ) -> Result<Arc<Contract<Http>>, Box<dyn Error>> {
+        let address = Address::from_str(plain_contract_address)?;
+        Ok(self.cached_contract(address, kind))
+    }
+
+    pub fn contract_cache(&self) -> &ContractCache {
+        &self.contract_cache
+    }
+
+    /// Instance of `contract` whose ABI only has the `index`-th overload of
+    /// `function`. `Contract` always calls the first overload of a name.
+    pub fn overload_contract(
+        &self,
+        contract: &Contract<Http>,
+        function: &str,
+        index: usize,
+    ) -> Result<Contract<Http>, web3::contract::Error> {
+        let overload = contract
+            .abi()
+            .functions_by_name(function)?
+            .get(index)
+            .cloned()
+            .ok_or_else(|| ethabi::Error::InvalidName(format!("{}#{}", function, index)))?;
+        let mut abi = ethabi::Contract::default();
+        abi.functions.insert(function.to_string(), vec![overload]);
+        Ok(Contract::new(self.web3http.eth(), contract.address(), abi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_abis_parse() {
+        assert!(AbiKind::Token.abi().function("balanceOf").is_ok());
+        assert!(AbiKind::Router.abi().function("getAmountsOut").is_ok());
+        assert!(AbiKind::Factory.abi().function("getPair").is_ok());
+        assert!(AbiKind::Pair.abi().function("getReserves").is_ok());
+        assert!(AbiKind::AggregatorProxy
+            .abi()
+            .function("latestRoundData")
+            .is_ok());
+        assert!(AbiKind::Disperse.abi().function("disperseToken").is_ok());
+        assert!(AbiKind::WrappedNative.abi().function("deposit").is_ok());
+    }
+
+    #[test]
+    fn abi_is_shared() {
+        assert!(Arc::ptr_eq(&AbiKind::Router.abi(), &AbiKind::Router.abi()));
+    }
+}
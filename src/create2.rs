@@ -0,0 +1,156 @@
+//! Offline contract address prediction (CREATE and CREATE2), used to know a
+//! V2 pair address before the factory deploys it.
+
+use crate::{AbiKind, Web3Manager};
+use hex_literal::hex;
+use rlp::RlpStream;
+use std::error::Error;
+use std::str::FromStr;
+use web3::signing::keccak256;
+use web3::types::{Address, H160, H256, U256};
+
+/// Init code hash of the Uniswap V2 pair. Pancake-style factories expose
+/// their own through `INIT_CODE_PAIR_HASH`, see `get_init_code_pair_hash`.
+pub const UNISWAP_V2_INIT_CODE_HASH: H256 = H256(hex!(
+    "96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f"
+));
+
+/// Address of a contract deployed with `CREATE` by `deployer` at `nonce`.
+pub fn create_address(deployer: H160, nonce: U256) -> H160 {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&deployer);
+    stream.append(&nonce);
+    let hash = keccak256(&stream.out());
+    Address::from_slice(&hash[12..])
+}
+
+/// Address of a contract deployed with `CREATE2`.
+pub fn create2_address(deployer: H160, salt: H256, init_code_hash: H256) -> H160 {
+    let mut buffer = Vec::with_capacity(1 + 20 + 32 + 32);
+    buffer.push(0xff);
+    buffer.extend_from_slice(deployer.as_bytes());
+    buffer.extend_from_slice(salt.as_bytes());
+    buffer.extend_from_slice(init_code_hash.as_bytes());
+    let hash = keccak256(&buffer);
+    Address::from_slice(&hash[12..])
+}
+
+pub fn create2_address_from_code(deployer: H160, salt: H256, init_code: &[u8]) -> H160 {
+    create2_address(deployer, salt, H256(keccak256(init_code)))
+}
+
+/// Orders a token pair the way V2 factories do (`token0 < token1`).
+pub fn sort_tokens(token_a: H160, token_b: H160) -> (H160, H160) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
+}
+
+/// Address of the V2 pair of `token_a` and `token_b`, whether it exists yet or not.
+pub fn compute_pair_address(
+    factory: H160,
+    token_a: H160,
+    token_b: H160,
+    init_code_hash: H256,
+) -> H160 {
+    let (token0, token1) = sort_tokens(token_a, token_b);
+    let mut packed = [0u8; 40];
+    packed[..20].copy_from_slice(token0.as_bytes());
+    packed[20..].copy_from_slice(token1.as_bytes());
+    create2_address(factory, H256(keccak256(&packed)), init_code_hash)
+}
+
+impl Web3Manager {
+    /// Reads `INIT_CODE_PAIR_HASH` from a Pancake-style factory.
+    pub async fn get_init_code_pair_hash(
+        &self,
+        factory_address: &str,
+    ) -> Result<H256, Box<dyn Error>> {
+        let factory_instance = self.cached_contract_from_str(factory_address, AbiKind::Factory)?;
+        let init_code_hash: H256 = self
+            .query_contract(&factory_instance, "INIT_CODE_PAIR_HASH", ())
+            .await?;
+        Ok(init_code_hash)
+    }
+
+    /// Predicts the LP address of `token_address` against `weth_address`
+    /// without asking the factory for it.
+    pub fn predict_lp_pair(
+        &self,
+        weth_address: &str,
+        factory_address: &str,
+        token_address: &str,
+        init_code_hash: H256,
+    ) -> Result<H160, Box<dyn Error>> {
+        Ok(compute_pair_address(
+            H160::from_str(factory_address)?,
+            H160::from_str(weth_address)?,
+            H160::from_str(token_address)?,
+            init_code_hash,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(text: &str) -> H160 {
+        H160::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn uniswap_v2_pair() {
+        let pair = compute_pair_address(
+            address("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"),
+            address("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+            address("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            UNISWAP_V2_INIT_CODE_HASH,
+        );
+        assert_eq!(pair, address("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"));
+    }
+
+    #[test]
+    fn pair_address_ignores_token_order() {
+        let factory = address("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f");
+        let weth = address("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let usdc = address("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+        assert_eq!(
+            compute_pair_address(factory, weth, usdc, UNISWAP_V2_INIT_CODE_HASH),
+            compute_pair_address(factory, usdc, weth, UNISWAP_V2_INIT_CODE_HASH)
+        );
+        assert_eq!(sort_tokens(weth, usdc), (usdc, weth));
+    }
+
+    #[test]
+    fn create_addresses() {
+        let deployer = address("0x6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0");
+        assert_eq!(
+            create_address(deployer, U256::zero()),
+            address("0xcd234a471b72ba2f1ccf0a70fcaba648a5eecd8d")
+        );
+        assert_eq!(
+            create_address(deployer, U256::one()),
+            address("0x343c43a37d37dff08ae8c4a11544c718abb4fcf8")
+        );
+    }
+
+    #[test]
+    fn create2_eip1014_examples() {
+        assert_eq!(
+            create2_address_from_code(H160::zero(), H256::zero(), &[0x00]),
+            address("0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38")
+        );
+        assert_eq!(
+            create2_address_from_code(
+                address("0xdeadbeef00000000000000000000000000000000"),
+                H256::zero(),
+                &[0x00]
+            ),
+            address("0xB928f69Bb1D91Cd65274e3c79d8986362984fDA3")
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Contract deployment from an ABI plus bytecode, or from the artifact JSON
+//! written by Hardhat, Truffle, Foundry or solc.
+
+use crate::Web3Manager;
+use serde_json::Value;
+use std::error::Error;
+use std::time::Duration;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::ethabi;
+use web3::transports::Http;
+use web3::types::{Bytes, CallRequest, TransactionParameters, H160, U256};
+
+const DEPLOY_RECEIPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// ABI and creation bytecode of a compiled contract.
+#[derive(Clone, Debug)]
+pub struct ContractArtifact {
+    pub contract_name: Option<String>,
+    pub abi: ethabi::Contract,
+    pub bytecode: Bytes,
+}
+
+impl ContractArtifact {
+    pub fn new(abi_json: &[u8], bytecode: &str) -> Result<ContractArtifact, Box<dyn Error>> {
+        Ok(ContractArtifact {
+            contract_name: None,
+            abi: ethabi::Contract::load(abi_json)?,
+            bytecode: decode_bytecode(bytecode)?,
+        })
+    }
+
+    /// Loads a Hardhat/Truffle (`abi` + `bytecode`) or Foundry (`abi` +
+    /// `bytecode.object`) artifact. For solc combined/standard JSON output
+    /// holding several contracts use `from_solc_output`.
+    pub fn from_file(path: &str) -> Result<ContractArtifact, Box<dyn Error>> {
+        let json: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+        ContractArtifact::from_json(&json)
+    }
+
+    pub fn from_json(json: &Value) -> Result<ContractArtifact, Box<dyn Error>> {
+        let abi = json.get("abi").ok_or("artifact has no `abi`")?;
+        let bytecode = match json.get("bytecode") {
+            Some(Value::String(bytecode)) => bytecode.as_str(),
+            Some(Value::Object(foundry)) => foundry
+                .get("object")
+                .and_then(Value::as_str)
+                .ok_or("artifact has no `bytecode.object`")?,
+            _ => return Err("artifact has no `bytecode`".into()),
+        };
+
+        Ok(ContractArtifact {
+            contract_name: json
+                .get("contractName")
+                .and_then(Value::as_str)
+                .map(String::from),
+            abi: load_abi(abi)?,
+            bytecode: decode_bytecode(bytecode)?,
+        })
+    }
+
+    /// Loads `contract_name` from the output of `solc --combined-json abi,bin`
+    /// or from a solc standard JSON output file.
+    pub fn from_solc_output(
+        path: &str,
+        contract_name: &str,
+    ) -> Result<ContractArtifact, Box<dyn Error>> {
+        let json: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+        let contracts = json
+            .get("contracts")
+            .and_then(Value::as_object)
+            .ok_or("solc output has no `contracts`")?;
+
+        for (key, entry) in contracts {
+            // combined json: {"file.sol:Name": {"abi", "bin"}}
+            if key == contract_name || key.ends_with(&format!(":{}", contract_name)) {
+                if let Some(bin) = entry.get("bin").and_then(Value::as_str) {
+                    return Ok(ContractArtifact {
+                        contract_name: Some(contract_name.to_string()),
+                        abi: load_abi(entry.get("abi").ok_or("contract has no `abi`")?)?,
+                        bytecode: decode_bytecode(bin)?,
+                    });
+                }
+            }
+
+            // standard json: {"file.sol": {"Name": {"abi", "evm": {"bytecode": {"object"}}}}}
+            if let Some(entry) = entry.get(contract_name) {
+                let object = entry
+                    .pointer("/evm/bytecode/object")
+                    .and_then(Value::as_str)
+                    .ok_or("contract has no `evm.bytecode.object`")?;
+                return Ok(ContractArtifact {
+                    contract_name: Some(contract_name.to_string()),
+                    abi: load_abi(entry.get("abi").ok_or("contract has no `abi`")?)?,
+                    bytecode: decode_bytecode(object)?,
+                });
+            }
+        }
+        Err(format!("`{}` not found in {}", contract_name, path).into())
+    }
+
+    /// Creation bytecode followed by the ABI encoded constructor arguments.
+    pub fn creation_data<P: Tokenize>(&self, constructor_args: P) -> Result<Bytes, Box<dyn Error>> {
+        let tokens = constructor_args.into_tokens();
+        match &self.abi.constructor {
+            Some(constructor) => Ok(constructor
+                .encode_input(self.bytecode.0.clone(), &tokens)?
+                .into()),
+            None if tokens.is_empty() => Ok(self.bytecode.clone()),
+            None => Err("contract has no constructor but arguments were given".into()),
+        }
+    }
+}
+
+// solc writes the abi as a json string in combined json output
+fn load_abi(abi: &Value) -> Result<ethabi::Contract, Box<dyn Error>> {
+    match abi {
+        Value::String(abi) => Ok(ethabi::Contract::load(abi.as_bytes())?),
+        abi => Ok(ethabi::Contract::load(serde_json::to_vec(abi)?.as_slice())?),
+    }
+}
+
+fn decode_bytecode(bytecode: &str) -> Result<Bytes, Box<dyn Error>> {
+    let bytecode = bytecode.trim().trim_start_matches("0x");
+    if bytecode.is_empty() {
+        return Err("empty bytecode, is the contract abstract?".into());
+    }
+    if bytecode.contains("__") {
+        return Err("bytecode has unlinked library references".into());
+    }
+    Ok(hex::decode(bytecode)?.into())
+}
+
+impl Web3Manager {
+    /// Deploys a contract and waits until it is mined.
+    ///
+    /// The creation transaction goes through the same nonce, gas and signing
+    /// path as `sign_and_send_tx`.
+    pub async fn deploy_contract<P: Tokenize>(
+        &mut self,
+        account: H160,
+        abi_json: &[u8],
+        bytecode: &str,
+        constructor_args: P,
+    ) -> Result<Contract<Http>, Box<dyn Error>> {
+        let artifact = ContractArtifact::new(abi_json, bytecode)?;
+        self.deploy_artifact(account, &artifact, constructor_args)
+            .await
+    }
+
+    pub async fn deploy_artifact<P: Tokenize>(
+        &mut self,
+        account: H160,
+        artifact: &ContractArtifact,
+        constructor_args: P,
+    ) -> Result<Contract<Http>, Box<dyn Error>> {
+        let data = artifact.creation_data(constructor_args)?;
+
+        let call_request = CallRequest {
+            from: Some(account),
+            data: Some(data.clone()),
+            ..Default::default()
+        };
+        let estimated_tx_gas: U256 = self
+            .gas_settings()
+            .gas_limit(self.web3http.eth().estimate_gas(call_request, None).await?);
+
+        // fees are filled from the gas settings when signing
+        let tx_parameters = TransactionParameters {
+            nonce: Some(self.get_current_nonce()),
+            to: None,
+            gas: estimated_tx_gas,
+            data,
+            chain_id: self.chain_id,
+            ..Default::default()
+        };
+
+        let tx_hash = self
+            .sign_and_send_transaction(account, tx_parameters)
+            .await?;
+        self.update_nonce();
+
+        let receipt = self
+            .wait_for_receipt(tx_hash, DEPLOY_RECEIPT_TIMEOUT)
+            .await?;
+        let address = receipt
+            .contract_address
+            .ok_or("receipt has no contract address")?;
+
+        Ok(Contract::new(
+            self.web3http.eth(),
+            address,
+            artifact.abi.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use web3::types::Address;
+
+    const ABI: &str = r#"[{"type":"constructor","stateMutability":"nonpayable",
+        "inputs":[{"name":"owner","type":"address"}]}]"#;
+
+    #[test]
+    fn loads_hardhat_and_foundry_artifacts() {
+        let abi: Value = serde_json::from_str(ABI).unwrap();
+        let hardhat = json!({"contractName": "MyToken", "abi": abi, "bytecode": "0x6080"});
+        let artifact = ContractArtifact::from_json(&hardhat).unwrap();
+        assert_eq!(artifact.contract_name.as_deref(), Some("MyToken"));
+        assert_eq!(artifact.bytecode.0, vec![0x60, 0x80]);
+
+        let foundry = json!({"abi": abi, "bytecode": {"object": "0x6080", "linkReferences": {}}});
+        let artifact = ContractArtifact::from_json(&foundry).unwrap();
+        assert_eq!(artifact.bytecode.0, vec![0x60, 0x80]);
+    }
+
+    #[test]
+    fn rejects_unusable_bytecode() {
+        assert!(ContractArtifact::new(ABI.as_bytes(), "0x").is_err());
+        assert!(ContractArtifact::new(ABI.as_bytes(), "0x60__$abc$__80").is_err());
+    }
+
+    #[test]
+    fn appends_constructor_arguments() {
+        let artifact = ContractArtifact::new(ABI.as_bytes(), "6080").unwrap();
+        let data = artifact.creation_data(Address::from_low_u64_be(1)).unwrap();
+        assert_eq!(data.0.len(), 2 + 32);
+        assert_eq!(&data.0[..2], &[0x60, 0x80]);
+        assert_eq!(data.0[33], 1);
+    }
+}
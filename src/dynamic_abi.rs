@@ -0,0 +1,531 @@
+//! Runtime ABI calls: functions are described by a human readable signature
+//! or picked from an ABI file, and arguments/results travel as JSON.
+//!
+//! ```ignore
+//! let balance = web3m
+//!     .call_function(token, "balanceOf(address)(uint256)", r#"["0x..."]"#)
+//!     .await?;
+//! let tx_hash = web3m
+//!     .send_function(account, token, "transfer(address,uint256)", r#"["0x...", "1000"]"#, U256::zero())
+//!     .await?;
+//! ```
+
+use crate::Web3Manager;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use web3::contract::Contract;
+use web3::ethabi::param_type::Reader;
+use web3::ethabi::{self, Function, Param, ParamType, StateMutability, Token};
+use web3::transports::Http;
+use web3::types::{Address, Bytes, CallRequest, H160, H256, U256};
+
+/// Parses a human readable function signature.
+///
+/// Outputs are optional and can be written as `balanceOf(address)(uint256)`
+/// or `balanceOf(address) returns (uint256)`.
+pub fn parse_function_signature(signature: &str) -> Result<Function, Box<dyn Error>> {
+    let signature = signature.trim();
+    let open = signature
+        .find('(')
+        .ok_or_else(|| format!("missing parameter list in `{}`", signature))?;
+    let name = signature[..open].trim();
+    if name.is_empty() {
+        return Err(format!("missing function name in `{}`", signature).into());
+    }
+
+    let close = matching_paren(signature, open)?;
+    let inputs = parse_param_list(&signature[open..=close])?;
+
+    let rest = signature[close + 1..].trim();
+    let rest = rest.strip_prefix("returns").unwrap_or(rest).trim();
+    let outputs = if rest.is_empty() {
+        vec![]
+    } else {
+        parse_param_list(rest)?
+    };
+
+    #[allow(deprecated)]
+    Ok(Function {
+        name: name.to_string(),
+        inputs,
+        outputs,
+        constant: false,
+        state_mutability: StateMutability::NonPayable,
+    })
+}
+
+fn matching_paren(text: &str, open: usize) -> Result<usize, Box<dyn Error>> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(format!("unbalanced parentheses in `{}`", text).into())
+}
+
+fn parse_param_list(list: &str) -> Result<Vec<Param>, Box<dyn Error>> {
+    let list: String = list.chars().filter(|c| !c.is_whitespace()).collect();
+    if list == "()" {
+        return Ok(vec![]);
+    }
+
+    // a parameter list has the same syntax as a tuple type
+    let kinds = match Reader::read(&list)? {
+        ParamType::Tuple(kinds) => kinds,
+        other => vec![other],
+    };
+    kinds.iter().try_for_each(check_param_type)?;
+
+    Ok(kinds
+        .into_iter()
+        .enumerate()
+        .map(|(i, kind)| Param {
+            name: format!("arg{}", i),
+            kind,
+            internal_type: None,
+        })
+        .collect())
+}
+
+/// Converts a JSON argument into a token of type `kind`.
+///
+/// Integers can be JSON numbers, decimal strings or `0x` hex strings, bytes
+/// are `0x` hex strings and tuples are JSON arrays.
+pub fn json_to_token(value: &Value, kind: &ParamType) -> Result<Token, Box<dyn Error>> {
+    let token = match kind {
+        ParamType::Address => Token::Address(Address::from_str(expect_str(value)?)?),
+        ParamType::Bool => match value {
+            Value::Bool(b) => Token::Bool(*b),
+            Value::String(s) => Token::Bool(bool::from_str(s)?),
+            _ => return Err(format!("expected bool, got {}", value).into()),
+        },
+        ParamType::String => Token::String(expect_str(value)?.to_string()),
+        ParamType::Bytes => Token::Bytes(decode_hex(expect_str(value)?)?),
+        ParamType::FixedBytes(size) => {
+            let bytes = decode_hex(expect_str(value)?)?;
+            if bytes.len() != *size {
+                return Err(format!("expected {} bytes, got {}", size, bytes.len()).into());
+            }
+            Token::FixedBytes(bytes)
+        }
+        ParamType::Uint(size) => Token::Uint(json_to_uint(value, *size)?),
+        ParamType::Int(size) => Token::Int(json_to_int(value, *size)?),
+        ParamType::Array(item) => Token::Array(json_to_tokens(expect_array(value)?, item)?),
+        ParamType::FixedArray(item, size) => {
+            let items = expect_array(value)?;
+            if items.len() != *size {
+                return Err(format!("expected {} items, got {}", size, items.len()).into());
+            }
+            Token::FixedArray(json_to_tokens(items, item)?)
+        }
+        ParamType::Tuple(kinds) => {
+            let items = expect_array(value)?;
+            if items.len() != kinds.len() {
+                return Err(
+                    format!("expected {} tuple items, got {}", kinds.len(), items.len()).into(),
+                );
+            }
+            Token::Tuple(
+                items
+                    .iter()
+                    .zip(kinds)
+                    .map(|(item, kind)| json_to_token(item, kind))
+                    .collect::<Result<Vec<Token>, Box<dyn Error>>>()?,
+            )
+        }
+    };
+    Ok(token)
+}
+
+fn json_to_tokens(items: &[Value], kind: &ParamType) -> Result<Vec<Token>, Box<dyn Error>> {
+    items.iter().map(|item| json_to_token(item, kind)).collect()
+}
+
+/// Converts the JSON array `args` into the input tokens of `function`.
+pub fn json_to_inputs(function: &Function, args: &Value) -> Result<Vec<Token>, Box<dyn Error>> {
+    let args: &[Value] = match args {
+        Value::Null => &[],
+        Value::Array(items) => items,
+        _ => return Err("arguments must be a json array".into()),
+    };
+    if args.len() != function.inputs.len() {
+        return Err(format!(
+            "`{}` takes {} arguments, got {}",
+            function.signature(),
+            function.inputs.len(),
+            args.len()
+        )
+        .into());
+    }
+    function
+        .inputs
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| json_to_token(arg, &param.kind))
+        .collect()
+}
+
+/// Converts a token into JSON. Integers are encoded as decimal strings so no
+/// precision is lost.
+pub fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => json!(format!("{:?}", address)),
+        Token::Bool(b) => json!(b),
+        Token::String(s) => json!(s),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => {
+            json!(format!("0x{}", hex::encode(bytes)))
+        }
+        Token::Uint(value) => json!(value.to_string()),
+        Token::Int(value) => json!(i256_to_string(*value)),
+        Token::Array(items) | Token::FixedArray(items) | Token::Tuple(items) => {
+            Value::Array(items.iter().map(token_to_json).collect())
+        }
+    }
+}
+
+fn expect_str(value: &Value) -> Result<&str, Box<dyn Error>> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected string, got {}", value).into())
+}
+
+fn expect_array(value: &Value) -> Result<&[Value], Box<dyn Error>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => Err(format!("expected array, got {}", value).into()),
+    }
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(hex::decode(text.trim_start_matches("0x"))?)
+}
+
+// the parser accepts any `uint<N>`/`int<N>`, solidity only multiples of 8
+// from 8 to 256
+fn check_int_size(size: usize) -> Result<(), Box<dyn Error>> {
+    if !size.is_multiple_of(8) || !(8..=256).contains(&size) {
+        return Err(format!("invalid integer size {}", size).into());
+    }
+    Ok(())
+}
+
+fn check_param_type(kind: &ParamType) -> Result<(), Box<dyn Error>> {
+    match kind {
+        ParamType::Uint(size) | ParamType::Int(size) => check_int_size(*size),
+        ParamType::Array(item) | ParamType::FixedArray(item, _) => check_param_type(item),
+        ParamType::Tuple(kinds) => kinds.iter().try_for_each(check_param_type),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn json_to_uint(value: &Value, size: usize) -> Result<U256, Box<dyn Error>> {
+    check_int_size(size)?;
+    let uint = json_to_u256(value)?;
+    if size < 256 && !(uint >> size).is_zero() {
+        return Err(format!("{} doesn't fit in uint{}", value, size).into());
+    }
+    Ok(uint)
+}
+
+pub(crate) fn json_to_int(value: &Value, size: usize) -> Result<U256, Box<dyn Error>> {
+    check_int_size(size)?;
+    let int = json_to_i256(value)?;
+    // every bit above the sign bit of int<size> is a copy of it
+    let high_bits = int >> (size - 1);
+    if !high_bits.is_zero() && high_bits != U256::MAX >> (size - 1) {
+        return Err(format!("{} doesn't fit in int{}", value, size).into());
+    }
+    Ok(int)
+}
+
+fn json_to_u256(value: &Value) -> Result<U256, Box<dyn Error>> {
+    match value {
+        Value::Number(n) => n
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| format!("expected unsigned integer, got {}", n).into()),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex_value) => Ok(U256::from_str_radix(hex_value, 16)?),
+            None => Ok(U256::from_dec_str(s)?),
+        },
+        _ => Err(format!("expected integer, got {}", value).into()),
+    }
+}
+
+fn json_to_i256(value: &Value) -> Result<U256, Box<dyn Error>> {
+    let (negative, magnitude) = match value {
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => (i < 0, U256::from(i.unsigned_abs())),
+            None => return Err(format!("expected integer, got {}", n).into()),
+        },
+        Value::String(s) => match s.strip_prefix('-') {
+            Some(abs) => (true, json_to_u256(&json!(abs))?),
+            None => (false, json_to_u256(value)?),
+        },
+        _ => return Err(format!("expected integer, got {}", value).into()),
+    };
+    // int256 goes from -2^255 to 2^255 - 1
+    let min_magnitude = U256::one() << 255;
+    if (negative && magnitude > min_magnitude) || (!negative && magnitude >= min_magnitude) {
+        return Err(format!("{} doesn't fit in int256", value).into());
+    }
+    // two's complement
+    Ok(if negative {
+        (!magnitude).overflowing_add(U256::one()).0
+    } else {
+        magnitude
+    })
+}
+
+fn i256_to_string(value: U256) -> String {
+    if value.bit(255) {
+        format!("-{}", (!value).overflowing_add(U256::one()).0)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Reads `function` from an ABI (or compiled artifact) file. `function` can be
+/// a plain name or a full signature to pick between overloads.
+pub fn function_from_abi_file(abi_path: &str, function: &str) -> Result<Function, Box<dyn Error>> {
+    let json: Value = serde_json::from_slice(&std::fs::read(abi_path)?)?;
+    let abi_json = match json.get("abi") {
+        Some(abi) => abi.clone(),
+        None => json,
+    };
+    let abi = ethabi::Contract::load(serde_json::to_vec(&abi_json)?.as_slice())?;
+
+    if function.contains('(') {
+        let wanted = parse_function_signature(function)?.short_signature();
+        abi.functions()
+            .find(|f| f.short_signature() == wanted)
+            .cloned()
+            .ok_or_else(|| format!("`{}` not found in {}", function, abi_path).into())
+    } else {
+        Ok(abi.function(function)?.clone())
+    }
+}
+
+impl Web3Manager {
+    fn single_function_contract(&self, address: H160, function: &Function) -> Contract<Http> {
+        let mut functions = BTreeMap::new();
+        functions.insert(function.name.clone(), vec![function.clone()]);
+        let abi = ethabi::Contract {
+            constructor: None,
+            functions,
+            events: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            receive: false,
+            fallback: false,
+        };
+        Contract::new(self.web3http.eth(), address, abi)
+    }
+
+    /// Calls (`eth_call`) `function` with JSON `args` and returns the decoded
+    /// outputs as a JSON array, or the raw return data when the function has
+    /// no declared outputs.
+    pub async fn call_dynamic_function(
+        &self,
+        contract_address: &str,
+        function: &Function,
+        args: &Value,
+    ) -> Result<Value, Box<dyn Error>> {
+        let tokens = json_to_inputs(function, args)?;
+        let data: Bytes = function.encode_input(&tokens)?.into();
+
+        let call_request = CallRequest {
+            from: self.accounts.first().cloned(),
+            to: Some(Address::from_str(contract_address)?),
+            data: Some(data),
+            ..Default::default()
+        };
+        let result: Bytes = self.web3http.eth().call(call_request, None).await?;
+
+        if function.outputs.is_empty() {
+            return Ok(json!(format!("0x{}", hex::encode(result.0))));
+        }
+        let outputs = function.decode_output(&result.0)?;
+        Ok(Value::Array(outputs.iter().map(token_to_json).collect()))
+    }
+
+    /// Signs and sends `function` with JSON `args` from `account`.
+    pub async fn send_dynamic_function(
+        &mut self,
+        account: H160,
+        contract_address: &str,
+        function: &Function,
+        args: &Value,
+        value: U256,
+    ) -> Result<H256, Box<dyn Error>> {
+        let tokens = json_to_inputs(function, args)?;
+        let contract =
+            self.single_function_contract(Address::from_str(contract_address)?, function);
+
+        self.sign_and_send_tx(
+            account,
+            &contract,
+            &function.name,
+            &tokens.as_slice(),
+            value,
+        )
+        .await
+    }
+
+    /// `call_dynamic_function` from a signature such as `"balanceOf(address)(uint256)"`
+    /// and a JSON array of arguments.
+    pub async fn call_function(
+        &self,
+        contract_address: &str,
+        signature: &str,
+        args_json: &str,
+    ) -> Result<Value, Box<dyn Error>> {
+        let function = parse_function_signature(signature)?;
+        let args: Value = serde_json::from_str(args_json)?;
+        self.call_dynamic_function(contract_address, &function, &args)
+            .await
+    }
+
+    /// `send_dynamic_function` from a signature such as `"transfer(address,uint256)"`
+    /// and a JSON array of arguments.
+    pub async fn send_function(
+        &mut self,
+        account: H160,
+        contract_address: &str,
+        signature: &str,
+        args_json: &str,
+        value: U256,
+    ) -> Result<H256, Box<dyn Error>> {
+        let function = parse_function_signature(signature)?;
+        let args: Value = serde_json::from_str(args_json)?;
+        self.send_dynamic_function(account, contract_address, &function, &args, value)
+            .await
+    }
+
+    pub async fn call_function_from_abi_file(
+        &self,
+        contract_address: &str,
+        abi_path: &str,
+        function: &str,
+        args_json: &str,
+    ) -> Result<Value, Box<dyn Error>> {
+        let function = function_from_abi_file(abi_path, function)?;
+        let args: Value = serde_json::from_str(args_json)?;
+        self.call_dynamic_function(contract_address, &function, &args)
+            .await
+    }
+
+    pub async fn send_function_from_abi_file(
+        &mut self,
+        account: H160,
+        contract_address: &str,
+        abi_path: &str,
+        function: &str,
+        args_json: &str,
+        value: U256,
+    ) -> Result<H256, Box<dyn Error>> {
+        let function = function_from_abi_file(abi_path, function)?;
+        let args: Value = serde_json::from_str(args_json)?;
+        self.send_dynamic_function(account, contract_address, &function, &args, value)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signatures() {
+        let transfer = parse_function_signature("transfer(address,uint256)").unwrap();
+        assert_eq!(transfer.signature(), "transfer(address,uint256)");
+        assert_eq!(transfer.short_signature(), [0xa9, 0x05, 0x9c, 0xbb]);
+        assert!(transfer.outputs.is_empty());
+
+        let balance = parse_function_signature("balanceOf(address) returns (uint256)").unwrap();
+        assert_eq!(balance.signature(), "balanceOf(address):(uint256)");
+
+        let reserves = parse_function_signature("getReserves()(uint112,uint112,uint32)").unwrap();
+        assert!(reserves.inputs.is_empty());
+        assert_eq!(reserves.outputs.len(), 3);
+
+        let tuple = parse_function_signature("f((address,uint256)[],bytes32)").unwrap();
+        assert_eq!(tuple.signature(), "f((address,uint256)[],bytes32)");
+        assert_eq!(tuple.inputs.len(), 2);
+
+        assert!(parse_function_signature("transfer").is_err());
+        assert!(parse_function_signature("transfer(address").is_err());
+    }
+
+    #[test]
+    fn json_arguments_round_trip() {
+        let function =
+            parse_function_signature("f(address,uint256,int256,bool,bytes,uint256[])").unwrap();
+        let args = json!([
+            "0x10ed43c718714eb63d5aa57b78b54704e256024e",
+            "0xff",
+            "-5",
+            true,
+            "0x0102",
+            [1, "2"]
+        ]);
+        let tokens = json_to_inputs(&function, &args).unwrap();
+        assert_eq!(tokens[1], Token::Uint(U256::from(255)));
+
+        let back: Vec<Value> = tokens.iter().map(token_to_json).collect();
+        assert_eq!(back[0], json!("0x10ed43c718714eb63d5aa57b78b54704e256024e"));
+        assert_eq!(back[1], json!("255"));
+        assert_eq!(back[2], json!("-5"));
+        assert_eq!(back[3], json!(true));
+        assert_eq!(back[4], json!("0x0102"));
+        assert_eq!(back[5], json!(["1", "2"]));
+    }
+
+    #[test]
+    fn rejects_wrong_arguments() {
+        let function = parse_function_signature("transfer(address,uint256)").unwrap();
+        assert!(json_to_inputs(&function, &json!(["0x00"])).is_err());
+        assert!(json_to_inputs(&function, &json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn checks_integer_sizes_and_ranges() {
+        assert!(parse_function_signature("f(uint7)").is_err());
+        assert!(parse_function_signature("f(int264)").is_err());
+        assert!(parse_function_signature("f((uint8,uint12)[])").is_err());
+
+        let function = parse_function_signature("f(uint8,int8,int256)").unwrap();
+        let tokens = json_to_inputs(&function, &json!([255, -128, "-1"])).unwrap();
+        assert_eq!(tokens[1], Token::Int(U256::MAX - 127));
+        assert_eq!(tokens[2], Token::Int(U256::MAX));
+        assert!(json_to_inputs(&function, &json!([256, 0, 0])).is_err());
+        assert!(json_to_inputs(&function, &json!([0, 128, 0])).is_err());
+        assert!(json_to_inputs(&function, &json!([0, -129, 0])).is_err());
+        let too_big = format!("{}", U256::one() << 255);
+        assert!(json_to_inputs(&function, &json!([0, 0, too_big])).is_err());
+        let smallest = format!("-{}", U256::one() << 255);
+        assert!(json_to_inputs(&function, &json!([0, 0, smallest])).is_ok());
+    }
+
+    #[test]
+    fn reads_functions_from_abi_files() {
+        let function =
+            function_from_abi_file("abi/PancakeRouterAbi.json", "getAmountsOut").unwrap();
+        assert_eq!(function.inputs.len(), 2);
+        let function = function_from_abi_file(
+            "abi/PancakeRouterAbi.json",
+            "getAmountsOut(uint256,address[])",
+        )
+        .unwrap();
+        assert_eq!(function.outputs.len(), 1);
+    }
+}
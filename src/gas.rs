@@ -0,0 +1,476 @@
+//! Gas price strategies and the fee guard.
+//!
+//! The fees of every transaction signed by the manager come from its
+//! `GasSettings` unless the transaction sets its own: a `GasStrategy` picks
+//! them, the estimated gas limit gets `gas_limit_margin_percent` on top and
+//! nothing is signed with a price per gas above `fee_ceiling`. Strategies
+//! compose, e.g. a capped 150% of the 60th percentile priority fee:
+//!
+//! ```ignore
+//! web3m.set_gas_strategy(Capped::new(
+//!     Multiplied::new(FeeHistoryPercentile::new(60.0), 150),
+//!     gwei(200),
+//! ));
+//! ```
+
+use crate::Web3Manager;
+use async_trait::async_trait;
+use std::error::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
+use web3::transports::Http;
+use web3::types::{BlockNumber, FeeHistory, Transaction, TransactionParameters, U256, U64};
+use web3::Web3;
+
+const EIP1559_TX_TYPE: u64 = 2;
+
+pub fn gwei(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(9)
+}
+
+/// Fees of a transaction, by transaction type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionFees {
+    Legacy {
+        gas_price: U256,
+    },
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl TransactionFees {
+    pub fn of(transaction: &Transaction) -> Result<TransactionFees, Box<dyn Error>> {
+        match (
+            transaction.max_fee_per_gas,
+            transaction.max_priority_fee_per_gas,
+        ) {
+            (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => {
+                Ok(TransactionFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                })
+            }
+            _ => Ok(TransactionFees::Legacy {
+                gas_price: transaction.gas_price.ok_or_else(|| {
+                    format!("transaction {:?} has no gas price", transaction.hash)
+                })?,
+            }),
+        }
+    }
+
+    /// Fees set on `parameters`, `None` when it has none.
+    pub fn of_parameters(parameters: &TransactionParameters) -> Option<TransactionFees> {
+        match (parameters.max_fee_per_gas, parameters.gas_price) {
+            (Some(max_fee_per_gas), _) => Some(TransactionFees::Eip1559 {
+                max_fee_per_gas,
+                // web3 signs a missing priority fee as the max fee
+                max_priority_fee_per_gas: parameters
+                    .max_priority_fee_per_gas
+                    .unwrap_or(max_fee_per_gas),
+            }),
+            (None, Some(gas_price)) => Some(TransactionFees::Legacy { gas_price }),
+            (None, None) => None,
+        }
+    }
+
+    /// Most the transaction can pay per gas.
+    pub fn max_price_per_gas(&self) -> U256 {
+        match *self {
+            TransactionFees::Legacy { gas_price } => gas_price,
+            TransactionFees::Eip1559 {
+                max_fee_per_gas, ..
+            } => max_fee_per_gas,
+        }
+    }
+
+    /// Every fee times `percent` / 100.
+    pub fn scaled(&self, percent: u64) -> TransactionFees {
+        let scale = |fee: U256| fee * U256::from(percent) / U256::from(100);
+        match *self {
+            TransactionFees::Legacy { gas_price } => TransactionFees::Legacy {
+                gas_price: scale(gas_price),
+            },
+            TransactionFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => TransactionFees::Eip1559 {
+                max_fee_per_gas: scale(max_fee_per_gas),
+                max_priority_fee_per_gas: scale(max_priority_fee_per_gas),
+            },
+        }
+    }
+
+    /// Every fee lowered to `max_price_per_gas` at most.
+    pub fn capped(&self, max_price_per_gas: U256) -> TransactionFees {
+        match *self {
+            TransactionFees::Legacy { gas_price } => TransactionFees::Legacy {
+                gas_price: gas_price.min(max_price_per_gas),
+            },
+            TransactionFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => TransactionFees::Eip1559 {
+                max_fee_per_gas: max_fee_per_gas.min(max_price_per_gas),
+                max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_price_per_gas),
+            },
+        }
+    }
+
+    /// Sets the fees, and the transaction type they need, on `parameters`.
+    pub fn apply(&self, parameters: &mut TransactionParameters) {
+        match *self {
+            TransactionFees::Legacy { gas_price } => {
+                parameters.gas_price = Some(gas_price);
+                parameters.max_fee_per_gas = None;
+                parameters.max_priority_fee_per_gas = None;
+                if parameters.transaction_type == Some(U64::from(EIP1559_TX_TYPE)) {
+                    parameters.transaction_type = None;
+                }
+            }
+            TransactionFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                parameters.gas_price = None;
+                parameters.max_fee_per_gas = Some(max_fee_per_gas);
+                parameters.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+                parameters.transaction_type = Some(U64::from(EIP1559_TX_TYPE));
+            }
+        }
+    }
+}
+
+/// Picks the fees of the next transaction.
+#[async_trait]
+pub trait GasStrategy: Debug + Send + Sync {
+    async fn fees(&self, web3: &Web3<Http>) -> Result<TransactionFees, web3::Error>;
+}
+
+/// `eth_gasPrice` as the node suggests it, a legacy transaction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NodeGasPrice;
+
+#[async_trait]
+impl GasStrategy for NodeGasPrice {
+    async fn fees(&self, web3: &Web3<Http>) -> Result<TransactionFees, web3::Error> {
+        Ok(TransactionFees::Legacy {
+            gas_price: web3.eth().gas_price().await?,
+        })
+    }
+}
+
+/// Always the same fees, no node call.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedFees(pub TransactionFees);
+
+impl FixedFees {
+    pub fn gwei(gas_price: u64) -> FixedFees {
+        FixedFees(TransactionFees::Legacy {
+            gas_price: gwei(gas_price),
+        })
+    }
+}
+
+#[async_trait]
+impl GasStrategy for FixedFees {
+    async fn fees(&self, _web3: &Web3<Http>) -> Result<TransactionFees, web3::Error> {
+        Ok(self.0)
+    }
+}
+
+/// EIP-1559 fees from `eth_feeHistory`: the `percentile` priority fee paid
+/// in the last `block_count` blocks, and a max fee that still covers the
+/// next base fee after it doubles.
+#[derive(Clone, Copy, Debug)]
+pub struct FeeHistoryPercentile {
+    pub percentile: f64,
+    pub block_count: u64,
+}
+
+impl FeeHistoryPercentile {
+    pub fn new(percentile: f64) -> FeeHistoryPercentile {
+        FeeHistoryPercentile {
+            percentile,
+            block_count: 10,
+        }
+    }
+
+    /// Fees suggested by `history`, which must hold the rewards of a single
+    /// percentile.
+    pub fn fees_from_history(history: &FeeHistory) -> TransactionFees {
+        // the last base fee is the one of the next block
+        let base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+        let mut rewards: Vec<U256> = history
+            .reward
+            .iter()
+            .flatten()
+            .filter_map(|block_rewards| block_rewards.first().copied())
+            .collect();
+        rewards.sort();
+        let priority_fee = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+        TransactionFees::Eip1559 {
+            max_fee_per_gas: base_fee * 2 + priority_fee,
+            max_priority_fee_per_gas: priority_fee,
+        }
+    }
+}
+
+#[async_trait]
+impl GasStrategy for FeeHistoryPercentile {
+    async fn fees(&self, web3: &Web3<Http>) -> Result<TransactionFees, web3::Error> {
+        let history = web3
+            .eth()
+            .fee_history(
+                U256::from(self.block_count.max(1)),
+                BlockNumber::Latest,
+                Some(vec![self.percentile]),
+            )
+            .await?;
+        Ok(FeeHistoryPercentile::fees_from_history(&history))
+    }
+}
+
+/// Fees of another strategy times `percent` / 100, to outbid everyone on
+/// urgent snipes.
+#[derive(Clone, Debug)]
+pub struct Multiplied {
+    pub inner: Arc<dyn GasStrategy>,
+    pub percent: u64,
+}
+
+impl Multiplied {
+    pub fn new<S: GasStrategy + 'static>(inner: S, percent: u64) -> Multiplied {
+        Multiplied {
+            inner: Arc::new(inner),
+            percent,
+        }
+    }
+}
+
+#[async_trait]
+impl GasStrategy for Multiplied {
+    async fn fees(&self, web3: &Web3<Http>) -> Result<TransactionFees, web3::Error> {
+        Ok(self.inner.fees(web3).await?.scaled(self.percent))
+    }
+}
+
+/// Fees of another strategy lowered to a maximum price per gas. Unlike the
+/// fee ceiling the transaction is still sent, just cheaper.
+#[derive(Clone, Debug)]
+pub struct Capped {
+    pub inner: Arc<dyn GasStrategy>,
+    pub max_price_per_gas: U256,
+}
+
+impl Capped {
+    pub fn new<S: GasStrategy + 'static>(inner: S, max_price_per_gas: U256) -> Capped {
+        Capped {
+            inner: Arc::new(inner),
+            max_price_per_gas,
+        }
+    }
+}
+
+#[async_trait]
+impl GasStrategy for Capped {
+    async fn fees(&self, web3: &Web3<Http>) -> Result<TransactionFees, web3::Error> {
+        Ok(self.inner.fees(web3).await?.capped(self.max_price_per_gas))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GasSettings {
+    pub strategy: Arc<dyn GasStrategy>,
+    /// Added to estimated gas limits.
+    pub gas_limit_margin_percent: u64,
+    /// Transactions paying more per gas are refused.
+    pub fee_ceiling: Option<U256>,
+}
+
+impl Default for GasSettings {
+    fn default() -> GasSettings {
+        GasSettings {
+            strategy: Arc::new(NodeGasPrice),
+            gas_limit_margin_percent: 20,
+            fee_ceiling: None,
+        }
+    }
+}
+
+impl GasSettings {
+    pub fn with_strategy<S: GasStrategy + 'static>(strategy: S) -> GasSettings {
+        GasSettings {
+            strategy: Arc::new(strategy),
+            ..Default::default()
+        }
+    }
+
+    pub fn gas_limit(&self, estimated_gas: U256) -> U256 {
+        estimated_gas + estimated_gas * U256::from(self.gas_limit_margin_percent) / U256::from(100)
+    }
+
+    pub fn check_ceiling(&self, fees: &TransactionFees) -> Result<(), Box<dyn Error>> {
+        match self.fee_ceiling {
+            Some(ceiling) if fees.max_price_per_gas() > ceiling => Err(format!(
+                "fee of {} wei per gas is above the ceiling of {}",
+                fees.max_price_per_gas(),
+                ceiling
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Web3Manager {
+    pub fn gas_settings(&self) -> &GasSettings {
+        &self.gas_settings
+    }
+
+    pub fn set_gas_settings(&mut self, gas_settings: GasSettings) {
+        self.gas_settings = gas_settings;
+    }
+
+    pub fn set_gas_strategy<S: GasStrategy + 'static>(&mut self, strategy: S) {
+        self.gas_settings.strategy = Arc::new(strategy);
+    }
+
+    pub fn set_gas_limit_margin(&mut self, percent: u64) {
+        self.gas_settings.gas_limit_margin_percent = percent;
+    }
+
+    pub fn set_fee_ceiling(&mut self, fee_ceiling: Option<U256>) {
+        self.gas_settings.fee_ceiling = fee_ceiling;
+    }
+
+    /// Fees picked by the strategy of `gas_settings`, checked against its
+    /// ceiling.
+    pub async fn transaction_fees(
+        &self,
+        gas_settings: &GasSettings,
+    ) -> Result<TransactionFees, Box<dyn Error>> {
+        let fees = gas_settings.strategy.fees(&self.web3http).await?;
+        gas_settings.check_ceiling(&fees)?;
+        Ok(fees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract_cache::AbiKind;
+    use crate::gas_accounting::GasBudget;
+    use crate::mock_node::mock_manager;
+    use serde_json::json;
+    use web3::types::H160;
+
+    #[test]
+    fn suggests_fees_from_history() {
+        let history = FeeHistory {
+            oldest_block: BlockNumber::Number(U64::from(100)),
+            base_fee_per_gas: vec![gwei(10), gwei(11), gwei(12)],
+            gas_used_ratio: vec![0.5, 0.6],
+            reward: Some(vec![vec![gwei(3)], vec![gwei(1)], vec![gwei(2)]]),
+        };
+        assert_eq!(
+            FeeHistoryPercentile::fees_from_history(&history),
+            TransactionFees::Eip1559 {
+                max_fee_per_gas: gwei(26),
+                max_priority_fee_per_gas: gwei(2),
+            }
+        );
+    }
+
+    #[test]
+    fn scales_caps_and_applies_fees() {
+        let fees = TransactionFees::Eip1559 {
+            max_fee_per_gas: gwei(40),
+            max_priority_fee_per_gas: gwei(2),
+        };
+        assert_eq!(
+            fees.scaled(150),
+            TransactionFees::Eip1559 {
+                max_fee_per_gas: gwei(60),
+                max_priority_fee_per_gas: gwei(3),
+            }
+        );
+        assert_eq!(fees.capped(gwei(30)).max_price_per_gas(), gwei(30));
+
+        let mut parameters = TransactionParameters::default();
+        fees.apply(&mut parameters);
+        assert_eq!(parameters.transaction_type, Some(U64::from(2)));
+        assert_eq!(TransactionFees::of_parameters(&parameters), Some(fees));
+
+        let legacy = TransactionFees::Legacy { gas_price: gwei(5) };
+        legacy.apply(&mut parameters);
+        assert_eq!(parameters.transaction_type, None);
+        assert_eq!(parameters.max_fee_per_gas, None);
+        assert_eq!(TransactionFees::of_parameters(&parameters), Some(legacy));
+    }
+
+    #[test]
+    fn guards_gas_limit_and_ceiling() {
+        let settings = GasSettings {
+            fee_ceiling: Some(gwei(50)),
+            ..Default::default()
+        };
+        assert_eq!(settings.gas_limit(U256::from(100_000)), U256::from(120_000));
+        assert!(settings
+            .check_ceiling(&TransactionFees::Legacy {
+                gas_price: gwei(50)
+            })
+            .is_ok());
+        assert!(settings
+            .check_ceiling(&TransactionFees::Legacy {
+                gas_price: gwei(51)
+            })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn refused_transactions_keep_the_nonce() {
+        let (mut web3m, _) = mock_manager(|method, _| match method {
+            "eth_getTransactionCount" => Ok(json!("0x5")),
+            "eth_estimateGas" => Ok(json!("0xb411")),
+            "eth_gasPrice" => Ok(json!(format!("{:#x}", gwei(5)))),
+            _ => Err(format!("unexpected {}", method)),
+        })
+        .await;
+        web3m
+            .load_account("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .await;
+        let account = web3m.first_account();
+        let token = web3m
+            .cached_contract_from_str(&format!("{:?}", H160::repeat_byte(1)), AbiKind::Token)
+            .unwrap();
+        let approve = (H160::repeat_byte(2), U256::one());
+
+        // the call settings have no ceiling, the one of the manager still applies
+        web3m.set_fee_ceiling(Some(gwei(1)));
+        let result = web3m
+            .sign_and_send_tx_with_gas(
+                account,
+                &token,
+                "approve",
+                &approve,
+                U256::zero(),
+                &GasSettings::default(),
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(web3m.get_current_nonce(), U256::from(5));
+
+        web3m.set_fee_ceiling(None);
+        web3m.set_gas_budget(GasBudget {
+            total: Some(U256::zero()),
+            ..Default::default()
+        });
+        let result = web3m
+            .sign_and_send_tx(account, &token, "approve", &approve, U256::zero())
+            .await;
+        assert!(result.is_err());
+        assert_eq!(web3m.get_current_nonce(), U256::from(5));
+    }
+}
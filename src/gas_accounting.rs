@@ -0,0 +1,496 @@
+//! Gas spent by the transactions the manager sends.
+//!
+//! When the receipt of a tracked transaction is seen (`wait_for_receipt`,
+//! replacements or the tracker polling) its `gas_used` × effective gas
+//! price is recorded once in the `GasLedger`, reverted transactions
+//! included. Costs are totalled per account, per run (a label set with
+//! `start_gas_run` around a strategy, e.g. the trading check of the bot) and
+//! per UTC day of the block.
+//!
+//! A `GasBudget` limits what one account may spend, once a limit is reached
+//! `sign_transaction` fails before signing anything. Transactions still in
+//! flight count at the most they can cost, so a burst of sends can't go over
+//! the budget before their receipts are seen.
+
+use crate::tx_tracker::{TrackedTransaction, TransactionStatus};
+use crate::Web3Manager;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use web3::types::{BlockId, TransactionId, TransactionReceipt, H160, H256, U256, U64};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasCost {
+    pub transaction_hash: H256,
+    pub account: H160,
+    pub run: Option<String>,
+    /// UTC day of the block it was mined in.
+    pub day: NaiveDate,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    pub reverted: bool,
+}
+
+impl GasCost {
+    /// Wei paid for gas.
+    pub fn cost(&self) -> U256 {
+        self.gas_used * self.effective_gas_price
+    }
+}
+
+/// Limits of what one account may spend on gas, in wei. `None` is no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GasBudget {
+    pub total: Option<U256>,
+    /// In the current run.
+    pub per_run: Option<U256>,
+    /// In the current UTC day.
+    pub per_day: Option<U256>,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    costs: Vec<GasCost>,
+    current_run: Option<String>,
+    budget: GasBudget,
+}
+
+/// Gas costs recorded by a manager, shared between its clones like the
+/// transaction tracker.
+#[derive(Clone, Debug, Default)]
+pub struct GasLedger {
+    ledger: Arc<RwLock<Ledger>>,
+}
+
+fn today() -> NaiveDate {
+    Utc::now().naive_utc().date()
+}
+
+fn sum<'a>(costs: impl Iterator<Item = &'a GasCost>) -> U256 {
+    costs.fold(U256::zero(), |total, cost| total + cost.cost())
+}
+
+// Only one transaction per nonce can be mined, replacements count once at
+// the highest cost
+fn max_in_flight_cost<'a>(in_flight: impl Iterator<Item = &'a TrackedTransaction>) -> U256 {
+    let mut by_nonce: BTreeMap<U256, U256> = BTreeMap::new();
+    for transaction in in_flight {
+        let cost = by_nonce.entry(transaction.nonce).or_default();
+        *cost = (*cost).max(transaction.max_gas_cost());
+    }
+    by_nonce
+        .values()
+        .fold(U256::zero(), |total, cost| total + cost)
+}
+
+impl GasLedger {
+    pub fn new() -> GasLedger {
+        GasLedger::default()
+    }
+
+    /// Records `cost` unless its transaction already was, returns whether
+    /// it was new.
+    pub fn record(&self, cost: GasCost) -> bool {
+        let mut ledger = self.ledger.write().unwrap();
+        if ledger
+            .costs
+            .iter()
+            .any(|recorded| recorded.transaction_hash == cost.transaction_hash)
+        {
+            return false;
+        }
+        ledger.costs.push(cost);
+        true
+    }
+
+    pub fn costs(&self) -> Vec<GasCost> {
+        self.ledger.read().unwrap().costs.clone()
+    }
+
+    pub fn current_run(&self) -> Option<String> {
+        self.ledger.read().unwrap().current_run.clone()
+    }
+
+    pub fn set_current_run(&self, run: Option<String>) {
+        self.ledger.write().unwrap().current_run = run;
+    }
+
+    pub fn budget(&self) -> GasBudget {
+        self.ledger.read().unwrap().budget
+    }
+
+    pub fn set_budget(&self, budget: GasBudget) {
+        self.ledger.write().unwrap().budget = budget;
+    }
+
+    pub fn spent_by(&self, account: H160) -> U256 {
+        let ledger = self.ledger.read().unwrap();
+        sum(ledger.costs.iter().filter(|cost| cost.account == account))
+    }
+
+    pub fn spent_in_run(&self, run: &str) -> U256 {
+        let ledger = self.ledger.read().unwrap();
+        sum(ledger
+            .costs
+            .iter()
+            .filter(|cost| cost.run.as_deref() == Some(run)))
+    }
+
+    pub fn totals_by_account(&self) -> BTreeMap<H160, U256> {
+        self.totals(|cost| Some(cost.account))
+    }
+
+    pub fn totals_by_run(&self) -> BTreeMap<String, U256> {
+        self.totals(|cost| cost.run.clone())
+    }
+
+    pub fn totals_by_day(&self) -> BTreeMap<NaiveDate, U256> {
+        self.totals(|cost| Some(cost.day))
+    }
+
+    fn totals<K: Ord>(&self, key: impl Fn(&GasCost) -> Option<K>) -> BTreeMap<K, U256> {
+        let mut totals = BTreeMap::new();
+        for cost in &self.ledger.read().unwrap().costs {
+            if let Some(key) = key(cost) {
+                *totals.entry(key).or_insert_with(U256::zero) += cost.cost();
+            }
+        }
+        totals
+    }
+
+    /// Fails when `account` reached one of the limits of the budget on
+    /// `day`, counting its `in_flight` transactions at their max cost.
+    pub fn check_budget(
+        &self,
+        account: H160,
+        day: NaiveDate,
+        in_flight: &[TrackedTransaction],
+    ) -> Result<(), Box<dyn Error>> {
+        let ledger = self.ledger.read().unwrap();
+        let costs = || ledger.costs.iter().filter(|cost| cost.account == account);
+        let in_flight = || {
+            in_flight
+                .iter()
+                .filter(|transaction| transaction.account == account)
+        };
+        let exceeded = |limit: Option<U256>, spent: U256, what: &str| match limit {
+            Some(limit) if spent >= limit => Err(format!(
+                "{:?} spent {} wei on gas {}, the budget is {}",
+                account, spent, what, limit
+            )),
+            _ => Ok(()),
+        };
+
+        exceeded(
+            ledger.budget.total,
+            sum(costs()) + max_in_flight_cost(in_flight()),
+            "in total",
+        )?;
+        if let Some(run) = &ledger.current_run {
+            exceeded(
+                ledger.budget.per_run,
+                sum(costs().filter(|cost| cost.run.as_ref() == Some(run)))
+                    + max_in_flight_cost(
+                        in_flight().filter(|transaction| transaction.run.as_ref() == Some(run)),
+                    ),
+                &format!("in run {}", run),
+            )?;
+        }
+        // the ones in flight are mined today at the earliest
+        exceeded(
+            ledger.budget.per_day,
+            sum(costs().filter(|cost| cost.day == day)) + max_in_flight_cost(in_flight()),
+            &format!("on {}", day),
+        )?;
+        Ok(())
+    }
+}
+
+impl Web3Manager {
+    pub fn gas_ledger(&self) -> &GasLedger {
+        &self.gas_ledger
+    }
+
+    /// Labels the gas spent by the transactions sent from now on.
+    pub fn start_gas_run(&self, run: &str) {
+        self.gas_ledger.set_current_run(Some(run.to_string()));
+    }
+
+    /// Ends the current run and returns the gas it spent.
+    pub fn end_gas_run(&self) -> U256 {
+        let spent = match self.gas_ledger.current_run() {
+            Some(run) => self.gas_ledger.spent_in_run(&run),
+            None => U256::zero(),
+        };
+        self.gas_ledger.set_current_run(None);
+        spent
+    }
+
+    pub fn set_gas_budget(&self, budget: GasBudget) {
+        self.gas_ledger.set_budget(budget);
+    }
+
+    /// Fails when `account` reached its gas budget, with its transactions in
+    /// flight included.
+    pub fn check_gas_budget(&self, account: H160) -> Result<(), Box<dyn Error>> {
+        self.gas_ledger.check_budget(
+            account,
+            today(),
+            &self.tx_tracker().unmined_in_flight(account),
+        )
+    }
+
+    /// Settles the tracked transaction of `receipt` and records the gas it
+    /// paid, receipts of transactions that aren't tracked are ignored. The
+    /// transaction is mined either way, so failing to record it is only
+    /// logged.
+    pub(crate) async fn record_gas_cost(&self, receipt: &TransactionReceipt) {
+        // its cost is in the ledger from now on, not in flight
+        self.tx_tracker().set_status(
+            receipt.transaction_hash,
+            TransactionStatus::from_receipt(receipt),
+        );
+        if let Err(err) = self.try_record_gas_cost(receipt).await {
+            eprintln!(
+                "gas of {:?} not recorded: {}",
+                receipt.transaction_hash, err
+            );
+        }
+    }
+
+    async fn try_record_gas_cost(
+        &self,
+        receipt: &TransactionReceipt,
+    ) -> Result<(), Box<dyn Error>> {
+        let tracked = match self.tx_tracker().get(receipt.transaction_hash) {
+            Some(tracked) => tracked,
+            None => return Ok(()),
+        };
+        // older nodes don't report the effective price, it is the gas price
+        // of legacy transactions
+        let effective_gas_price = match receipt.effective_gas_price {
+            Some(price) => price,
+            None => self
+                .web3http
+                .eth()
+                .transaction(TransactionId::Hash(receipt.transaction_hash))
+                .await?
+                .and_then(|transaction| transaction.gas_price)
+                .unwrap_or_default(),
+        };
+        let block_hash = receipt.block_hash.ok_or("the receipt has no block")?;
+        let block = self
+            .web3http
+            .eth()
+            .block(BlockId::Hash(block_hash))
+            .await?
+            .ok_or_else(|| format!("the node doesn't know block {:?}", block_hash))?;
+        let mined_at = NaiveDateTime::from_timestamp_opt(block.timestamp.low_u64() as i64, 0)
+            .ok_or("invalid block timestamp")?;
+        self.gas_ledger.record(GasCost {
+            transaction_hash: receipt.transaction_hash,
+            account: tracked.account,
+            run: tracked.run,
+            day: mined_at.date(),
+            gas_used: receipt.gas_used.unwrap_or_default(),
+            effective_gas_price,
+            reverted: receipt.status == Some(U64::from(0)),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::mock_manager;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    fn cost(hash: u64, account: u64, run: Option<&str>, day: u32) -> GasCost {
+        GasCost {
+            transaction_hash: H256::from_low_u64_be(hash),
+            account: H160::from_low_u64_be(account),
+            run: run.map(str::to_string),
+            day: NaiveDate::from_ymd(2022, 5, day),
+            gas_used: U256::from(21_000),
+            effective_gas_price: U256::from(10),
+            reverted: false,
+        }
+    }
+
+    #[test]
+    fn totals_costs() {
+        let ledger = GasLedger::new();
+        assert!(ledger.record(cost(1, 1, Some("buy"), 1)));
+        assert!(ledger.record(cost(2, 1, Some("sell"), 2)));
+        assert!(ledger.record(cost(3, 2, Some("buy"), 2)));
+        // the same receipt seen twice
+        assert!(!ledger.record(cost(3, 2, Some("buy"), 2)));
+
+        let per_transaction = U256::from(210_000);
+        assert_eq!(
+            ledger.spent_by(H160::from_low_u64_be(1)),
+            per_transaction * 2
+        );
+        assert_eq!(ledger.spent_in_run("buy"), per_transaction * 2);
+        assert_eq!(ledger.totals_by_run()["sell"], per_transaction);
+        assert_eq!(
+            ledger.totals_by_day()[&NaiveDate::from_ymd(2022, 5, 2)],
+            per_transaction * 2
+        );
+        assert_eq!(ledger.totals_by_account().len(), 2);
+    }
+
+    #[test]
+    fn enforces_budgets() {
+        let ledger = GasLedger::new();
+        let account = H160::from_low_u64_be(1);
+        let day = NaiveDate::from_ymd(2022, 5, 1);
+        ledger.record(cost(1, 1, Some("buy"), 1));
+        ledger.set_budget(GasBudget {
+            total: Some(U256::from(1_000_000)),
+            per_run: Some(U256::from(210_000)),
+            per_day: Some(U256::from(420_000)),
+        });
+        assert!(ledger.check_budget(account, day, &[]).is_ok());
+
+        ledger.set_current_run(Some("buy".to_string()));
+        assert!(ledger.check_budget(account, day, &[]).is_err());
+        ledger.set_current_run(Some("sell".to_string()));
+        assert!(ledger.check_budget(account, day, &[]).is_ok());
+
+        ledger.record(cost(2, 1, None, 1));
+        assert!(ledger.check_budget(account, day, &[]).is_err());
+        assert!(ledger
+            .check_budget(account, NaiveDate::from_ymd(2022, 5, 2), &[])
+            .is_ok());
+        // other accounts have their own budget
+        assert!(ledger
+            .check_budget(H160::from_low_u64_be(2), day, &[])
+            .is_ok());
+    }
+
+    fn in_flight(hash: u64, nonce: u64, max_price_per_gas: u64) -> TrackedTransaction {
+        TrackedTransaction {
+            hash: H256::from_low_u64_be(hash),
+            account: H160::from_low_u64_be(1),
+            nonce: U256::from(nonce),
+            to: None,
+            value: U256::zero(),
+            gas: U256::from(21_000),
+            max_price_per_gas: U256::from(max_price_per_gas),
+            run: None,
+            sent_at: Instant::now(),
+            status: TransactionStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn counts_transactions_in_flight() {
+        let ledger = GasLedger::new();
+        let account = H160::from_low_u64_be(1);
+        let day = NaiveDate::from_ymd(2022, 5, 1);
+        ledger.set_budget(GasBudget {
+            total: Some(U256::from(630_000)),
+            ..Default::default()
+        });
+        // at most 210_000 + 420_000, nothing has a receipt yet
+        let sent = [in_flight(1, 5, 10), in_flight(2, 6, 20)];
+        assert!(ledger.check_budget(account, day, &sent[..1]).is_ok());
+        assert!(ledger.check_budget(account, day, &sent).is_err());
+        // a replacement of nonce 5 counts instead of the replaced one
+        let replaced = [in_flight(1, 5, 10), in_flight(3, 5, 15)];
+        assert!(ledger.check_budget(account, day, &replaced).is_ok());
+    }
+
+    #[tokio::test]
+    async fn records_the_day_of_the_block() {
+        let block_hash = H256::repeat_byte(7);
+        let (web3m, _) = mock_manager(move |method, _| match method {
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": H256::from_low_u64_be(1),
+                "transactionIndex": "0x0",
+                "blockHash": block_hash,
+                "blockNumber": "0x10",
+                "from": H160::from_low_u64_be(1),
+                "cumulativeGasUsed": "0x5208",
+                "gasUsed": "0x5208",
+                "effectiveGasPrice": "0xa",
+                "logs": [],
+                "status": "0x1",
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+            })),
+            // 2022-05-01 23:59:59 UTC
+            "eth_getBlockByHash" => Ok(json!({
+                "hash": block_hash,
+                "parentHash": H256::zero(),
+                "sha3Uncles": H256::zero(),
+                "miner": H160::zero(),
+                "stateRoot": H256::zero(),
+                "transactionsRoot": H256::zero(),
+                "receiptsRoot": H256::zero(),
+                "number": "0x10",
+                "gasUsed": "0x5208",
+                "gasLimit": "0x1c9c380",
+                "extraData": "0x",
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "timestamp": format!("{:#x}", 1_651_449_599u64),
+                "difficulty": "0x0",
+                "uncles": [],
+                "transactions": [],
+                "size": "0x0",
+            })),
+            _ => Err(format!("unexpected {}", method)),
+        })
+        .await;
+        web3m.tx_tracker().record(in_flight(1, 5, 10));
+
+        web3m
+            .wait_for_receipt(H256::from_low_u64_be(1), Duration::from_secs(5))
+            .await
+            .unwrap();
+        let costs = web3m.gas_ledger().costs();
+        assert_eq!(costs.len(), 1);
+        assert_eq!(costs[0].day, NaiveDate::from_ymd(2022, 5, 1));
+        assert_eq!(costs[0].cost(), U256::from(210_000));
+
+        // counted once, from the ledger and no longer in flight
+        let account = H160::from_low_u64_be(1);
+        assert!(web3m.tx_tracker().in_flight(account).is_empty());
+        web3m.set_gas_budget(GasBudget {
+            total: Some(U256::from(210_001)),
+            ..Default::default()
+        });
+        assert!(web3m.check_gas_budget(account).is_ok());
+    }
+
+    #[tokio::test]
+    async fn mined_transactions_dont_fail_on_accounting_errors() {
+        let (web3m, _) = mock_manager(|method, _| match method {
+            "eth_getTransactionReceipt" => Ok(json!({
+                "transactionHash": H256::from_low_u64_be(1),
+                "transactionIndex": "0x0",
+                "blockHash": H256::repeat_byte(7),
+                "blockNumber": "0x10",
+                "from": H160::from_low_u64_be(1),
+                "cumulativeGasUsed": "0x5208",
+                "gasUsed": "0x5208",
+                "logs": [],
+                "status": "0x1",
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+            })),
+            // no effective gas price in the receipt and no node to ask
+            _ => Err("unavailable".to_string()),
+        })
+        .await;
+        web3m.tx_tracker().record(in_flight(1, 5, 10));
+
+        assert!(web3m
+            .wait_for_receipt(H256::from_low_u64_be(1), Duration::from_secs(5))
+            .await
+            .is_ok());
+        assert!(web3m.gas_ledger().costs().is_empty());
+    }
+}
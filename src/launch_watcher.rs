@@ -0,0 +1,555 @@
+//! Event-driven launch detection: waits for the factory's `PairCreated` and
+//! the pair's `Mint`/`Sync` logs instead of polling `getReserves`, and can
+//! fire a buy signed ahead of time as soon as liquidity shows up.
+
+use crate::bindings::PancakeFactory;
+use crate::create2::sort_tokens;
+use crate::{AbiKind, Web3Manager};
+use futures::StreamExt;
+use std::error::Error;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::contract::tokens::Tokenize;
+use web3::contract::Options;
+use web3::ethabi::{self, Token};
+use web3::types::{
+    Bytes, FilterBuilder, TransactionId, TransactionParameters, H160, H256, U256, U64,
+};
+
+/// Liquidity found on a pair, either already there when the watch started or
+/// added by the log in `transaction_hash`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiquidityEvent {
+    pub pair: H160,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub block_number: Option<U64>,
+    pub transaction_hash: Option<H256>,
+}
+
+/// A pending `addLiquidity`/`addLiquidityETH` transaction seen in the mempool.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingLiquidity {
+    pub transaction_hash: H256,
+    pub from: Option<H160>,
+    pub gas_price: Option<U256>,
+    pub function: String,
+}
+
+/// A transaction signed in advance, ready to be broadcast without any
+/// further round trip to the node.
+#[derive(Clone, Debug)]
+pub struct PrebuiltTransaction {
+    pub account: H160,
+    pub nonce: U256,
+    pub transaction_hash: H256,
+    pub raw_transaction: Bytes,
+}
+
+/// Returns true if `input` is an `addLiquidity` or `addLiquidityETH` call of
+/// `router_abi` that adds liquidity for `token`.
+pub fn adds_liquidity_for(router_abi: &ethabi::Contract, input: &[u8], token: H160) -> bool {
+    if input.len() < 4 {
+        return false;
+    }
+
+    // (function, number of leading token arguments)
+    for (name, token_args) in [("addLiquidityETH", 1), ("addLiquidity", 2)] {
+        let function = match router_abi.function(name) {
+            Ok(function) => function,
+            Err(_) => continue,
+        };
+        if function.short_signature() != input[..4] {
+            continue;
+        }
+        return match function.decode_input(&input[4..]) {
+            Ok(args) => args
+                .iter()
+                .take(token_args)
+                .any(|arg| *arg == Token::Address(token)),
+            Err(_) => false,
+        };
+    }
+    false
+}
+
+fn address_topic(address: H160) -> H256 {
+    H256::from(address)
+}
+
+// Gives the nonce of a prebuilt buy back to the manager when it is dropped
+// before the buy was broadcast, on errors and when the watch is cancelled
+struct PrebuiltNonceGuard<'a> {
+    manager: &'a mut Web3Manager,
+    buy: &'a PrebuiltTransaction,
+    sent: bool,
+}
+
+impl Deref for PrebuiltNonceGuard<'_> {
+    type Target = Web3Manager;
+
+    fn deref(&self) -> &Web3Manager {
+        self.manager
+    }
+}
+
+impl Drop for PrebuiltNonceGuard<'_> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.manager.release_prebuilt_nonce(self.buy);
+        }
+    }
+}
+
+impl Web3Manager {
+    /// Waits until `token_address` has liquidity against `weth_address`,
+    /// following the pair from its `PairCreated` log if it doesn't exist
+    /// yet. `on_liquidity` is called once with the reserves and block.
+    pub async fn watch_for_liquidity<F>(
+        &self,
+        factory_address: &str,
+        weth_address: &str,
+        token_address: &str,
+        mut on_liquidity: F,
+    ) -> Result<LiquidityEvent, Box<dyn Error>>
+    where
+        F: FnMut(&LiquidityEvent),
+    {
+        let factory = self.factory_bindings(H160::from_str(factory_address)?);
+        let pair_address = self
+            .wait_for_pair(
+                &factory,
+                H160::from_str(weth_address)?,
+                H160::from_str(token_address)?,
+            )
+            .await?;
+
+        let event = self.watch_pair_liquidity(pair_address).await?;
+        on_liquidity(&event);
+        Ok(event)
+    }
+
+    /// Returns the pair of `token_a` and `token_b`, waiting for its
+    /// `PairCreated` log if the factory hasn't deployed it yet.
+    pub async fn wait_for_pair(
+        &self,
+        factory: &PancakeFactory,
+        token_a: H160,
+        token_b: H160,
+    ) -> Result<H160, Box<dyn Error>> {
+        let (token0, token1) = sort_tokens(token_a, token_b);
+        let filter = FilterBuilder::default()
+            .address(vec![factory.address()])
+            .topics(
+                Some(vec![factory.pair_created_topic()]),
+                Some(vec![address_topic(token0)]),
+                Some(vec![address_topic(token1)]),
+                None,
+            )
+            .build();
+        let mut logs = self
+            .web3web_socket
+            .eth_subscribe()
+            .subscribe_logs(filter)
+            .await?;
+
+        // subscribe first so a pair created in between isn't missed
+        let pair_address = factory.get_pair(token0, token1).await?;
+        if !pair_address.is_zero() {
+            return Ok(pair_address);
+        }
+
+        while let Some(log) = logs.next().await {
+            let created = factory.decode_pair_created(&log?)?;
+            if created.token0 == token0 && created.token1 == token1 {
+                return Ok(created.pair);
+            }
+        }
+        Err("PairCreated subscription closed".into())
+    }
+
+    /// Waits for the first `Sync`/`Mint` log that leaves reserves on
+    /// `pair_address`. The pair doesn't need to exist yet, so an address from
+    /// `predict_lp_pair` can be watched before the factory creates it.
+    pub async fn watch_pair_liquidity(
+        &self,
+        pair_address: H160,
+    ) -> Result<LiquidityEvent, Box<dyn Error>> {
+        let pair = self.pair_bindings(pair_address);
+        let sync_topic = pair.sync_topic();
+        let filter = FilterBuilder::default()
+            .address(vec![pair_address])
+            .topics(Some(vec![sync_topic, pair.mint_topic()]), None, None, None)
+            .build();
+        let mut logs = self
+            .web3web_socket
+            .eth_subscribe()
+            .subscribe_logs(filter)
+            .await?;
+
+        // liquidity may have been added before the subscription started
+        let code = self.web3http.eth().code(pair_address, None).await?;
+        if !code.0.is_empty() {
+            let reserves = pair.get_reserves().await?;
+            if !reserves.reserve0.is_zero() && !reserves.reserve1.is_zero() {
+                return Ok(LiquidityEvent {
+                    pair: pair_address,
+                    reserve0: reserves.reserve0,
+                    reserve1: reserves.reserve1,
+                    block_number: Some(self.web3http.eth().block_number().await?),
+                    transaction_hash: None,
+                });
+            }
+        }
+
+        while let Some(log) = logs.next().await {
+            let log = log?;
+            let (reserve0, reserve1) = if log.topics.first() == Some(&sync_topic) {
+                let sync = pair.decode_sync(&log)?;
+                (sync.reserve0, sync.reserve1)
+            } else {
+                let reserves = pair.get_reserves().await?;
+                (reserves.reserve0, reserves.reserve1)
+            };
+
+            if !reserve0.is_zero() && !reserve1.is_zero() {
+                return Ok(LiquidityEvent {
+                    pair: pair_address,
+                    reserve0,
+                    reserve1,
+                    block_number: log.block_number,
+                    transaction_hash: log.transaction_hash,
+                });
+            }
+        }
+        Err("pair log subscription closed".into())
+    }
+
+    /// Waits for a pending `addLiquidity`/`addLiquidityETH` call for
+    /// `token_address` sent to the router. Broadcasting a buy with the same
+    /// gas price right after it gives it a chance to land in the same block.
+    pub async fn watch_pending_liquidity(
+        &self,
+        router_address: &str,
+        token_address: &str,
+    ) -> Result<PendingLiquidity, Box<dyn Error>> {
+        let router_address = H160::from_str(router_address)?;
+        let token_address = H160::from_str(token_address)?;
+        let router_abi = AbiKind::Router.abi();
+
+        let mut pending = self
+            .web3web_socket
+            .eth_subscribe()
+            .subscribe_new_pending_transactions()
+            .await?;
+
+        while let Some(transaction_hash) = pending.next().await {
+            // the transaction may already be gone from the node's pool
+            let tx = match self
+                .web3http
+                .eth()
+                .transaction(TransactionId::Hash(transaction_hash?))
+                .await?
+            {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            if tx.to != Some(router_address)
+                || !adds_liquidity_for(&router_abi, &tx.input.0, token_address)
+            {
+                continue;
+            }
+
+            let function = router_abi
+                .functions()
+                .find(|function| function.short_signature() == tx.input.0[..4])
+                .map(|function| function.name.clone())
+                .unwrap_or_default();
+
+            return Ok(PendingLiquidity {
+                transaction_hash: tx.hash,
+                from: tx.from,
+                gas_price: tx.gas_price,
+                function,
+            });
+        }
+        Err("pending transaction subscription closed".into())
+    }
+
+    /// Signs a `swapExactETHForTokensSupportingFeeOnTransferTokens` buy of
+    /// `token_address` with the current nonce, without sending it.
+    ///
+    /// The pool is usually empty at this point, so the swap can't be
+    /// estimated: `options.gas` is required. `options.gas_price` defaults to
+    /// the fees of the gas strategy. The deadline is `expected_launch` plus
+    /// the deadline window (never before `chain_deadline`), rebuild the buy
+    /// if the launch is later than that. The nonce is taken until the buy is
+    /// sent, `snipe_*` give it back when they fail or are dropped, otherwise
+    /// call `release_prebuilt_nonce`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn prebuild_buy(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        eth_amount: U256,
+        amount_out_min: U256,
+        expected_launch: SystemTime,
+        options: Options,
+    ) -> Result<PrebuiltTransaction, Box<dyn Error>> {
+        let gas = options
+            .gas
+            .ok_or("a gas limit is required, the buy can't be estimated before launch")?;
+
+        let router_address = H160::from_str(router_address)?;
+        let router = self.router_bindings(router_address);
+        let weth_address = router.weth().await?;
+
+        let launch_deadline = U256::from(expected_launch.duration_since(UNIX_EPOCH)?.as_secs())
+            + self.deadline_window().as_secs();
+        let parameters = (
+            amount_out_min,
+            vec![weth_address, H160::from_str(token_address)?],
+            account,
+            launch_deadline.max(self.chain_deadline().await?),
+        );
+        let data = AbiKind::Router
+            .abi()
+            .function("swapExactETHForTokensSupportingFeeOnTransferTokens")?
+            .encode_input(&parameters.into_tokens())?;
+
+        let nonce = self.get_current_nonce();
+        let tx_parameters = TransactionParameters {
+            nonce: Some(nonce),
+            to: Some(router_address),
+            gas,
+            gas_price: options.gas_price,
+            value: eth_amount,
+            data: data.into(),
+            chain_id: self.chain_id,
+            ..Default::default()
+        };
+
+        let signed = self.sign_transaction(account, tx_parameters).await?;
+        self.update_nonce();
+
+        Ok(PrebuiltTransaction {
+            account,
+            nonce,
+            transaction_hash: signed.transaction_hash,
+            raw_transaction: signed.raw_transaction,
+        })
+    }
+
+    /// Gives the nonce of a buy that won't be sent back, if no transaction
+    /// took a later nonce since it was built.
+    pub fn release_prebuilt_nonce(&mut self, buy: &PrebuiltTransaction) {
+        if self.get_current_nonce() == buy.nonce + 1 {
+            self.set_current_nonce(buy.nonce);
+        }
+    }
+
+    /// Broadcasts `tx` as any other transaction of the manager, so it is
+    /// tracked and its gas goes to the gas ledger.
+    pub async fn send_prebuilt(&self, tx: &PrebuiltTransaction) -> Result<H256, Box<dyn Error>> {
+        self.send_raw_transaction_tracked(tx.raw_transaction.clone())
+            .await
+    }
+
+    /// Broadcasts `buy` as soon as the pair gets liquidity, so it lands in
+    /// the block after the one that added it.
+    pub async fn snipe_launch<F>(
+        &mut self,
+        factory_address: &str,
+        weth_address: &str,
+        token_address: &str,
+        buy: &PrebuiltTransaction,
+        on_liquidity: F,
+    ) -> Result<(LiquidityEvent, H256), Box<dyn Error>>
+    where
+        F: FnMut(&LiquidityEvent),
+    {
+        let mut guard = PrebuiltNonceGuard {
+            manager: self,
+            buy,
+            sent: false,
+        };
+        let event = guard
+            .watch_for_liquidity(factory_address, weth_address, token_address, on_liquidity)
+            .await?;
+        let tx_hash = guard.send_prebuilt(buy).await?;
+        guard.sent = true;
+        Ok((event, tx_hash))
+    }
+
+    /// Broadcasts `buy` as soon as the liquidity transaction is seen pending,
+    /// aiming for the same block. `buy` should be built with the gas price
+    /// the launch is expected to use.
+    pub async fn snipe_pending_liquidity(
+        &mut self,
+        router_address: &str,
+        token_address: &str,
+        buy: &PrebuiltTransaction,
+    ) -> Result<(PendingLiquidity, H256), Box<dyn Error>> {
+        let mut guard = PrebuiltNonceGuard {
+            manager: self,
+            buy,
+            sent: false,
+        };
+        let pending = guard
+            .watch_pending_liquidity(router_address, token_address)
+            .await?;
+        let tx_hash = guard.send_prebuilt(buy).await?;
+        guard.sent = true;
+        Ok((pending, tx_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::mock_manager;
+    use crate::signer::{LocalSigner, Signer};
+    use serde_json::{json, Value};
+
+    fn address(byte: u8) -> H160 {
+        H160::repeat_byte(byte)
+    }
+
+    #[test]
+    fn detects_add_liquidity_eth() {
+        let abi = AbiKind::Router.abi();
+        let input = abi
+            .function("addLiquidityETH")
+            .unwrap()
+            .encode_input(
+                &(
+                    address(1),
+                    U256::from(1000),
+                    U256::zero(),
+                    U256::zero(),
+                    address(9),
+                    U256::from(1),
+                )
+                    .into_tokens(),
+            )
+            .unwrap();
+
+        assert!(adds_liquidity_for(&abi, &input, address(1)));
+        assert!(!adds_liquidity_for(&abi, &input, address(2)));
+        // the recipient isn't the token
+        assert!(!adds_liquidity_for(&abi, &input, address(9)));
+    }
+
+    #[test]
+    fn detects_add_liquidity_either_side() {
+        let abi = AbiKind::Router.abi();
+        let input = abi
+            .function("addLiquidity")
+            .unwrap()
+            .encode_input(
+                &(
+                    address(1),
+                    address(2),
+                    U256::from(1000),
+                    U256::from(1000),
+                    U256::zero(),
+                    U256::zero(),
+                    address(9),
+                    U256::from(1),
+                )
+                    .into_tokens(),
+            )
+            .unwrap();
+
+        assert!(adds_liquidity_for(&abi, &input, address(1)));
+        assert!(adds_liquidity_for(&abi, &input, address(2)));
+    }
+
+    #[test]
+    fn ignores_other_calls() {
+        let abi = AbiKind::Router.abi();
+        let input = abi
+            .function("swapExactETHForTokens")
+            .unwrap()
+            .encode_input(
+                &(
+                    U256::zero(),
+                    vec![address(1), address(2)],
+                    address(9),
+                    U256::from(1),
+                )
+                    .into_tokens(),
+            )
+            .unwrap();
+
+        assert!(!adds_liquidity_for(&abi, &input, address(1)));
+        assert!(!adds_liquidity_for(&abi, &[0x01, 0x02], address(1)));
+    }
+
+    #[tokio::test]
+    async fn gives_the_nonce_back_when_the_snipe_fails() {
+        let (mut web3m, _) = mock_manager(|_, _| Ok(Value::Null)).await;
+        let buy = PrebuiltTransaction {
+            account: address(1),
+            nonce: U256::from(5),
+            transaction_hash: H256::zero(),
+            raw_transaction: Bytes::default(),
+        };
+        web3m.set_current_nonce(U256::from(6));
+
+        let token = format!("{:?}", address(2));
+        let result = web3m
+            .snipe_launch("not an address", &token, &token, &buy, |_| {})
+            .await;
+        assert!(result.is_err());
+        assert_eq!(web3m.get_current_nonce(), U256::from(5));
+
+        // a later transaction already took the next nonce
+        web3m.set_current_nonce(U256::from(7));
+        web3m.release_prebuilt_nonce(&buy);
+        assert_eq!(web3m.get_current_nonce(), U256::from(7));
+    }
+
+    #[tokio::test]
+    async fn tracks_prebuilt_buys() {
+        let signer = LocalSigner::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let signed = signer
+            .sign_transaction(TransactionParameters {
+                nonce: Some(U256::from(5)),
+                to: Some(address(1)),
+                gas: U256::from(300_000),
+                gas_price: Some(U256::from(5_000_000_000u64)),
+                value: U256::exp10(17),
+                chain_id: Some(56),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let hash = signed.transaction_hash;
+        let (web3m, _) = mock_manager(move |method, _| match method {
+            "eth_sendRawTransaction" => Ok(json!(hash)),
+            _ => Err(format!("unexpected {}", method)),
+        })
+        .await;
+        let buy = PrebuiltTransaction {
+            account: signer.address(),
+            nonce: U256::from(5),
+            transaction_hash: hash,
+            raw_transaction: signed.raw_transaction,
+        };
+
+        assert_eq!(web3m.send_prebuilt(&buy).await.unwrap(), hash);
+        let tracked = web3m.tx_tracker().get(hash).unwrap();
+        assert_eq!(tracked.account, signer.address());
+        assert_eq!(
+            tracked.max_gas_cost(),
+            U256::from(300_000u64 * 5_000_000_000)
+        );
+    }
+}
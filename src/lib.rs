@@ -1,14 +1,52 @@
 extern crate alloc;
 
+pub mod allowance;
+pub mod amm;
+pub mod amount;
+pub mod approval_audit;
+pub mod bindgen;
+pub mod bindings;
+pub mod contract_cache;
+pub mod create2;
+pub mod deploy;
+pub mod dynamic_abi;
 pub mod ethereum_mainnet;
+pub mod gas;
+pub mod gas_accounting;
+pub mod launch_watcher;
+pub mod liquidity;
+pub mod message_signing;
+#[cfg(test)]
+mod mock_node;
+pub mod multisend;
+pub mod offline;
+pub mod permit;
+pub mod replacement;
+pub mod reserve_tracker;
 pub mod rinkeby_testnet;
+pub mod signer;
+pub mod slippage;
+pub mod swap;
 pub mod traits;
+pub mod tx_inspector;
+pub mod tx_tracker;
+pub mod wrap;
+
+pub use allowance::{AllowanceSettings, ApprovalPolicy};
+pub use amount::Amount;
+pub use contract_cache::{AbiKind, ContractCache};
+pub use gas::{GasSettings, GasStrategy, TransactionFees};
+pub use gas_accounting::{GasBudget, GasLedger};
+pub use multisend::{MultisendMode, MultisendOptions, MultisendState, RecipientList};
+pub use signer::{KeystoreSigner, LocalSigner, RemoteSigner, Signer, SignerError};
+pub use slippage::Slippage;
+pub use tx_tracker::{TrackedTransaction, TransactionStatus, TransactionTracker};
+pub use wrap::WrapOutcome;
 
 use alloc::boxed::Box;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
-use ethereum_private_key_to_address::PrivateKey;
 use futures::StreamExt;
 use secp256k1::rand::rngs::StdRng;
 use secp256k1::rand::Rng;
@@ -22,7 +60,8 @@ use std::collections::HashMap;
 use std::convert::{From, TryFrom};
 use std::error::Error;
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use web3::api::SubscriptionStream;
 use web3::contract::tokens::{Detokenize, Tokenize};
 use web3::contract::{Contract, Options};
@@ -32,13 +71,19 @@ use web3::helpers as w3h;
 use web3::signing::keccak256;
 use web3::transports::{Http, WebSocket};
 use web3::types::{
-    Address, BlockNumber, Bytes, FilterBuilder, Log, SignedTransaction, TransactionId,
-    TransactionParameters, H160, U256, U64,
+    Address, BlockId, BlockNumber, Bytes, CallRequest, FilterBuilder, Log, SignedTransaction,
+    TransactionId, TransactionParameters, TransactionReceipt, H160, U256, U64,
 };
 use web3::Web3;
 
+use crate::replacement::ReplaceableTransaction;
+
 // use hex_literal::hex;
 
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_DEADLINE_WINDOW: Duration = Duration::from_secs(20 * 60);
+const NATIVE_TRANSFER_GAS: u64 = 21_000;
+
 /// Emulates a `switch` statement.
 ///
 /// The syntax is similar to `match` except that every left-side expression is
@@ -226,10 +271,23 @@ pub struct Web3Manager {
     // web3 https instance (for use call or write contract functions)
     pub web3web_socket: Web3<WebSocket>,
     // web3 websocket instance (for listen contracts events)
-    accounts_map: HashMap<H160, String>,
+    // signer of each loaded account
+    signers: HashMap<H160, Arc<dyn Signer>>,
     current_nonce: U256,
-    // hashmap (like mapping on solidity) for store public and private keys
     chain_id: Option<u64>,
+    // contract instances built from the bundled abis
+    contract_cache: ContractCache,
+    // slippage and deadline window used when a swap doesn't set its own
+    default_slippage: Slippage,
+    deadline_window: Duration,
+    // approval policy used before spending tokens
+    allowance_settings: AllowanceSettings,
+    // fees and gas limit margin of the transactions signed
+    gas_settings: GasSettings,
+    // gas paid by the transactions sent, and the budgets
+    gas_ledger: GasLedger,
+    // transactions sent and not settled yet
+    tx_tracker: TransactionTracker,
 }
 
 impl Web3Manager {
@@ -240,6 +298,14 @@ impl Web3Manager {
         self.current_nonce
     }
 
+    pub fn default_slippage(&self) -> Slippage {
+        self.default_slippage
+    }
+
+    pub fn deadline_window(&self) -> Duration {
+        self.deadline_window
+    }
+
     //-------------------------------------------------------------------------
     //                        setters
     //-------------------------------------------------------------------------
@@ -247,6 +313,14 @@ impl Web3Manager {
         self.current_nonce = new_nonce;
     }
 
+    pub fn set_default_slippage(&mut self, slippage: Slippage) {
+        self.default_slippage = slippage;
+    }
+
+    pub fn set_deadline_window(&mut self, window: Duration) {
+        self.deadline_window = window;
+    }
+
     /*
     pub async fn call_write_function<P: Clone>(
         &mut self,
@@ -314,10 +388,8 @@ impl Web3Manager {
     }
 
     pub async fn get_token_balance(&self, token_address: &str, account: H160) -> U256 {
-        let token_abi = include_bytes!("../abi/TokenAbi.json");
-        let token_instance: Contract<Http> = self
-            .instance_contract(token_address, token_abi)
-            .await
+        let token_instance = self
+            .cached_contract_from_str(token_address, AbiKind::Token)
             .unwrap();
 
         /*
@@ -335,6 +407,8 @@ impl Web3Manager {
         token_balance
     }
 
+    // Deadline from the local clock, prefer `chain_deadline`
+    // Fecha límite según el reloj local, mejor usar `chain_deadline`
     pub fn generate_deadline(&self) -> U256 {
         U256::from(
             SystemTime::now()
@@ -344,59 +418,142 @@ impl Web3Manager {
         ) + 10000usize
     }
 
-    // TODO(elsuizo:2022-03-03): documentation here
+    // Deadline from the latest block timestamp plus the deadline window
+    // Fecha límite a partir del timestamp del último bloque más la ventana configurada
+    pub async fn chain_deadline(&self) -> Result<U256, Box<dyn Error>> {
+        self.chain_deadline_in(self.deadline_window).await
+    }
+
+    pub async fn chain_deadline_in(&self, window: Duration) -> Result<U256, Box<dyn Error>> {
+        let latest_block = self
+            .web3http
+            .eth()
+            .block(BlockId::Number(BlockNumber::Latest))
+            .await?
+            .ok_or("the node returned no latest block")?;
+        Ok(latest_block.timestamp + window.as_secs())
+    }
+
+    // Swaps an exact amount of the first token of the path for as many of the last as possible
+    // Intercambia una cantidad exacta del primer token de la ruta por el máximo posible del último
+    pub async fn swap_exact_tokens_for_tokens(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        amount_in: U256,
+        pairs: &[&str],
+        slippage: Slippage,
+        to: H160,
+    ) -> Result<H256, Box<dyn Error>> {
+        self.swap_exact_tokens_with(
+            "swapExactTokensForTokens",
+            account,
+            router_address,
+            amount_in,
+            pairs,
+            slippage,
+            to,
+        )
+        .await
+    }
+
+    // Swaps as few of the first token as needed for an exact amount of the last
+    // Intercambia lo mínimo necesario del primer token por una cantidad exacta del último
     pub async fn swap_tokens_for_exact_tokens(
         &mut self,
         account: H160,
         router_address: &str,
-        token_amount: U256,
+        amount_out: U256,
         pairs: &[&str],
-        slippage: usize,
+        slippage: Slippage,
+        to: H160,
     ) -> Result<H256, Box<dyn Error>> {
         let contract_function = "swapTokensForExactTokens";
 
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
-            .expect("error creating the router instance");
-
-        let mut addresses = Vec::new();
-        for pair in pairs {
-            addresses.push(Address::from_str(pair).unwrap());
-        }
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        let addresses = pairs
+            .iter()
+            .map(|pair| Address::from_str(pair))
+            .collect::<Result<Vec<Address>, _>>()?;
 
-        let parameter_out = (token_amount, addresses.clone());
-        let amount_out_min: Vec<Uint> = self
-            .query_contract(&router_instance, "getAmountsOut", parameter_out)
-            .await
-            .unwrap();
+        let amounts_in: Vec<Uint> = self
+            .query_contract(
+                &router_instance,
+                "getAmountsIn",
+                (amount_out, addresses.clone()),
+            )
+            .await?;
+        let amount_in = *amounts_in.first().ok_or("empty swap path")?;
+        let amount_in_max = slippage.max_in(amount_in);
 
-        let min_amount = U256::from(amount_out_min[1].as_u128());
-        let min_amount_less_slippage = min_amount - ((min_amount * slippage) / 100usize);
+        self.ensure_allowance(account, pairs[0], router_address, amount_in_max)
+            .await?;
 
-        let parameters2 = (
-            token_amount,
-            min_amount_less_slippage,
+        let parameters = (
+            amount_out,
+            amount_in_max,
             addresses,
-            self.first_account(),
-            self.generate_deadline(),
+            to,
+            self.chain_deadline().await?,
         );
 
-        println!("amount_out: {:?}", token_amount);
-        println!("min_amount_less_slippage: {:?}", min_amount_less_slippage);
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            contract_function,
+            &parameters,
+            U256::zero(),
+        )
+        .await
+    }
 
-        let send_tx_result = self
-            .sign_and_send_tx(
-                account,
+    // The exact input swaps only differ on the router function
+    #[allow(clippy::too_many_arguments)]
+    async fn swap_exact_tokens_with(
+        &mut self,
+        contract_function: &str,
+        account: H160,
+        router_address: &str,
+        amount_in: U256,
+        pairs: &[&str],
+        slippage: Slippage,
+        to: H160,
+    ) -> Result<H256, Box<dyn Error>> {
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        let addresses = pairs
+            .iter()
+            .map(|pair| Address::from_str(pair))
+            .collect::<Result<Vec<Address>, _>>()?;
+
+        let amounts_out: Vec<Uint> = self
+            .query_contract(
                 &router_instance,
-                contract_function,
-                &parameters2,
-                token_amount,
+                "getAmountsOut",
+                (amount_in, addresses.clone()),
             )
-            .await;
+            .await?;
+        let amount_out = *amounts_out.last().ok_or("empty swap path")?;
+        let amount_out_min = slippage.min_out(amount_out);
 
-        send_tx_result
+        self.ensure_allowance(account, pairs[0], router_address, amount_in)
+            .await?;
+
+        let parameters = (
+            amount_in,
+            amount_out_min,
+            addresses,
+            to,
+            self.chain_deadline().await?,
+        );
+
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            contract_function,
+            &parameters,
+            U256::zero(),
+        )
+        .await
     }
 
     pub async fn get_token_allowance(
@@ -405,10 +562,8 @@ impl Web3Manager {
         account: H160,
         spender: H160,
     ) -> U256 {
-        let token_abi = include_bytes!("../abi/TokenAbi.json");
-        let token_instance: Contract<Http> = self
-            .instance_contract(token_address, token_abi)
-            .await
+        let token_instance = self
+            .cached_contract_from_str(token_address, AbiKind::Token)
             .unwrap();
 
         let token_allowance: U256 = self
@@ -420,10 +575,8 @@ impl Web3Manager {
     }
 
     pub async fn get_token_price(&mut self, router_address: &str, token_address: &str) -> U256 {
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
+        let router_instance = self
+            .cached_contract_from_str(router_address, AbiKind::Router)
             .expect("error creating the router instance");
         let weth_address = self.get_weth_address(&router_instance).await;
 
@@ -443,45 +596,27 @@ impl Web3Manager {
         min_amount
     }
 
+    // For tokens with a transfer fee, the slippage has to cover the fee
+    // Para tokens con comisión por transferencia, el slippage debe cubrir la comisión
     pub async fn swap_exact_tokens_for_tokens_supporting_fee_on_transfer_tokens(
         &mut self,
         account: H160,
         router_address: &str,
-        token_amount: U256,
+        amount_in: U256,
         pairs: &[&str],
+        slippage: Slippage,
+        to: H160,
     ) -> Result<H256, Box<dyn Error>> {
-        let contract_function: &str = "swapExactTokensForTokensSupportingFeeOnTransferTokens";
-
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
-            .expect("error creating the router instance");
-
-        let mut addresses = Vec::new();
-        for pair in pairs {
-            addresses.push(Address::from_str(pair).unwrap());
-        }
-
-        let parameters = (
-            token_amount,
-            U256::from_dec_str("0").unwrap(),
-            addresses,
+        self.swap_exact_tokens_with(
+            "swapExactTokensForTokensSupportingFeeOnTransferTokens",
             account,
-            self.generate_deadline(),
-        );
-
-        let send_tx_result = self
-            .sign_and_send_tx(
-                account,
-                &router_instance,
-                contract_function,
-                &parameters,
-                U256::from_dec_str("0").unwrap(),
-            )
-            .await;
-
-        send_tx_result
+            router_address,
+            amount_in,
+            pairs,
+            slippage,
+            to,
+        )
+        .await
     }
 
     pub async fn swap_exact_tokens_for_eth(
@@ -490,14 +625,12 @@ impl Web3Manager {
         router_address: &str,
         token_address: &str,
         token_amount: U256,
-        slippage: usize,
+        slippage: Slippage,
     ) -> Result<H256, Box<dyn Error>> {
         let contract_function: &str = "swapExactTokensForETH";
 
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
+        let router_instance = self
+            .cached_contract_from_str(router_address, AbiKind::Router)
             .expect("error creating the router instance");
         let weth_address = self.get_weth_address(&router_instance).await;
 
@@ -512,14 +645,17 @@ impl Web3Manager {
             .unwrap();
 
         let min_amount = U256::from(amount_out_min[1].as_u128());
-        let min_amount_less_slippage = min_amount - ((min_amount * slippage) / 100);
+        let min_amount_less_slippage = slippage.min_out(min_amount);
+
+        self.ensure_allowance(account, token_address, router_address, token_amount)
+            .await?;
 
         let parameters = (
             token_amount,
             min_amount_less_slippage,
             addresses,
             account,
-            self.generate_deadline(),
+            self.chain_deadline().await?,
         );
 
         println!("token_amount: {:?}", token_amount);
@@ -545,14 +681,12 @@ impl Web3Manager {
         router_address: &str,
         token_address: &str,
         token_amount: U256,
-        slippage: usize,
+        slippage: Slippage,
     ) -> Result<H256, Box<dyn Error>> {
         let contract_function: &str = "swapExactTokensForETHSupportingFeeOnTransferTokens";
 
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
+        let router_instance = self
+            .cached_contract_from_str(router_address, AbiKind::Router)
             .expect("error creating the router instance");
         let weth_address = self.get_weth_address(&router_instance).await;
 
@@ -567,14 +701,17 @@ impl Web3Manager {
             .unwrap();
 
         let min_amount = U256::from(amount_out_min[1].as_u128());
-        let min_amount_less_slippage = min_amount - ((min_amount * slippage) / 100);
+        let min_amount_less_slippage = slippage.min_out(min_amount);
+
+        self.ensure_allowance(account, token_address, router_address, token_amount)
+            .await?;
 
         let parameters = (
             token_amount,
             min_amount_less_slippage,
             addresses,
             account,
-            self.generate_deadline(),
+            self.chain_deadline().await?,
         );
 
         println!("token_amount: {:?}", token_amount);
@@ -600,15 +737,13 @@ impl Web3Manager {
         router_address: &str,
         token_address: &str,
         eth_amount: U256,
-        slippage: usize,
+        slippage: Slippage,
     ) -> Result<H256, Box<dyn Error>> {
         let mut router_abi_path = "../abi/PancakeRouterAbi.json";
         let mut contract_function: &str = "swapExactETHForTokens";
 
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
+        let router_instance = self
+            .cached_contract_from_str(router_address, AbiKind::Router)
             .unwrap();
 
         let weth_address = self.get_weth_address(&router_instance).await;
@@ -656,13 +791,13 @@ impl Web3Manager {
             .unwrap();
 
         let min_amount = U256::from(amount_out_min[1].as_u128());
-        let min_amount_less_slippage = min_amount - ((min_amount * slippage) / 100usize);
+        let min_amount_less_slippage = slippage.min_out(min_amount);
 
         let parameters = (
             min_amount_less_slippage,
             addresses,
             account,
-            self.generate_deadline(),
+            self.chain_deadline().await?,
         );
 
         /*
@@ -732,18 +867,10 @@ impl Web3Manager {
     }
 
     pub async fn load_account(&mut self, plain_private_key: &str) {
-        let private_key = PrivateKey::from_str(plain_private_key).unwrap();
-        let wallet: H160 = H160::from_str(private_key.address().as_str()).unwrap();
-
-        // push on account list
-        self.accounts_map
-            .insert(wallet, plain_private_key.to_string());
-        self.accounts.push(wallet);
-
-        // get last nonce from loaded account
-        let nonce: U256 = self.last_nonce(wallet).await;
+        let signer = LocalSigner::from_str(plain_private_key).unwrap();
 
-        self.set_current_nonce(nonce);
+        // push on account list and get last nonce from loaded account
+        self.load_signer(signer).await;
     }
 
     pub async fn new_from_rpc_url(
@@ -763,7 +890,7 @@ impl Web3Manager {
 
         // create empty vector for store accounts
         let accounts: Vec<Address> = vec![];
-        let accounts_map: HashMap<H160, String> = HashMap::new();
+        let signers: HashMap<H160, Arc<dyn Signer>> = HashMap::new();
         let current_nonce: U256 = U256::from_dec_str("0").unwrap();
 
         //let chain_id: Option<u64> = Option::Some(u64::try_from(web3http.eth().chain_id().await.unwrap()).unwrap());
@@ -773,9 +900,16 @@ impl Web3Manager {
             accounts,
             web3http,
             web3web_socket,
-            accounts_map,
+            signers,
             current_nonce,
             chain_id,
+            contract_cache: ContractCache::new(),
+            default_slippage: Slippage::default(),
+            deadline_window: DEFAULT_DEADLINE_WINDOW,
+            allowance_settings: AllowanceSettings::default(),
+            gas_settings: GasSettings::default(),
+            gas_ledger: GasLedger::new(),
+            tx_tracker: TransactionTracker::new(),
         }
     }
 
@@ -797,7 +931,7 @@ impl Web3Manager {
 
         // create empty vector for store accounts
         let accounts: Vec<Address> = vec![];
-        let accounts_map: HashMap<H160, String> = HashMap::new();
+        let signers: HashMap<H160, Arc<dyn Signer>> = HashMap::new();
         let current_nonce: U256 = U256::from_dec_str("0").unwrap();
         let chain_id: Option<u64> = Option::Some(u64::try_from(network.chain_id.unwrap()).unwrap());
 
@@ -805,9 +939,16 @@ impl Web3Manager {
             accounts,
             web3http,
             web3web_socket,
-            accounts_map,
+            signers,
             current_nonce,
             chain_id,
+            contract_cache: ContractCache::new(),
+            default_slippage: Slippage::default(),
+            deadline_window: DEFAULT_DEADLINE_WINDOW,
+            allowance_settings: AllowanceSettings::default(),
+            gas_settings: GasSettings::default(),
+            gas_ledger: GasLedger::new(),
+            tx_tracker: TransactionTracker::new(),
         }
     }
 
@@ -823,6 +964,31 @@ impl Web3Manager {
         self.web3http.eth().block_number().await
     }
 
+    // Polls the node until the transaction is mined or the timeout expires
+    // Consulta al nodo hasta que la transacción se mina o expira el tiempo de espera
+    pub async fn wait_for_receipt(
+        &self,
+        tx_hash: H256,
+        timeout: Duration,
+    ) -> Result<TransactionReceipt, Box<dyn Error>> {
+        let started = Instant::now();
+        loop {
+            if let Some(receipt) = self.web3http.eth().transaction_receipt(tx_hash).await? {
+                self.record_gas_cost(&receipt).await;
+                if receipt.status == Some(U64::from(0)) {
+                    return Err(format!("transaction {:?} reverted", tx_hash).into());
+                }
+                return Ok(receipt);
+            }
+            if started.elapsed() > timeout {
+                return Err(
+                    format!("transaction {:?} not mined after {:?}", tx_hash, timeout).into(),
+                );
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+
     pub async fn query_contract<P, T>(
         &self,
         contract_instance: &Contract<Http>,
@@ -839,21 +1005,35 @@ impl Web3Manager {
             .await
     }
 
-    // The transactions must be signed with the private key of the wallet that executes it
-    // Las transacciones han de ser firmadas con la clave privada de la cartera que la ejecuta
+    // The transactions must be signed by the signer of the wallet that executes it
+    // Las transacciones han de ser firmadas por el firmante de la cartera que la ejecuta
     pub async fn sign_transaction(
-        &mut self,
+        &self,
         account: H160,
-        transact_obj: TransactionParameters,
-    ) -> SignedTransaction {
-        let plain_pk = self.accounts_map.get(&account).unwrap();
-        let private_key = SecretKey::from_str(plain_pk).unwrap();
+        mut transact_obj: TransactionParameters,
+    ) -> Result<SignedTransaction, Box<dyn Error>> {
+        let signer = self.signer(account)?;
+        self.check_gas_budget(account)?;
+
+        // fill what the signer can't query by itself
+        if transact_obj.nonce.is_none() {
+            transact_obj.nonce = Some(self.web3http.eth().transaction_count(account, None).await?);
+        }
+        match TransactionFees::of_parameters(&transact_obj) {
+            Some(fees) => self.gas_settings.check_ceiling(&fees)?,
+            None => self
+                .transaction_fees(&self.gas_settings)
+                .await?
+                .apply(&mut transact_obj),
+        }
+        if transact_obj.chain_id.is_none() {
+            transact_obj.chain_id = match self.chain_id {
+                Some(chain_id) => Some(chain_id),
+                None => Some(self.web3http.eth().chain_id().await?.as_u64()),
+            };
+        }
 
-        self.web3http
-            .accounts()
-            .sign_transaction(transact_obj, &private_key)
-            .await
-            .unwrap()
+        Ok(signer.sign_transaction(transact_obj).await?)
     }
 
     pub fn encode_tx_parameters(
@@ -929,28 +1109,21 @@ impl Web3Manager {
         token_address: &str,
         spender: &str,
         value: &str,
-    ) -> Result<H256, web3::Error> {
-        let token_abi = include_bytes!("../abi/TokenAbi.json");
-        let token_instance: Contract<Http> = self
-            .instance_contract(token_address, token_abi)
-            .await
-            .unwrap();
+    ) -> Result<H256, Box<dyn Error>> {
+        let token_instance = self.cached_contract_from_str(token_address, AbiKind::Token)?;
 
-        let spender_address: Address = Address::from_str(spender).unwrap();
+        let spender_address: Address = Address::from_str(spender)?;
         let contract_function = "approve";
-        let contract_function_parameters = (spender_address, U256::from_dec_str(value).unwrap());
-
-        let send_tx_result = self
-            .sign_and_send_tx(
-                account,
-                &token_instance,
-                &contract_function.to_string(),
-                &contract_function_parameters,
-                U256::from_dec_str("0").unwrap(),
-            )
-            .await;
+        let contract_function_parameters = (spender_address, U256::from_dec_str(value)?);
 
-        Ok(send_tx_result.unwrap())
+        self.sign_and_send_tx(
+            account,
+            &token_instance,
+            contract_function,
+            &contract_function_parameters,
+            U256::zero(),
+        )
+        .await
     }
 
     pub async fn sign_and_send_tx<P: Clone>(
@@ -963,6 +1136,68 @@ impl Web3Manager {
     ) -> Result<H256, Box<dyn Error>>
     where
         P: Tokenize,
+    {
+        let gas_settings = self.gas_settings.clone();
+        self.sign_and_send_tx_with_gas(
+            account,
+            contract_instance,
+            func,
+            params,
+            value,
+            &gas_settings,
+        )
+        .await
+    }
+
+    // Same as `sign_and_send_tx` with other gas settings for this call, the
+    // fee ceiling of the manager still applies
+    pub async fn sign_and_send_tx_with_gas<P>(
+        &mut self,
+        account: H160,
+        contract_instance: &Contract<Http>,
+        func: &str,
+        params: &P,
+        value: U256,
+        gas_settings: &GasSettings,
+    ) -> Result<H256, Box<dyn Error>>
+    where
+        P: Clone + Tokenize,
+    {
+        let tx_parameters = self
+            .build_tx_parameters(
+                account,
+                contract_instance,
+                func,
+                params,
+                value,
+                gas_settings,
+            )
+            .await?;
+
+        // 4. sign tx and send tx
+        let tx_hash = self
+            .sign_and_send_transaction(account, tx_parameters)
+            .await?;
+
+        // the nonce is only used when the transaction was sent
+        self.update_nonce();
+
+        Ok(tx_hash)
+    }
+
+    // Parameters of a contract call with the current nonce, gas limit and
+    // fees, ready to be signed. The nonce isn't incremented
+    pub async fn build_tx_parameters<P>(
+        &mut self,
+        account: H160,
+        contract_instance: &Contract<Http>,
+        func: &str,
+        params: &P,
+        value: U256,
+        gas_settings: &GasSettings,
+    ) -> Result<TransactionParameters, Box<dyn Error>>
+    where
+        P: Clone + Tokenize,
     {
         // estimate gas for call this function with this parameters
         // increase 200ms execution time, we use high gas available
@@ -983,85 +1218,148 @@ impl Web3Manager {
         if gas_estimation_result.is_err() {
             return Err(Box::new(gas_estimation_result.err().unwrap()));
         }
-        let mut estimated_tx_gas = gas_estimation_result.unwrap();
-
-        // increase estimated_tx_gas in 20%
-        estimated_tx_gas += estimated_tx_gas / 5;
+        // add the gas limit margin to the estimation
+        let estimated_tx_gas = gas_settings.gas_limit(gas_estimation_result.unwrap());
 
         // 2. encode_tx_data
         let tx_data: Bytes = self.encode_tx_data(contract_instance, func, params.clone());
-        let gas_price: U256 = self.web3http.eth().gas_price().await.unwrap();
+        let fees = self.transaction_fees(gas_settings).await?;
         let nonce: U256 = self.get_current_nonce();
 
         // 3. build tx parameters
-        let tx_parameters: TransactionParameters = self.encode_tx_parameters(
+        let mut tx_parameters: TransactionParameters = self.encode_tx_parameters(
             nonce,
             contract_instance.address(),
             value,
             estimated_tx_gas,
-            gas_price,
+            fees.max_price_per_gas(),
             tx_data,
         );
+        fees.apply(&mut tx_parameters);
 
-        // 4. sign tx and send tx
-        let tx_result = self.sign_and_send_transaction(account, tx_parameters).await;
-
-        self.update_nonce();
-
-        return Ok(tx_result.unwrap());
+        Ok(tx_parameters)
     }
 
     async fn sign_and_send_transaction(
         &mut self,
         account: H160,
         tx_parameters: TransactionParameters,
-    ) -> Result<H256, web3::Error> {
+    ) -> Result<H256, Box<dyn Error>> {
         let signed_transaction: SignedTransaction =
-            self.sign_transaction(account, tx_parameters).await;
+            self.sign_transaction(account, tx_parameters).await?;
 
         // send tx
         let tx_result = self
-            .web3http
-            .eth()
-            .send_raw_transaction(signed_transaction.raw_transaction)
-            .await;
-        tx_result
+            .send_raw_transaction_tracked(signed_transaction.raw_transaction)
+            .await?;
+        Ok(tx_result)
     }
 
     fn update_nonce(&mut self) {
         self.set_current_nonce(self.get_current_nonce() + 1)
     }
 
-    pub async fn sent_eth(&mut self, account: H160, to: H160, amount: &str) {
-        let amount_out: U256 = U256::from_dec_str(amount).unwrap();
-
-        // Build the tx object
-        let tx_object = TransactionParameters {
-            to: Some(to),
-            value: amount_out, //0.1 eth
-            ..Default::default()
-        };
-
-        let plain_pk = self.accounts_map.get(&account).unwrap();
-        let private_key = SecretKey::from_str(plain_pk).unwrap();
+    // Sends `amount` of the native token with the nonce, gas settings and
+    // signer of `account`, like any contract call
+    // Envía `amount` del token nativo con el nonce, la configuración de gas y
+    // el firmante de `account`, igual que cualquier llamada a un contrato
+    pub async fn sent_eth(
+        &mut self,
+        account: H160,
+        to: H160,
+        amount: Amount,
+    ) -> Result<ReplaceableTransaction, Box<dyn Error>> {
+        let gas = self
+            .native_transfer_gas(account, to, amount.as_wei())
+            .await?;
+        let fees = self.transaction_fees(&self.gas_settings).await?;
+        self.send_native(account, to, amount.as_wei(), gas, fees)
+            .await
+    }
 
-        // Sign the tx (can be done offline)
-        let signed = self
-            .web3http
-            .accounts()
-            .sign_transaction(tx_object, &private_key)
+    // Sends the whole balance of `account` minus the most the transfer can
+    // pay for gas. With EIP-1559 fees the unused part of the max fee stays in
+    // the account
+    // Envía todo el saldo de `account` menos lo máximo que la transferencia
+    // puede pagar de gas
+    pub async fn sent_max_eth(
+        &mut self,
+        account: H160,
+        to: H160,
+    ) -> Result<ReplaceableTransaction, Box<dyn Error>> {
+        let balance = self.web3http.eth().balance(account, None).await?;
+        let gas = self.native_transfer_gas(account, to, U256::zero()).await?;
+        let fees = self.transaction_fees(&self.gas_settings).await?;
+        let max_gas_cost = gas * fees.max_price_per_gas();
+        if balance <= max_gas_cost {
+            return Err(format!(
+                "balance of {} can't pay the {} gas of the transfer",
+                Amount(balance),
+                Amount(max_gas_cost)
+            )
+            .into());
+        }
+        self.send_native(account, to, balance - max_gas_cost, gas, fees)
             .await
-            .unwrap();
+    }
 
-        // Send the tx to infura
-        let result = self
+    // Plain transfers need exactly 21000 gas, contracts receiving the value
+    // get the gas limit margin
+    async fn native_transfer_gas(
+        &self,
+        account: H160,
+        to: H160,
+        value: U256,
+    ) -> Result<U256, Box<dyn Error>> {
+        let estimated_gas = self
             .web3http
             .eth()
-            .send_raw_transaction(signed.raw_transaction)
-            .await
-            .unwrap();
+            .estimate_gas(
+                CallRequest {
+                    from: Some(account),
+                    to: Some(to),
+                    value: Some(value),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await?;
+        if estimated_gas == U256::from(NATIVE_TRANSFER_GAS) {
+            return Ok(estimated_gas);
+        }
+        Ok(self.gas_settings.gas_limit(estimated_gas))
+    }
+
+    async fn send_native(
+        &mut self,
+        account: H160,
+        to: H160,
+        value: U256,
+        gas: U256,
+        fees: TransactionFees,
+    ) -> Result<ReplaceableTransaction, Box<dyn Error>> {
+        let nonce = self.get_current_nonce();
+        let mut tx_parameters = TransactionParameters {
+            nonce: Some(nonce),
+            to: Some(to),
+            value,
+            gas,
+            chain_id: self.chain_id,
+            ..Default::default()
+        };
+        fees.apply(&mut tx_parameters);
+
+        // the nonce is only used when the transfer was sent
+        let tx_hash = self
+            .sign_and_send_transaction(account, tx_parameters)
+            .await?;
+        self.update_nonce();
 
-        println!("Tx succeeded with hash: {}", result);
+        Ok(ReplaceableTransaction {
+            account,
+            nonce,
+            hashes: vec![tx_hash],
+        })
     }
 
     pub async fn sent_erc20_token(
@@ -1099,10 +1397,11 @@ impl Web3Manager {
         network: impl crate::traits::GetAddress,
         pair_address: &str,
     ) -> Int {
-        let proxy_abi = include_bytes!("../abi/EACAggregatorProxy.json");
-        let proxy_instance: Contract<Http> = self
-            .instance_contract(&network.get_address(pair_address).unwrap(), proxy_abi)
-            .await
+        let proxy_instance = self
+            .cached_contract_from_str(
+                &network.get_address(pair_address).unwrap(),
+                AbiKind::AggregatorProxy,
+            )
             .unwrap();
 
         let res: (Uint, Int, Uint, Uint, Uint) = self
@@ -1203,34 +1502,19 @@ impl Web3Manager {
         return sub;
     }
 
-    pub async fn init_pair(&self, lp_address: &str) -> Contract<Http> {
-        let lp_pair_abi = include_bytes!("../abi/PancakeLPTokenAbi.json");
-        let lp_pair_instance_address = lp_address;
-        let lp_pair_instance: Contract<Http> = self
-            .instance_contract(lp_pair_instance_address, lp_pair_abi)
-            .await
-            .expect("error creating the contract instance");
-        lp_pair_instance
+    pub async fn init_pair(&self, lp_address: &str) -> Arc<Contract<Http>> {
+        self.cached_contract_from_str(lp_address, AbiKind::Pair)
+            .expect("error creating the contract instance")
     }
 
-    pub async fn init_router_factory(&mut self, factory_address: &str) -> Contract<Http> {
-        let factory_abi = include_bytes!("../abi/PancakeFactoryAbi.json");
-        let factory_instance: Contract<Http> = self
-            .instance_contract(factory_address, factory_abi)
-            .await
-            .expect("error creating the contract instance");
-        factory_instance
+    pub async fn init_router_factory(&mut self, factory_address: &str) -> Arc<Contract<Http>> {
+        self.cached_contract_from_str(factory_address, AbiKind::Factory)
+            .expect("error creating the contract instance")
     }
 
-    pub async fn init_router(&mut self, router_address: &str) -> Contract<Http> {
-        //let abi: Abi = load_abi_from_json("factoryabi.json");
-        let router_abi = include_bytes!("../abi/PancakeRouterAbi.json");
-
-        let router_instance: Contract<Http> = self
-            .instance_contract(router_address, router_abi)
-            .await
-            .expect("error creating the contract instance");
-        router_instance
+    pub async fn init_router(&mut self, router_address: &str) -> Arc<Contract<Http>> {
+        self.cached_contract_from_str(router_address, AbiKind::Router)
+            .expect("error creating the contract instance")
     }
 
     pub async fn get_factory_address(&mut self, router_instance: &Contract<Http>) -> String {
@@ -1250,9 +1534,9 @@ impl Web3Manager {
         w3h::to_string(&weth_address).replace("\"", "")
     }
 
-    pub async fn token_has_liquidity(&self, lp_pair_factory_instance: Contract<Http>) -> bool {
+    pub async fn token_has_liquidity(&self, lp_pair_factory_instance: &Contract<Http>) -> bool {
         let lp_pair_reserves: (Uint, Uint, Uint) = self
-            .query_contract(&lp_pair_factory_instance, "getReserves", ())
+            .query_contract(lp_pair_factory_instance, "getReserves", ())
             .await
             .unwrap();
         lp_pair_reserves.0 > U256::from(0) && lp_pair_reserves.1 > U256::from(0)
@@ -1264,8 +1548,9 @@ impl Web3Manager {
         factory_address: &str,
         token_address: &str,
     ) -> String {
-        let factory_instance = self.init_router_factory(factory_address).await;
-        let initial_lp_address = "0x0000000000000000000000000000000000000000";
+        let factory_instance = self
+            .cached_contract_from_str(factory_address, AbiKind::Factory)
+            .unwrap();
 
         let lp_pair_address: H160 = self
             .query_contract(
@@ -1279,30 +1564,17 @@ impl Web3Manager {
             .await
             .unwrap();
 
-        let mut lp_token_address = w3h::to_string(&lp_pair_address).replace("\"", "");
-        if lp_token_address == initial_lp_address {
-            let lp_pair_address: H160 = self
-                .query_contract(
-                    &factory_instance,
-                    "getPair",
-                    (
-                        H160::from_str(weth_address).unwrap(),
-                        H160::from_str(token_address).unwrap(),
-                    ),
-                )
-                .await
-                .unwrap();
-            lp_token_address = w3h::to_string(&lp_pair_address).replace("\"", "")
-        }
-        lp_token_address
+        // the zero address means the pair doesn't exist yet, use
+        // `predict_lp_pair` to know its address before it is created
+        w3h::to_string(&lp_pair_address).replace("\"", "")
     }
 
     pub async fn get_token_reserves(
         &mut self,
-        lp_pair_factory_instance: Contract<Http>,
+        lp_pair_factory_instance: &Contract<Http>,
     ) -> (U256, U256, U256) {
         let lp_pair_reserves: (Uint, Uint, Uint) = self
-            .query_contract(&lp_pair_factory_instance, "getReserves", ())
+            .query_contract(lp_pair_factory_instance, "getReserves", ())
             .await
             .unwrap();
         println!("lp_pair_reserves: {:?}", lp_pair_reserves);
@@ -0,0 +1,593 @@
+//! Liquidity provision through the router: add/remove liquidity, expected
+//! LP tokens and underlying amounts, and LP position valuation.
+//!
+//! The router pulls the tokens (or the LP tokens when removing without a
+//! permit) with `transferFrom`, the allowance is checked with
+//! `ensure_allowance` before sending.
+
+use crate::amm;
+use crate::bindings::PancakePair;
+use crate::{AbiKind, Slippage, Web3Manager};
+use std::error::Error;
+use std::str::FromStr;
+use web3::contract::tokens::Tokenize;
+use web3::types::{H160, H256, U256};
+
+/// Signature of an EIP-2612 `permit` of the pair's LP token for the router,
+/// used by the `removeLiquidity*WithPermit` functions. Built by
+/// `sign_liquidity_permit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidityPermit {
+    /// Permit `U256::MAX` instead of the exact liquidity removed.
+    pub approve_max: bool,
+    /// Deadline signed in the permit, it is also the deadline of the removal.
+    pub deadline: U256,
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+/// Amounts `addLiquidity` would deposit and the LP tokens it would mint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddLiquidityQuote {
+    pub pair: H160,
+    pub amount_a: U256,
+    pub amount_b: U256,
+    pub liquidity: U256,
+}
+
+/// LP tokens held by an account and the share of the reserves they redeem.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidityPosition {
+    pub pair: H160,
+    pub token0: H160,
+    pub token1: H160,
+    pub liquidity: U256,
+    pub total_supply: U256,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+impl LiquidityPosition {
+    /// Underlying amount of `token` redeemed by the position.
+    pub fn amount_of(&self, token: H160) -> Option<U256> {
+        if token == self.token0 {
+            Some(self.amount0)
+        } else if token == self.token1 {
+            Some(self.amount1)
+        } else {
+            None
+        }
+    }
+
+    /// Whole position valued in `token` at the pool price (no fee or price
+    /// impact applied to the other side).
+    pub fn value_in(&self, token: H160) -> Option<U256> {
+        if token == self.token0 {
+            Some(self.amount0 + amm::quote(self.amount1, self.reserve1, self.reserve0))
+        } else if token == self.token1 {
+            Some(self.amount1 + amm::quote(self.amount0, self.reserve0, self.reserve1))
+        } else {
+            None
+        }
+    }
+
+    /// Share of the pool owned by the position, in basis points.
+    pub fn share_bps(&self) -> u32 {
+        if self.total_supply.is_zero() {
+            return 0;
+        }
+        (self.liquidity * U256::from(10_000) / self.total_supply).as_u32()
+    }
+}
+
+impl Web3Manager {
+    // Pair of two tokens on the factory of the router, zero if it doesn't exist
+    pub(crate) async fn router_pair(
+        &self,
+        router_address: H160,
+        token_a: H160,
+        token_b: H160,
+    ) -> Result<H160, Box<dyn Error>> {
+        let factory_address = self.router_bindings(router_address).factory().await?;
+        Ok(self
+            .factory_bindings(factory_address)
+            .get_pair(token_a, token_b)
+            .await?)
+    }
+
+    // The router burns LP tokens it pulls from the caller, the pair is the
+    // token to approve
+    async fn ensure_lp_allowance(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+    ) -> Result<(), Box<dyn Error>> {
+        let pair = self
+            .router_pair(
+                H160::from_str(router_address)?,
+                H160::from_str(token_a)?,
+                H160::from_str(token_b)?,
+            )
+            .await?;
+        self.ensure_allowance(account, &format!("{:?}", pair), router_address, liquidity)
+            .await?;
+        Ok(())
+    }
+
+    // (reserve_a, reserve_b, total_supply) of a pair, all zero before it exists
+    async fn pair_state(
+        &self,
+        pair: &PancakePair,
+        token_a: H160,
+    ) -> Result<(U256, U256, U256), Box<dyn Error>> {
+        if pair.address().is_zero() {
+            return Ok((U256::zero(), U256::zero(), U256::zero()));
+        }
+        let reserves = pair.get_reserves().await?;
+        let total_supply = pair.total_supply().await?;
+        if pair.token0().await? == token_a {
+            Ok((reserves.reserve0, reserves.reserve1, total_supply))
+        } else {
+            Ok((reserves.reserve1, reserves.reserve0, total_supply))
+        }
+    }
+
+    /// Amounts deposited and LP tokens minted by adding liquidity, the pair
+    /// may not exist yet.
+    pub async fn quote_add_liquidity(
+        &self,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        amount_a_desired: U256,
+        amount_b_desired: U256,
+    ) -> Result<AddLiquidityQuote, Box<dyn Error>> {
+        let token_a = H160::from_str(token_a)?;
+        let token_b = H160::from_str(token_b)?;
+        let pair_address = self
+            .router_pair(H160::from_str(router_address)?, token_a, token_b)
+            .await?;
+        let pair = self.pair_bindings(pair_address);
+        let (reserve_a, reserve_b, total_supply) = self.pair_state(&pair, token_a).await?;
+
+        let (amount_a, amount_b) = amm::optimal_liquidity_amounts(
+            amount_a_desired,
+            amount_b_desired,
+            reserve_a,
+            reserve_b,
+        )
+        .ok_or("desired amounts don't match the pool price")?;
+
+        Ok(AddLiquidityQuote {
+            pair: pair_address,
+            amount_a,
+            amount_b,
+            liquidity: amm::liquidity_minted(
+                amount_a,
+                amount_b,
+                reserve_a,
+                reserve_b,
+                total_supply,
+            ),
+        })
+    }
+
+    /// `(amount_a, amount_b)` returned by removing `liquidity` LP tokens.
+    pub async fn quote_remove_liquidity(
+        &self,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+    ) -> Result<(U256, U256), Box<dyn Error>> {
+        let token_a = H160::from_str(token_a)?;
+        let token_b = H160::from_str(token_b)?;
+        let pair_address = self
+            .router_pair(H160::from_str(router_address)?, token_a, token_b)
+            .await?;
+        if pair_address.is_zero() {
+            return Err("pair doesn't exist".into());
+        }
+        let pair = self.pair_bindings(pair_address);
+        let (reserve_a, reserve_b, total_supply) = self.pair_state(&pair, token_a).await?;
+
+        Ok(amm::liquidity_underlying(
+            liquidity,
+            reserve_a,
+            reserve_b,
+            total_supply,
+        ))
+    }
+
+    pub async fn get_liquidity_position(
+        &self,
+        account: H160,
+        pair_address: &str,
+    ) -> Result<LiquidityPosition, Box<dyn Error>> {
+        let pair_address = H160::from_str(pair_address)?;
+        let pair = self.pair_bindings(pair_address);
+
+        let liquidity = pair.balance_of(account).await?;
+        let total_supply = pair.total_supply().await?;
+        let reserves = pair.get_reserves().await?;
+        let (amount0, amount1) = amm::liquidity_underlying(
+            liquidity,
+            reserves.reserve0,
+            reserves.reserve1,
+            total_supply,
+        );
+
+        Ok(LiquidityPosition {
+            pair: pair_address,
+            token0: pair.token0().await?,
+            token1: pair.token1().await?,
+            liquidity,
+            total_supply,
+            reserve0: reserves.reserve0,
+            reserve1: reserves.reserve1,
+            amount0,
+            amount1,
+        })
+    }
+
+    /// Adds liquidity to the `token_a`/`token_b` pair, creating it if needed.
+    /// `slippage` applies to the amounts the router will deposit.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_liquidity(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        amount_a_desired: U256,
+        amount_b_desired: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        let quote = self
+            .quote_add_liquidity(
+                router_address,
+                token_a,
+                token_b,
+                amount_a_desired,
+                amount_b_desired,
+            )
+            .await?;
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        self.ensure_allowance(account, token_a, router_address, amount_a_desired)
+            .await?;
+        self.ensure_allowance(account, token_b, router_address, amount_b_desired)
+            .await?;
+
+        let parameters = (
+            H160::from_str(token_a)?,
+            H160::from_str(token_b)?,
+            amount_a_desired,
+            amount_b_desired,
+            slippage.min_out(quote.amount_a),
+            slippage.min_out(quote.amount_b),
+            account,
+            self.chain_deadline().await?,
+        );
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            "addLiquidity",
+            &parameters,
+            U256::zero(),
+        )
+        .await
+    }
+
+    /// Adds liquidity to the `token`/WETH pair sending `eth_amount`, the
+    /// router refunds the ETH that isn't needed.
+    pub async fn add_liquidity_eth(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        amount_token_desired: U256,
+        eth_amount: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        let weth_address = self.get_weth_address(&router_instance).await;
+        let quote = self
+            .quote_add_liquidity(
+                router_address,
+                token_address,
+                weth_address.as_str(),
+                amount_token_desired,
+                eth_amount,
+            )
+            .await?;
+        self.ensure_allowance(account, token_address, router_address, amount_token_desired)
+            .await?;
+
+        let parameters = (
+            H160::from_str(token_address)?,
+            amount_token_desired,
+            slippage.min_out(quote.amount_a),
+            slippage.min_out(quote.amount_b),
+            account,
+            self.chain_deadline().await?,
+        );
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            "addLiquidityETH",
+            &parameters,
+            eth_amount,
+        )
+        .await
+    }
+
+    pub async fn remove_liquidity(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        let (amount_a, amount_b) = self
+            .quote_remove_liquidity(router_address, token_a, token_b, liquidity)
+            .await?;
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        self.ensure_lp_allowance(account, router_address, token_a, token_b, liquidity)
+            .await?;
+
+        let parameters = (
+            H160::from_str(token_a)?,
+            H160::from_str(token_b)?,
+            liquidity,
+            slippage.min_out(amount_a),
+            slippage.min_out(amount_b),
+            account,
+            self.chain_deadline().await?,
+        );
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            "removeLiquidity",
+            &parameters,
+            U256::zero(),
+        )
+        .await
+    }
+
+    /// Removes liquidity from the `token_a`/`token_b` pair with a signed
+    /// permit instead of a prior LP token approval.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn remove_liquidity_with_permit(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+        slippage: Slippage,
+        permit: &LiquidityPermit,
+    ) -> Result<H256, Box<dyn Error>> {
+        let (amount_a, amount_b) = self
+            .quote_remove_liquidity(router_address, token_a, token_b, liquidity)
+            .await?;
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+
+        let parameters = (
+            H160::from_str(token_a)?,
+            H160::from_str(token_b)?,
+            liquidity,
+            slippage.min_out(amount_a),
+            slippage.min_out(amount_b),
+            account,
+            permit.deadline,
+            permit.approve_max,
+            permit.v,
+            permit.r,
+            permit.s,
+        );
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            "removeLiquidityWithPermit",
+            &parameters,
+            U256::zero(),
+        )
+        .await
+    }
+
+    pub async fn remove_liquidity_eth(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        self.remove_liquidity_eth_with(
+            account,
+            router_address,
+            token_address,
+            liquidity,
+            slippage,
+            "removeLiquidityETH",
+            None,
+        )
+        .await
+    }
+
+    /// Same as `remove_liquidity_eth` for tokens that take a fee on transfer,
+    /// the router forwards its whole token balance instead of the amount
+    /// burned.
+    pub async fn remove_liquidity_eth_supporting_fee_on_transfer_tokens(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        self.remove_liquidity_eth_with(
+            account,
+            router_address,
+            token_address,
+            liquidity,
+            slippage,
+            "removeLiquidityETHSupportingFeeOnTransferTokens",
+            None,
+        )
+        .await
+    }
+
+    pub async fn remove_liquidity_eth_with_permit(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+        permit: &LiquidityPermit,
+    ) -> Result<H256, Box<dyn Error>> {
+        self.remove_liquidity_eth_with(
+            account,
+            router_address,
+            token_address,
+            liquidity,
+            slippage,
+            "removeLiquidityETHWithPermit",
+            Some(permit),
+        )
+        .await
+    }
+
+    pub async fn remove_liquidity_eth_with_permit_supporting_fee_on_transfer_tokens(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+        permit: &LiquidityPermit,
+    ) -> Result<H256, Box<dyn Error>> {
+        self.remove_liquidity_eth_with(
+            account,
+            router_address,
+            token_address,
+            liquidity,
+            slippage,
+            "removeLiquidityETHWithPermitSupportingFeeOnTransferTokens",
+            Some(permit),
+        )
+        .await
+    }
+
+    // The removeLiquidityETH* functions share their leading parameters, the
+    // permit variants append (approveMax, v, r, s)
+    #[allow(clippy::too_many_arguments)]
+    async fn remove_liquidity_eth_with(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+        contract_function: &str,
+        permit: Option<&LiquidityPermit>,
+    ) -> Result<H256, Box<dyn Error>> {
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        let weth_address = self.get_weth_address(&router_instance).await;
+        let (amount_token, amount_eth) = self
+            .quote_remove_liquidity(
+                router_address,
+                token_address,
+                weth_address.as_str(),
+                liquidity,
+            )
+            .await?;
+        if permit.is_none() {
+            self.ensure_lp_allowance(
+                account,
+                router_address,
+                token_address,
+                weth_address.as_str(),
+                liquidity,
+            )
+            .await?;
+        }
+
+        let token_address = H160::from_str(token_address)?;
+        let amount_token_min = slippage.min_out(amount_token);
+        let amount_eth_min = slippage.min_out(amount_eth);
+
+        let parameters = match permit {
+            Some(permit) => (
+                token_address,
+                liquidity,
+                amount_token_min,
+                amount_eth_min,
+                account,
+                permit.deadline,
+                permit.approve_max,
+                permit.v,
+                permit.r,
+                permit.s,
+            )
+                .into_tokens(),
+            None => (
+                token_address,
+                liquidity,
+                amount_token_min,
+                amount_eth_min,
+                account,
+                self.chain_deadline().await?,
+            )
+                .into_tokens(),
+        };
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            contract_function,
+            &parameters.as_slice(),
+            U256::zero(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create2::sort_tokens;
+
+    fn position() -> LiquidityPosition {
+        let (token0, token1) = sort_tokens(H160::repeat_byte(1), H160::repeat_byte(2));
+        LiquidityPosition {
+            pair: H160::repeat_byte(3),
+            token0,
+            token1,
+            liquidity: U256::from(100),
+            total_supply: U256::from(1000),
+            reserve0: U256::from(5000),
+            reserve1: U256::from(20000),
+            amount0: U256::from(500),
+            amount1: U256::from(2000),
+        }
+    }
+
+    #[test]
+    fn values_position_in_either_token() {
+        let position = position();
+        assert_eq!(position.value_in(position.token0), Some(U256::from(1000)));
+        assert_eq!(position.value_in(position.token1), Some(U256::from(4000)));
+        assert_eq!(position.value_in(H160::zero()), None);
+        assert_eq!(position.amount_of(position.token1), Some(U256::from(2000)));
+        assert_eq!(position.share_bps(), 1000);
+    }
+}
@@ -0,0 +1,514 @@
+//! Off-chain signatures with the keys of the loaded accounts: EIP-191
+//! `personal_sign` messages and EIP-712 typed data given as the JSON
+//! document wallets use (`types`, `primaryType`, `domain`, `message`).
+//!
+//! ```ignore
+//! let signature = web3m.sign_message(account, b"hello").await?;
+//! assert!(verify_message(b"hello", &signature, account));
+//! let compact = signature.to_compact();
+//! ```
+
+use crate::dynamic_abi::{json_to_int, json_to_uint};
+use crate::Web3Manager;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use web3::ethabi::{self, Token};
+use web3::signing::{hash_message, keccak256, recover};
+use web3::types::{H160, H256};
+
+/// ECDSA signature with its recovery id, `v` is kept as 27 or 28.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RecoverableSignature {
+    pub r: H256,
+    pub s: H256,
+    pub v: u8,
+}
+
+impl RecoverableSignature {
+    /// 0 or 1
+    pub fn y_parity(&self) -> u8 {
+        self.v - 27
+    }
+
+    /// `r ‖ s ‖ v`, the format returned by `personal_sign` and `eth_signTypedData`.
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[..32].copy_from_slice(self.r.as_bytes());
+        bytes[32..64].copy_from_slice(self.s.as_bytes());
+        bytes[64] = self.v;
+        bytes
+    }
+
+    /// EIP-2098 `r ‖ yParityAndS`, the parity is stored in the top bit of `s`.
+    pub fn to_compact(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(self.r.as_bytes());
+        bytes[32..].copy_from_slice(self.s.as_bytes());
+        bytes[32] |= self.y_parity() << 7;
+        bytes
+    }
+
+    /// Parses the 65 bytes format (`v` as 0/1 or 27/28) or the 64 bytes
+    /// compact one.
+    pub fn from_bytes(bytes: &[u8]) -> Result<RecoverableSignature, Box<dyn Error>> {
+        match bytes.len() {
+            65 => {
+                let v = match bytes[64] {
+                    0 | 1 => bytes[64] + 27,
+                    27 | 28 => bytes[64],
+                    v => return Err(format!("invalid signature v {}", v).into()),
+                };
+                Ok(RecoverableSignature {
+                    r: H256::from_slice(&bytes[..32]),
+                    s: H256::from_slice(&bytes[32..64]),
+                    v,
+                })
+            }
+            64 => {
+                let mut s = H256::from_slice(&bytes[32..]);
+                let y_parity = s.0[0] >> 7;
+                s.0[0] &= 0x7f;
+                Ok(RecoverableSignature {
+                    r: H256::from_slice(&bytes[..32]),
+                    s,
+                    v: y_parity + 27,
+                })
+            }
+            len => Err(format!("signature must be 64 or 65 bytes, got {}", len).into()),
+        }
+    }
+}
+
+impl From<web3::signing::Signature> for RecoverableSignature {
+    fn from(signature: web3::signing::Signature) -> RecoverableSignature {
+        RecoverableSignature {
+            r: signature.r,
+            s: signature.s,
+            v: signature.v as u8,
+        }
+    }
+}
+
+impl fmt::Display for RecoverableSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.to_bytes()))
+    }
+}
+
+impl FromStr for RecoverableSignature {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<RecoverableSignature, Box<dyn Error>> {
+        RecoverableSignature::from_bytes(&hex::decode(s.trim_start_matches("0x"))?)
+    }
+}
+
+/// Address that signed `digest`.
+pub fn recover_digest(
+    digest: H256,
+    signature: &RecoverableSignature,
+) -> Result<H160, Box<dyn Error>> {
+    let bytes = signature.to_bytes();
+    Ok(recover(
+        digest.as_bytes(),
+        &bytes[..64],
+        signature.y_parity() as i32,
+    )?)
+}
+
+/// Address that signed `message` with `personal_sign`.
+pub fn recover_message(
+    message: &[u8],
+    signature: &RecoverableSignature,
+) -> Result<H160, Box<dyn Error>> {
+    recover_digest(hash_message(message), signature)
+}
+
+pub fn verify_message(message: &[u8], signature: &RecoverableSignature, address: H160) -> bool {
+    recover_message(message, signature).ok() == Some(address)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypedDataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// EIP-712 typed data document, as passed to `eth_signTypedData_v4`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    pub primary_type: String,
+    pub domain: Value,
+    pub message: Value,
+}
+
+// Field order of `EIP712Domain` when the document doesn't declare it
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+impl TypedData {
+    pub fn from_json(json: &str) -> Result<TypedData, Box<dyn Error>> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn domain_separator(&self) -> Result<H256, Box<dyn Error>> {
+        self.hash_struct("EIP712Domain", &self.domain)
+    }
+
+    /// Digest signed by `eth_signTypedData_v4`.
+    pub fn signing_hash(&self) -> Result<H256, Box<dyn Error>> {
+        let mut message = Vec::with_capacity(66);
+        message.extend_from_slice(b"\x19\x01");
+        message.extend_from_slice(self.domain_separator()?.as_bytes());
+        message.extend_from_slice(
+            self.hash_struct(&self.primary_type, &self.message)?
+                .as_bytes(),
+        );
+        Ok(H256(keccak256(&message)))
+    }
+
+    fn fields(&self, struct_type: &str) -> Option<Vec<TypedDataField>> {
+        if let Some(fields) = self.types.get(struct_type) {
+            return Some(fields.clone());
+        }
+        if struct_type != "EIP712Domain" {
+            return None;
+        }
+        let domain = self.domain.as_object()?;
+        Some(
+            DOMAIN_FIELDS
+                .iter()
+                .filter(|(name, _)| domain.contains_key(*name))
+                .map(|(name, kind)| TypedDataField {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    /// `Mail(Person from,Person to,string contents)Person(string name,address wallet)`
+    pub fn encode_type(&self, struct_type: &str) -> Result<String, Box<dyn Error>> {
+        let mut dependencies = BTreeSet::new();
+        self.collect_dependencies(struct_type, &mut dependencies)?;
+        dependencies.remove(struct_type);
+
+        let mut encoded = String::new();
+        for name in std::iter::once(struct_type).chain(dependencies.iter().map(String::as_str)) {
+            let fields = self.fields(name).unwrap_or_default();
+            let members: Vec<String> = fields
+                .iter()
+                .map(|field| format!("{} {}", field.kind, field.name))
+                .collect();
+            encoded.push_str(&format!("{}({})", name, members.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn collect_dependencies(
+        &self,
+        struct_type: &str,
+        found: &mut BTreeSet<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        if found.contains(struct_type) {
+            return Ok(());
+        }
+        let fields = self
+            .fields(struct_type)
+            .ok_or_else(|| format!("unknown type `{}`", struct_type))?;
+        found.insert(struct_type.to_string());
+        for field in fields {
+            let base = field.kind.split('[').next().unwrap_or_default();
+            if self.fields(base).is_some() {
+                self.collect_dependencies(base, found)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn hash_struct(&self, struct_type: &str, value: &Value) -> Result<H256, Box<dyn Error>> {
+        let fields = self
+            .fields(struct_type)
+            .ok_or_else(|| format!("unknown type `{}`", struct_type))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("`{}` value must be an object", struct_type))?;
+
+        let mut encoded = keccak256(self.encode_type(struct_type)?.as_bytes()).to_vec();
+        for field in fields {
+            let field_value = object.get(&field.name).unwrap_or(&Value::Null);
+            encoded.extend_from_slice(&self.encode_value(&field.kind, field_value)?);
+        }
+        Ok(H256(keccak256(&encoded)))
+    }
+
+    // 32 bytes word of a member, dynamic and nested values are hashed
+    fn encode_value(&self, kind: &str, value: &Value) -> Result<[u8; 32], Box<dyn Error>> {
+        if let Some(element_kind) = kind.strip_suffix(']') {
+            let element_kind = &element_kind[..element_kind
+                .rfind('[')
+                .ok_or_else(|| format!("invalid type `{}`", kind))?];
+            let elements = value
+                .as_array()
+                .ok_or_else(|| format!("`{}` value must be an array", kind))?;
+            let mut encoded = Vec::with_capacity(elements.len() * 32);
+            for element in elements {
+                encoded.extend_from_slice(&self.encode_value(element_kind, element)?);
+            }
+            return Ok(keccak256(&encoded));
+        }
+        if self.fields(kind).is_some() {
+            return Ok(self.hash_struct(kind, value)?.0);
+        }
+
+        let token = match kind {
+            "string" => {
+                let text = value
+                    .as_str()
+                    .ok_or_else(|| format!("expected string, got {}", value))?;
+                return Ok(keccak256(text.as_bytes()));
+            }
+            "bytes" => return Ok(keccak256(&json_bytes(value)?)),
+            "bool" => Token::Bool(
+                value
+                    .as_bool()
+                    .ok_or_else(|| format!("expected bool, got {}", value))?,
+            ),
+            "address" => Token::Address(H160::from_str(
+                value
+                    .as_str()
+                    .ok_or_else(|| format!("expected address, got {}", value))?,
+            )?),
+            _ if kind.starts_with("uint") => {
+                Token::Uint(json_to_uint(value, int_size(kind, "uint")?)?)
+            }
+            _ if kind.starts_with("int") => Token::Int(json_to_int(value, int_size(kind, "int")?)?),
+            _ if kind.starts_with("bytes") => {
+                let mut word = json_bytes(value)?;
+                if word.len() > 32 {
+                    return Err(format!("`{}` value is longer than 32 bytes", kind).into());
+                }
+                word.resize(32, 0);
+                Token::FixedBytes(word)
+            }
+            _ => return Err(format!("unknown type `{}`", kind).into()),
+        };
+        let mut word = [0u8; 32];
+        word.copy_from_slice(&ethabi::encode(&[token]));
+        Ok(word)
+    }
+}
+
+fn json_bytes(value: &Value) -> Result<Vec<u8>, Box<dyn Error>> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| format!("expected hex bytes, got {}", value))?;
+    Ok(hex::decode(text.trim_start_matches("0x"))?)
+}
+
+// `uint`/`int` without a size are 256 bits
+fn int_size(kind: &str, prefix: &str) -> Result<usize, Box<dyn Error>> {
+    match &kind[prefix.len()..] {
+        "" => Ok(256),
+        size => size
+            .parse()
+            .map_err(|_| format!("unknown type `{}`", kind).into()),
+    }
+}
+
+impl Web3Manager {
+    /// EIP-191 `personal_sign` of `message` by `account`.
+    pub async fn sign_message(
+        &self,
+        account: H160,
+        message: &[u8],
+    ) -> Result<RecoverableSignature, Box<dyn Error>> {
+        Ok(self.signer(account)?.sign_message(message).await?)
+    }
+
+    /// EIP-712 signature of a JSON typed data document by `account`.
+    pub async fn sign_typed_data(
+        &self,
+        account: H160,
+        typed_data_json: &str,
+    ) -> Result<RecoverableSignature, Box<dyn Error>> {
+        let typed_data = TypedData::from_json(typed_data_json)?;
+        Ok(self.signer(account)?.sign_typed_data(&typed_data).await?)
+    }
+
+    /// Address that signed the JSON typed data document.
+    pub fn recover_typed_data(
+        typed_data_json: &str,
+        signature: &RecoverableSignature,
+    ) -> Result<H160, Box<dyn Error>> {
+        recover_digest(
+            TypedData::from_json(typed_data_json)?.signing_hash()?,
+            signature,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
+    use web3::signing::Key;
+    use web3::types::U256;
+
+    // Example of the EIP-712 specification
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    fn cow_key() -> SecretKey {
+        SecretKey::from_slice(&keccak256(b"cow")).unwrap()
+    }
+
+    #[test]
+    fn hashes_typed_data() {
+        let typed_data = TypedData::from_json(MAIL).unwrap();
+        assert_eq!(
+            typed_data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            typed_data.domain_separator().unwrap(),
+            H256::from_str("0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f")
+                .unwrap()
+        );
+        assert_eq!(
+            typed_data.signing_hash().unwrap(),
+            H256::from_str("0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn signs_typed_data_like_the_specification() {
+        let digest = TypedData::from_json(MAIL).unwrap().signing_hash().unwrap();
+        let signature: RecoverableSignature =
+            (&cow_key()).sign(digest.as_bytes(), None).unwrap().into();
+        assert_eq!(signature.v, 28);
+        assert_eq!(
+            signature.r,
+            H256::from_str("0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d")
+                .unwrap()
+        );
+        assert_eq!(
+            signature.s,
+            H256::from_str("0x07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562")
+                .unwrap()
+        );
+        assert_eq!(
+            Web3Manager::recover_typed_data(MAIL, &signature).unwrap(),
+            H160::from_str("0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826").unwrap()
+        );
+    }
+
+    #[test]
+    fn recovers_personal_messages() {
+        let key = cow_key();
+        let signature: RecoverableSignature = (&key)
+            .sign(hash_message(b"hello").as_bytes(), None)
+            .unwrap()
+            .into();
+        assert!(verify_message(b"hello", &signature, (&key).address()));
+        assert!(!verify_message(b"hell0", &signature, (&key).address()));
+    }
+
+    #[test]
+    fn converts_signature_formats() {
+        let signature = RecoverableSignature {
+            r: H256::repeat_byte(0x11),
+            s: H256::repeat_byte(0x22),
+            v: 28,
+        };
+        let compact = signature.to_compact();
+        assert_eq!(compact[32], 0xa2);
+        assert_eq!(
+            RecoverableSignature::from_bytes(&compact).unwrap(),
+            signature
+        );
+        assert_eq!(
+            RecoverableSignature::from_str(&signature.to_string()).unwrap(),
+            signature
+        );
+
+        let mut raw = signature.to_bytes();
+        raw[64] = 1;
+        assert_eq!(RecoverableSignature::from_bytes(&raw).unwrap(), signature);
+        raw[64] = 5;
+        assert!(RecoverableSignature::from_bytes(&raw).is_err());
+        assert!(RecoverableSignature::from_bytes(&raw[..10]).is_err());
+    }
+
+    #[test]
+    fn encodes_signed_integers_and_arrays() {
+        let typed_data = TypedData::from_json(
+            r#"{"types": {"Group": [{"name": "ids", "type": "uint8[]"}]},
+                "primaryType": "Group", "domain": {"name": "test"}, "message": {"ids": [1, 2]}}"#,
+        )
+        .unwrap();
+        let word = |kind: &str, value: Value| typed_data.encode_value(kind, &value);
+        assert_eq!(word("int", serde_json::json!(-1)).unwrap(), [0xff; 32]);
+        assert_eq!(
+            U256::from(word("int16", serde_json::json!("-2")).unwrap()),
+            U256::MAX - 1
+        );
+        assert_eq!(
+            U256::from(word("uint256", serde_json::json!("0x10")).unwrap()),
+            U256::from(16)
+        );
+        // the same size checks as the dynamic ABI values
+        assert!(word("uint8", serde_json::json!(256)).is_err());
+        assert!(word("int8", serde_json::json!(-129)).is_err());
+        assert!(word("uint7", serde_json::json!(1)).is_err());
+
+        // the domain type is derived from the domain fields when missing
+        assert_eq!(
+            typed_data.encode_type("EIP712Domain").unwrap(),
+            "EIP712Domain(string name)"
+        );
+        assert!(typed_data.signing_hash().is_ok());
+    }
+}
@@ -0,0 +1,129 @@
+//! A local JSON-RPC node for the tests: answers HTTP requests with a closure
+//! and accepts the WebSocket connection of the manager, so a `Web3Manager`
+//! can be built without a network.
+
+use crate::Web3Manager;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub type Requests = Arc<Mutex<Vec<Value>>>;
+
+/// Serves JSON-RPC over HTTP, `respond` gets the method and the params and
+/// returns the result or the message of a JSON-RPC error.
+pub async fn mock_rpc_server(
+    respond: impl Fn(&str, &Value) -> Result<Value, String> + Send + 'static,
+) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request: Value = match read_request(&mut socket).await {
+                Some((_, body)) => serde_json::from_slice(&body).unwrap(),
+                None => continue,
+            };
+            let response = match respond(request["method"].as_str().unwrap(), &request["params"]) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                Err(message) => json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": { "code": -32000, "message": message },
+                }),
+            }
+            .to_string();
+            received.lock().unwrap().push(request);
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+        }
+    });
+    (url, requests)
+}
+
+/// Accepts WebSocket connections and keeps them open without answering.
+async fn idle_websocket_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let headers = match read_request(&mut socket).await {
+                Some((headers, _)) => headers,
+                None => continue,
+            };
+            let key = header(&headers, "sec-websocket-key").unwrap_or_default();
+            let mut hasher = Sha1::new();
+            hasher.update(key.as_bytes());
+            hasher.update(WEBSOCKET_GUID.as_bytes());
+            let reply = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                base64::encode(hasher.finalize())
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+            tokio::spawn(async move {
+                let mut chunk = [0u8; 4096];
+                while let Ok(read) = socket.read(&mut chunk).await {
+                    if read == 0 {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    url
+}
+
+/// A manager on chain 56 connected to a mock node answering with `respond`.
+pub async fn mock_manager(
+    respond: impl Fn(&str, &Value) -> Result<Value, String> + Send + 'static,
+) -> (Web3Manager, Requests) {
+    let (http_url, requests) = mock_rpc_server(respond).await;
+    let websocket_url = idle_websocket_server().await;
+    let manager = Web3Manager::new_from_rpc_url(&http_url, &websocket_url, 56).await;
+    (manager, requests)
+}
+
+/// Reads the headers and the whole body announced by content-length.
+async fn read_request(socket: &mut TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        let text = String::from_utf8_lossy(&buffer).to_string();
+        if let Some(header_end) = text.find("\r\n\r\n") {
+            let headers = text[..header_end].to_string();
+            let length = header(&headers, "content-length")
+                .map(|value| value.parse::<usize>().unwrap())
+                .unwrap_or(0);
+            if buffer.len() >= header_end + 4 + length {
+                let body = buffer[header_end + 4..header_end + 4 + length].to_vec();
+                return Some((headers, body));
+            }
+        }
+    }
+}
+
+fn header(headers: &str, name: &str) -> Option<String> {
+    headers.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
@@ -0,0 +1,762 @@
+//! ERC20 airdrops / multisends from a recipient CSV with a state file that
+//! makes them resumable.
+//!
+//! ```ignore
+//! let recipients = RecipientList::from_csv(File::open("out.csv")?, 0)?;
+//! let state = web3m
+//!     .multisend(account, token, &recipients, &MultisendOptions::default(), "state.json")
+//!     .await?;
+//! ```
+//!
+//! The CSV has a header and `address,amount` rows, amounts are decimal
+//! strings in units of `decimals` (`0` for raw token units). Rows are
+//! validated all at once, mixed case addresses must have a valid EIP-55
+//! checksum and repeated recipients are merged into one row.
+//!
+//! Every transaction is signed and written to the state file with its nonce
+//! and raw bytes *before* it is broadcast. After a crash `multisend` with the
+//! same state file settles the rows that were in flight (waits for them, or
+//! rebroadcasts the same signed transaction when the node lost it) and only
+//! then sends the rows never signed, so a recipient is never paid twice. A
+//! row whose nonce was taken by another transaction is marked failed and
+//! left for a manual check.
+
+use crate::amount::Amount;
+use crate::contract_cache::AbiKind;
+use crate::gas::GasSettings;
+use crate::{Web3Manager, RECEIPT_POLL_INTERVAL};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use web3::signing::keccak256;
+use web3::types::{BlockNumber, Bytes, TransactionId, H160, H256, U256, U64};
+
+/// Disperse (disperse.app), deployed at the same address on most chains.
+pub const DISPERSE_ADDRESS: &str = "0xD152f549545093347A162Dce210e7293f1452150";
+
+/// EIP-55 mixed case form of `address`.
+pub fn to_checksum_address(address: &H160) -> String {
+    let lower = hex::encode(address.as_bytes());
+    let hash = keccak256(lower.as_bytes());
+    let mut checksummed = String::from("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        if nibble >= 8 {
+            checksummed.extend(c.to_uppercase());
+        } else {
+            checksummed.push(c);
+        }
+    }
+    checksummed
+}
+
+/// Parses an address, checking the EIP-55 checksum when it is mixed case.
+pub fn parse_checksummed_address(address: &str) -> Result<H160, Box<dyn Error>> {
+    let address = address.trim();
+    let digits = address
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("address {} has no 0x prefix", address))?;
+    if digits.len() != 40 {
+        return Err(format!("address {} isn't 20 bytes", address).into());
+    }
+    let parsed = H160::from_str(digits)?;
+    let mixed_case = digits.chars().any(|c| c.is_ascii_lowercase())
+        && digits.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && to_checksum_address(&parsed) != address {
+        return Err(format!("address {} has an invalid checksum", address).into());
+    }
+    Ok(parsed)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipient {
+    pub address: H160,
+    pub amount: U256,
+}
+
+/// Validated recipients of a multisend, in the order of the CSV.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecipientList {
+    pub recipients: Vec<Recipient>,
+    /// Addresses that appeared in several rows, their amounts were added.
+    pub merged: Vec<H160>,
+}
+
+impl RecipientList {
+    /// Reads and validates a recipient CSV. Every invalid row is reported in
+    /// the error, with its line number.
+    pub fn from_csv<R: Read>(reader: R, decimals: u8) -> Result<RecipientList, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        for (i, record) in reader.records().enumerate() {
+            // the header is line 1
+            let line = i + 2;
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    errors.push(format!("line {}: {}", line, err));
+                    continue;
+                }
+            };
+            match parse_row(&record, decimals) {
+                Ok(recipient) => rows.push(recipient),
+                Err(err) => errors.push(format!("line {}: {}", line, err)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(format!("invalid recipients:\n{}", errors.join("\n")).into());
+        }
+        RecipientList::new(rows)
+    }
+
+    /// Merges repeated addresses, keeping the position of the first one.
+    pub fn new(rows: Vec<Recipient>) -> Result<RecipientList, Box<dyn Error>> {
+        let mut list = RecipientList::default();
+        let mut positions: HashMap<H160, usize> = HashMap::new();
+        for row in rows {
+            match positions.get(&row.address) {
+                Some(&position) => {
+                    let recipient = &mut list.recipients[position];
+                    recipient.amount = recipient
+                        .amount
+                        .checked_add(row.amount)
+                        .ok_or_else(|| format!("total of {:?} overflows", row.address))?;
+                    if !list.merged.contains(&row.address) {
+                        list.merged.push(row.address);
+                    }
+                }
+                None => {
+                    positions.insert(row.address, list.recipients.len());
+                    list.recipients.push(row);
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn total(&self) -> U256 {
+        self.recipients
+            .iter()
+            .fold(U256::zero(), |total, recipient| total + recipient.amount)
+    }
+}
+
+fn parse_row(record: &csv::StringRecord, decimals: u8) -> Result<Recipient, Box<dyn Error>> {
+    if record.len() < 2 {
+        return Err("expected an address and an amount".into());
+    }
+    let address = parse_checksummed_address(&record[0])?;
+    if address.is_zero() {
+        return Err("the zero address can't receive tokens".into());
+    }
+    let amount = Amount::from_units(&record[1], decimals)?.as_wei();
+    if amount.is_zero() {
+        return Err("amount is zero".into());
+    }
+    Ok(Recipient { address, amount })
+}
+
+/// How the transfers are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultisendMode {
+    /// One `transfer` per recipient.
+    Sequential,
+    /// One `disperseToken` call per batch on a Disperse contract, approved
+    /// for the whole remaining amount first.
+    Disperse(H160),
+}
+
+#[derive(Clone, Debug)]
+pub struct MultisendOptions {
+    pub mode: MultisendMode,
+    /// Recipients per `disperseToken` call, or transfers sent before waiting
+    /// for their receipts.
+    pub batch_size: usize,
+    pub receipt_timeout: Duration,
+}
+
+impl Default for MultisendOptions {
+    fn default() -> MultisendOptions {
+        MultisendOptions {
+            mode: MultisendMode::Sequential,
+            batch_size: 20,
+            receipt_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum RowStatus {
+    Pending,
+    /// Signed and saved, maybe broadcast.
+    #[serde(rename_all = "camelCase")]
+    Sent {
+        transaction_hash: H256,
+        nonce: U256,
+        raw_transaction: Bytes,
+    },
+    #[serde(rename_all = "camelCase")]
+    Confirmed {
+        transaction_hash: H256,
+    },
+    #[serde(rename_all = "camelCase")]
+    Reverted {
+        transaction_hash: H256,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisendRow {
+    pub recipient: Recipient,
+    #[serde(flatten)]
+    pub status: RowStatus,
+}
+
+/// Progress of a multisend, saved after every change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultisendState {
+    pub token: H160,
+    pub sender: H160,
+    pub rows: Vec<MultisendRow>,
+}
+
+impl MultisendState {
+    pub fn new(token: H160, sender: H160, recipients: &RecipientList) -> MultisendState {
+        MultisendState {
+            token,
+            sender,
+            rows: recipients
+                .recipients
+                .iter()
+                .map(|recipient| MultisendRow {
+                    recipient: recipient.clone(),
+                    status: RowStatus::Pending,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MultisendState, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes a temporary file and renames it, a crash never leaves a
+    /// truncated state.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Whether the state was created for this multisend.
+    pub fn matches(&self, token: H160, sender: H160, recipients: &RecipientList) -> bool {
+        self.token == token
+            && self.sender == sender
+            && self
+                .rows
+                .iter()
+                .map(|row| &row.recipient)
+                .eq(recipients.recipients.iter())
+    }
+
+    pub fn pending(&self) -> Vec<usize> {
+        self.rows_where(|status| *status == RowStatus::Pending)
+    }
+
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.rows_where(|status| matches!(status, RowStatus::Sent { .. }))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.rows
+            .iter()
+            .all(|row| matches!(row.status, RowStatus::Confirmed { .. }))
+    }
+
+    /// Tokens of the confirmed rows.
+    pub fn confirmed_amount(&self) -> U256 {
+        self.rows
+            .iter()
+            .filter(|row| matches!(row.status, RowStatus::Confirmed { .. }))
+            .fold(U256::zero(), |total, row| total + row.recipient.amount)
+    }
+
+    /// Sends the reverted and failed rows again on the next run. Only for
+    /// rows checked by hand, a failed row may have been paid.
+    pub fn retry_failed(&mut self) {
+        for row in &mut self.rows {
+            if matches!(
+                row.status,
+                RowStatus::Reverted { .. } | RowStatus::Failed { .. }
+            ) {
+                row.status = RowStatus::Pending;
+            }
+        }
+    }
+
+    fn rows_where(&self, filter: impl Fn(&RowStatus) -> bool) -> Vec<usize> {
+        (0..self.rows.len())
+            .filter(|&i| filter(&self.rows[i].status))
+            .collect()
+    }
+
+    fn set_status(&mut self, rows: &[usize], status: RowStatus) {
+        for &i in rows {
+            self.rows[i].status = status.clone();
+        }
+    }
+
+    // Rows in flight grouped by transaction, in nonce order
+    fn sent_transactions(&self) -> Vec<(H256, U256, Bytes, Vec<usize>)> {
+        let mut transactions: Vec<(H256, U256, Bytes, Vec<usize>)> = Vec::new();
+        for i in self.in_flight() {
+            if let RowStatus::Sent {
+                transaction_hash,
+                nonce,
+                raw_transaction,
+            } = &self.rows[i].status
+            {
+                match transactions
+                    .iter_mut()
+                    .find(|(hash, ..)| hash == transaction_hash)
+                {
+                    Some((.., rows)) => rows.push(i),
+                    None => transactions.push((
+                        *transaction_hash,
+                        *nonce,
+                        raw_transaction.clone(),
+                        vec![i],
+                    )),
+                }
+            }
+        }
+        transactions.sort_by_key(|(_, nonce, ..)| *nonce);
+        transactions
+    }
+}
+
+impl Web3Manager {
+    /// Sends `token` from `account` to every recipient, recording progress in
+    /// `state_path`. An existing state file for the same token, sender and
+    /// recipients is resumed, one for another multisend is an error.
+    pub async fn multisend<P: AsRef<Path>>(
+        &mut self,
+        account: H160,
+        token: H160,
+        recipients: &RecipientList,
+        options: &MultisendOptions,
+        state_path: P,
+    ) -> Result<MultisendState, Box<dyn Error>> {
+        let state_path = state_path.as_ref();
+        let mut state = if state_path.exists() {
+            let state = MultisendState::load(state_path)?;
+            if !state.matches(token, account, recipients) {
+                return Err(
+                    format!("{} belongs to another multisend", state_path.display()).into(),
+                );
+            }
+            state
+        } else {
+            MultisendState::new(token, account, recipients)
+        };
+        state.save(state_path)?;
+
+        // settle what a previous run left in flight before using new nonces
+        self.resume_sent_rows(&mut state, state_path).await?;
+        self.wait_for_sent_rows(&mut state, state_path, options.receipt_timeout)
+            .await?;
+
+        let pending = state.pending();
+        if pending.is_empty() {
+            return Ok(state);
+        }
+        let next_nonce = self
+            .web3http
+            .eth()
+            .transaction_count(account, Some(BlockNumber::Pending))
+            .await?;
+        self.set_current_nonce(next_nonce);
+
+        if let MultisendMode::Disperse(disperse) = options.mode {
+            let remaining = pending.iter().fold(U256::zero(), |total, &i| {
+                total + state.rows[i].recipient.amount
+            });
+            self.ensure_allowance(
+                account,
+                &format!("{:?}", token),
+                &format!("{:?}", disperse),
+                remaining,
+            )
+            .await?;
+        }
+
+        for batch in pending.chunks(options.batch_size.max(1)) {
+            match options.mode {
+                MultisendMode::Sequential => {
+                    for &i in batch {
+                        self.send_multisend_rows(&mut state, state_path, &[i], None)
+                            .await?;
+                    }
+                }
+                MultisendMode::Disperse(disperse) => {
+                    self.send_multisend_rows(&mut state, state_path, batch, Some(disperse))
+                        .await?;
+                }
+            }
+            self.wait_for_sent_rows(&mut state, state_path, options.receipt_timeout)
+                .await?;
+        }
+        Ok(state)
+    }
+
+    // Signs the transfer of `rows`, saves it and only then broadcasts it
+    async fn send_multisend_rows(
+        &mut self,
+        state: &mut MultisendState,
+        state_path: &Path,
+        rows: &[usize],
+        disperse: Option<H160>,
+    ) -> Result<(), Box<dyn Error>> {
+        let gas_settings: GasSettings = self.gas_settings().clone();
+        let tx_parameters = match disperse {
+            None => {
+                let recipient = &state.rows[rows[0]].recipient;
+                let token = self.cached_contract(state.token, AbiKind::Token);
+                self.build_tx_parameters(
+                    state.sender,
+                    &token,
+                    "transfer",
+                    &(recipient.address, recipient.amount),
+                    U256::zero(),
+                    &gas_settings,
+                )
+                .await
+            }
+            Some(disperse) => {
+                let addresses: Vec<H160> = rows
+                    .iter()
+                    .map(|&i| state.rows[i].recipient.address)
+                    .collect();
+                let amounts: Vec<U256> = rows
+                    .iter()
+                    .map(|&i| state.rows[i].recipient.amount)
+                    .collect();
+                let disperse = self.cached_contract(disperse, AbiKind::Disperse);
+                self.build_tx_parameters(
+                    state.sender,
+                    &disperse,
+                    "disperseToken",
+                    &(state.token, addresses, amounts),
+                    U256::zero(),
+                    &gas_settings,
+                )
+                .await
+            }
+        };
+        let tx_parameters = match tx_parameters {
+            Ok(tx_parameters) => tx_parameters,
+            // the estimation fails when the transfer would revert
+            Err(err) => {
+                state.set_status(
+                    rows,
+                    RowStatus::Failed {
+                        reason: err.to_string(),
+                    },
+                );
+                return state.save(state_path);
+            }
+        };
+        let nonce = self.get_current_nonce();
+        let signed = self.sign_transaction(state.sender, tx_parameters).await?;
+        state.set_status(
+            rows,
+            RowStatus::Sent {
+                transaction_hash: signed.transaction_hash,
+                nonce,
+                raw_transaction: signed.raw_transaction.clone(),
+            },
+        );
+        state.save(state_path)?;
+
+        if let Err(err) = self
+            .send_raw_transaction_tracked(signed.raw_transaction)
+            .await
+        {
+            // the error can come after the node took it, e.g. a timeout
+            if !self.knows_transaction(signed.transaction_hash).await? {
+                // rejected, the nonce is still free for the next rows
+                state.set_status(rows, rejected_status(err));
+                return state.save(state_path);
+            }
+        }
+        self.update_nonce();
+        Ok(())
+    }
+
+    async fn knows_transaction(&self, tx_hash: H256) -> Result<bool, Box<dyn Error>> {
+        Ok(self
+            .web3http
+            .eth()
+            .transaction(TransactionId::Hash(tx_hash))
+            .await?
+            .is_some())
+    }
+
+    // Rows a previous run signed: mined ones are settled, lost ones are
+    // broadcast again with the same signed transaction, and fail if the node
+    // rejects it
+    async fn resume_sent_rows(
+        &self,
+        state: &mut MultisendState,
+        state_path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let eth = self.web3http.eth();
+        for (tx_hash, nonce, raw_transaction, rows) in state.sent_transactions() {
+            // read before the receipt, so a transaction mined in between
+            // isn't mistaken for another one using its nonce
+            let mined_nonce = eth
+                .transaction_count(state.sender, Some(BlockNumber::Latest))
+                .await?;
+            if let Some(receipt) = eth.transaction_receipt(tx_hash).await? {
+                self.record_gas_cost(&receipt).await;
+                state.set_status(&rows, mined_status(tx_hash, receipt.status));
+            } else if self.knows_transaction(tx_hash).await? {
+                continue;
+            } else if mined_nonce > nonce {
+                state.set_status(
+                    &rows,
+                    RowStatus::Failed {
+                        reason: format!(
+                            "nonce {} was used by another transaction, check {:?}",
+                            nonce, tx_hash
+                        ),
+                    },
+                );
+            } else if let Err(err) = self.send_raw_transaction_tracked(raw_transaction).await {
+                state.set_status(&rows, rejected_status(err));
+            } else {
+                continue;
+            }
+            state.save(state_path)?;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_sent_rows(
+        &self,
+        state: &mut MultisendState,
+        state_path: &Path,
+        timeout: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let started = Instant::now();
+        loop {
+            let transactions = state.sent_transactions();
+            if transactions.is_empty() {
+                return Ok(());
+            }
+            for (tx_hash, _, _, rows) in transactions {
+                if let Some(receipt) = self.web3http.eth().transaction_receipt(tx_hash).await? {
+                    self.record_gas_cost(&receipt).await;
+                    state.set_status(&rows, mined_status(tx_hash, receipt.status));
+                    state.save(state_path)?;
+                }
+            }
+            if started.elapsed() > timeout {
+                // the rows stay in flight, the next run picks them up
+                return Err(format!(
+                    "multisend transactions not mined after {:?}, run it again to resume",
+                    timeout
+                )
+                .into());
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+fn rejected_status(err: Box<dyn Error>) -> RowStatus {
+    RowStatus::Failed {
+        reason: format!("rejected by the node: {}", err),
+    }
+}
+
+fn mined_status(transaction_hash: H256, status: Option<U64>) -> RowStatus {
+    if status == Some(U64::from(0)) {
+        RowStatus::Reverted { transaction_hash }
+    } else {
+        RowStatus::Confirmed { transaction_hash }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::mock_manager;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn checks_eip55_checksums() {
+        // from the EIP-55 test vectors
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let address = parse_checksummed_address(checksummed).unwrap();
+        assert_eq!(to_checksum_address(&address), checksummed);
+        assert_eq!(
+            parse_checksummed_address(&checksummed.to_lowercase()).unwrap(),
+            address
+        );
+        assert!(parse_checksummed_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").is_err());
+        assert!(parse_checksummed_address("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+    }
+
+    #[test]
+    fn validates_and_merges_csv_rows() {
+        let csv = "Wallet,Amount\n\
+                   0x733a4e5E23E03b64Bb635867c3b031865D08E930,1.5\n\
+                   0x8912800b3e539287a0e72d5b6b97486b257d5dbe,2\n\
+                   0x733a4e5e23e03b64bb635867c3b031865d08e930,0.5\n";
+        let list = RecipientList::from_csv(csv.as_bytes(), 1).unwrap();
+        assert_eq!(list.recipients.len(), 2);
+        assert_eq!(list.recipients[0].amount, U256::from(20));
+        assert_eq!(list.merged, vec![list.recipients[0].address]);
+        assert_eq!(list.total(), U256::from(40));
+
+        let invalid = "Wallet,Amount\n\
+                       0x733a4e5E23E03b64Bb635867c3b031865D08E931,1\n\
+                       0x8912800b3e539287a0e72d5b6b97486b257d5dbe,0\n\
+                       0x0000000000000000000000000000000000000000,1\n";
+        let err = RecipientList::from_csv(invalid.as_bytes(), 0)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 2: address"));
+        assert!(err.contains("line 3: amount is zero"));
+        assert!(err.contains("line 4"));
+    }
+
+    #[test]
+    fn state_round_trips_and_groups_sent_rows() {
+        let recipients = RecipientList::new(
+            (1..=3)
+                .map(|i| Recipient {
+                    address: H160::from_low_u64_be(i),
+                    amount: U256::from(i),
+                })
+                .collect(),
+        )
+        .unwrap();
+        let token = H160::from_low_u64_be(100);
+        let sender = H160::from_low_u64_be(200);
+        let mut state = MultisendState::new(token, sender, &recipients);
+        let sent = RowStatus::Sent {
+            transaction_hash: H256::from_low_u64_be(1),
+            nonce: U256::from(7),
+            raw_transaction: Bytes(vec![1, 2, 3]),
+        };
+        state.set_status(&[0, 1], sent);
+        assert_eq!(state.pending(), vec![2]);
+        assert_eq!(state.sent_transactions().len(), 1);
+        assert_eq!(state.sent_transactions()[0].3, vec![0, 1]);
+
+        let path = std::env::temp_dir().join(format!("multisend_{}.json", std::process::id()));
+        state.save(&path).unwrap();
+        let loaded = MultisendState::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.matches(token, sender, &recipients));
+        assert!(!loaded.matches(token, token, &recipients));
+
+        state.set_status(
+            &[2],
+            RowStatus::Failed {
+                reason: "reverted in estimation".to_string(),
+            },
+        );
+        state.retry_failed();
+        assert_eq!(state.pending(), vec![2]);
+        assert!(!state.is_complete());
+    }
+
+    #[tokio::test]
+    async fn rejected_broadcasts_fail_the_rows() {
+        let (mut web3m, requests) = mock_manager(|method, _| match method {
+            "eth_getTransactionCount" => Ok(json!("0x5")),
+            "eth_estimateGas" => Ok(json!("0xb411")),
+            "eth_gasPrice" => Ok(json!("0x3b9aca00")),
+            "eth_sendRawTransaction" => Err("insufficient funds for gas * price + value".into()),
+            "eth_getTransactionByHash" => Ok(Value::Null),
+            _ => Err(format!("unexpected {}", method)),
+        })
+        .await;
+        web3m
+            .load_account("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .await;
+        let account = web3m.first_account();
+        let recipients = RecipientList::new(
+            (1..=2)
+                .map(|i| Recipient {
+                    address: H160::from_low_u64_be(i),
+                    amount: U256::from(i),
+                })
+                .collect(),
+        )
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("multisend_rejected_{}.json", std::process::id()));
+
+        let state = web3m
+            .multisend(
+                account,
+                H160::from_low_u64_be(100),
+                &recipients,
+                &MultisendOptions::default(),
+                &path,
+            )
+            .await
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        for row in &state.rows {
+            match &row.status {
+                RowStatus::Failed { reason } => assert!(reason.contains("insufficient funds")),
+                status => panic!("unexpected {:?}", status),
+            }
+        }
+        assert!(state.sent_transactions().is_empty());
+        // the second row was signed with the nonce the first didn't use
+        let sent_nonces: Vec<u64> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request["method"] == "eth_sendRawTransaction")
+            .map(|request| {
+                let raw = hex::decode(
+                    request["params"][0]
+                        .as_str()
+                        .unwrap()
+                        .trim_start_matches("0x"),
+                )
+                .unwrap();
+                crate::tx_inspector::decode_raw_transaction(&raw)
+                    .unwrap()
+                    .nonce
+                    .as_u64()
+            })
+            .collect();
+        assert_eq!(sent_nonces, vec![5, 5]);
+        assert_eq!(web3m.get_current_nonce(), U256::from(5));
+    }
+}
@@ -0,0 +1,482 @@
+//! Offline signing: transactions are prepared online into an unsigned
+//! bundle (a JSON file), signed on a machine that never goes online and
+//! broadcast later from the signed bundle.
+//!
+//! ```ignore
+//! // online
+//! let bundle = web3m.prepare_bundle(treasury, &calls).await?;
+//! bundle.save("unsigned.json")?;
+//! // offline, no node needed
+//! let signed = TransactionBundle::load("unsigned.json")?.sign_with(&[&keystore_signer]).await?;
+//! signed.save("signed.json")?;
+//! // online
+//! let hashes = web3m.broadcast_bundle(&SignedBundle::load("signed.json")?).await?;
+//! ```
+//!
+//! Before broadcasting, every raw transaction is checked against the
+//! transaction it was signed for: same signing hash (so the same nonce, gas,
+//! fees, chain id, destination, value and data) and signed by its `from`.
+
+use crate::gas::TransactionFees;
+use crate::signer::{signed_transaction_from_raw, transaction_sender, Signer};
+use crate::Web3Manager;
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use web3::signing::keccak256;
+use web3::types::{
+    AccessList, BlockNumber, Bytes, CallRequest, TransactionParameters, H160, H256, U256, U64,
+};
+
+const LEGACY_TX_TYPE: u64 = 0;
+const ACCESS_LIST_TX_TYPE: u64 = 1;
+const EIP1559_TX_TYPE: u64 = 2;
+
+/// Everything needed to sign a transaction without a node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsignedTransaction {
+    pub from: H160,
+    /// `None` deploys a contract.
+    pub to: Option<H160>,
+    pub nonce: U256,
+    pub gas: U256,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Legacy when `None`.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub transaction_type: Option<U64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<AccessList>,
+    pub value: U256,
+    pub data: Bytes,
+    pub chain_id: u64,
+    /// Free text shown to whoever signs the bundle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl UnsignedTransaction {
+    fn kind(&self) -> Result<u64, Box<dyn Error>> {
+        let kind = self
+            .transaction_type
+            .map_or(LEGACY_TX_TYPE, |kind| kind.as_u64());
+        match kind {
+            LEGACY_TX_TYPE | ACCESS_LIST_TX_TYPE if self.gas_price.is_none() => {
+                Err("legacy and access list transactions need a gas price".into())
+            }
+            EIP1559_TX_TYPE if self.max_fee_per_gas.is_none() => {
+                Err("EIP-1559 transactions need a max fee per gas".into())
+            }
+            LEGACY_TX_TYPE | ACCESS_LIST_TX_TYPE | EIP1559_TX_TYPE => Ok(kind),
+            _ => Err(format!("unsupported transaction type {}", kind).into()),
+        }
+    }
+
+    pub fn to_parameters(&self) -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(self.nonce),
+            to: self.to,
+            gas: self.gas,
+            gas_price: self.gas_price,
+            value: self.value,
+            data: self.data.clone(),
+            chain_id: Some(self.chain_id),
+            transaction_type: self.transaction_type,
+            access_list: self.access_list.clone(),
+            max_fee_per_gas: self.max_fee_per_gas,
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+        }
+    }
+
+    /// Hash the signature is made over, as a node derives it from the raw
+    /// transaction.
+    pub fn signing_hash(&self) -> Result<H256, Box<dyn Error>> {
+        let kind = self.kind()?;
+        let mut stream = RlpStream::new();
+        match kind {
+            LEGACY_TX_TYPE => {
+                // EIP-155
+                stream.begin_list(9);
+                self.append_common(&mut stream, self.gas_price.unwrap_or_default());
+                stream.append(&self.chain_id);
+                stream.append(&0u8);
+                stream.append(&0u8);
+            }
+            ACCESS_LIST_TX_TYPE => {
+                stream.begin_list(8);
+                stream.append(&self.chain_id);
+                self.append_common(&mut stream, self.gas_price.unwrap_or_default());
+                self.append_access_list(&mut stream);
+            }
+            _ => {
+                let max_fee_per_gas = self.max_fee_per_gas.unwrap_or_default();
+                stream.begin_list(9);
+                stream.append(&self.chain_id);
+                stream.append(&self.nonce);
+                stream.append(&self.max_priority_fee_per_gas.unwrap_or(max_fee_per_gas));
+                self.append_common_after_nonce(&mut stream, max_fee_per_gas);
+                self.append_access_list(&mut stream);
+            }
+        }
+
+        let mut payload = Vec::new();
+        if kind != LEGACY_TX_TYPE {
+            payload.push(kind as u8);
+        }
+        payload.extend_from_slice(&stream.out());
+        Ok(H256(keccak256(&payload)))
+    }
+
+    fn append_common(&self, stream: &mut RlpStream, gas_price: U256) {
+        stream.append(&self.nonce);
+        self.append_common_after_nonce(stream, gas_price);
+    }
+
+    // (gas price, gas, to, value, data), the fields all types share
+    fn append_common_after_nonce(&self, stream: &mut RlpStream, gas_price: U256) {
+        stream.append(&gas_price);
+        stream.append(&self.gas);
+        match self.to {
+            Some(to) => stream.append(&to),
+            None => stream.append_empty_data(),
+        };
+        stream.append(&self.value);
+        stream.append(&self.data.0);
+    }
+
+    fn append_access_list(&self, stream: &mut RlpStream) {
+        let access_list = self.access_list.clone().unwrap_or_default();
+        stream.begin_list(access_list.len());
+        for item in access_list {
+            stream.begin_list(2);
+            stream.append(&item.address);
+            stream.begin_list(item.storage_keys.len());
+            for storage_key in item.storage_keys {
+                stream.append(&storage_key);
+            }
+        }
+    }
+}
+
+/// Call to prepare in a bundle, its nonce and fees are filled online.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BundleCall {
+    pub to: Option<H160>,
+    pub value: U256,
+    pub data: Bytes,
+    /// Estimated when `None`.
+    pub gas: Option<U256>,
+    pub description: Option<String>,
+}
+
+/// Unsigned transactions, in the order they must be broadcast.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransactionBundle {
+    pub transactions: Vec<UnsignedTransaction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedBundleTransaction {
+    pub transaction: UnsignedTransaction,
+    pub raw_transaction: Bytes,
+    pub transaction_hash: H256,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SignedBundle {
+    pub transactions: Vec<SignedBundleTransaction>,
+}
+
+fn save_json<T: Serialize>(value: &T, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
+
+impl TransactionBundle {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        save_json(self, path.as_ref())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TransactionBundle, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Signs every transaction with the signer of its `from`, no node is
+    /// used.
+    pub async fn sign_with(&self, signers: &[&dyn Signer]) -> Result<SignedBundle, Box<dyn Error>> {
+        let mut signed_bundle = SignedBundle::default();
+        for transaction in &self.transactions {
+            transaction.kind()?;
+            let signer = signers
+                .iter()
+                .find(|signer| signer.address() == transaction.from)
+                .ok_or_else(|| format!("no signer for {:?}", transaction.from))?;
+            let signed = signer.sign_transaction(transaction.to_parameters()).await?;
+            signed_bundle.transactions.push(SignedBundleTransaction {
+                transaction: transaction.clone(),
+                raw_transaction: signed.raw_transaction,
+                transaction_hash: signed.transaction_hash,
+            });
+        }
+        Ok(signed_bundle)
+    }
+}
+
+impl SignedBundleTransaction {
+    /// Checks the raw transaction is `transaction` signed by its `from`.
+    pub fn verify(&self) -> Result<(), Box<dyn Error>> {
+        let signed = signed_transaction_from_raw(self.raw_transaction.0.clone())?;
+        if signed.transaction_hash != self.transaction_hash {
+            return Err(format!(
+                "raw transaction hashes to {:?}, not {:?}",
+                signed.transaction_hash, self.transaction_hash
+            )
+            .into());
+        }
+        if signed.message_hash != self.transaction.signing_hash()? {
+            return Err("raw transaction doesn't match the unsigned transaction".into());
+        }
+
+        let signer = transaction_sender(&signed)?;
+        if signer != self.transaction.from {
+            return Err(format!(
+                "signed by {:?} instead of {:?}",
+                signer, self.transaction.from
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl SignedBundle {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        save_json(self, path.as_ref())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SignedBundle, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn verify(&self) -> Result<(), Box<dyn Error>> {
+        for (index, transaction) in self.transactions.iter().enumerate() {
+            transaction
+                .verify()
+                .map_err(|err| format!("transaction {}: {}", index, err))?;
+        }
+        Ok(())
+    }
+}
+
+impl Web3Manager {
+    /// Unsigned transactions for `calls` from `from`, with consecutive nonces
+    /// starting at its pending nonce and the fees of the gas settings.
+    pub async fn prepare_bundle(
+        &self,
+        from: H160,
+        calls: &[BundleCall],
+    ) -> Result<TransactionBundle, Box<dyn Error>> {
+        let eth = self.web3http.eth();
+        let mut nonce = eth
+            .transaction_count(from, Some(BlockNumber::Pending))
+            .await?;
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas, transaction_type) =
+            match self.transaction_fees(self.gas_settings()).await? {
+                TransactionFees::Legacy { gas_price } => (Some(gas_price), None, None, None),
+                TransactionFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                } => (
+                    None,
+                    Some(max_fee_per_gas),
+                    Some(max_priority_fee_per_gas),
+                    Some(U64::from(EIP1559_TX_TYPE)),
+                ),
+            };
+        let chain_id = match self.chain_id {
+            Some(chain_id) => chain_id,
+            None => eth.chain_id().await?.as_u64(),
+        };
+
+        let mut bundle = TransactionBundle::default();
+        for call in calls {
+            let gas = match call.gas {
+                Some(gas) => gas,
+                None => {
+                    let estimated = eth
+                        .estimate_gas(
+                            CallRequest {
+                                from: Some(from),
+                                to: call.to,
+                                value: Some(call.value),
+                                data: Some(call.data.clone()),
+                                ..Default::default()
+                            },
+                            None,
+                        )
+                        .await?;
+                    self.gas_settings().gas_limit(estimated)
+                }
+            };
+            bundle.transactions.push(UnsignedTransaction {
+                from,
+                to: call.to,
+                nonce,
+                gas,
+                gas_price,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                transaction_type,
+                access_list: None,
+                value: call.value,
+                data: call.data.clone(),
+                chain_id,
+                description: call.description.clone(),
+            });
+            nonce += U256::one();
+        }
+        Ok(bundle)
+    }
+
+    /// `TransactionBundle::sign_with` the signers of the loaded accounts.
+    pub async fn sign_bundle(
+        &self,
+        bundle: &TransactionBundle,
+    ) -> Result<SignedBundle, Box<dyn Error>> {
+        let mut signers = Vec::new();
+        for transaction in &bundle.transactions {
+            signers.push(self.signer(transaction.from)?);
+        }
+        let signers: Vec<&dyn Signer> = signers.iter().map(|signer| signer.as_ref()).collect();
+        bundle.sign_with(&signers).await
+    }
+
+    /// Verifies the whole bundle, then sends it in order. Nothing is sent if
+    /// any transaction fails verification.
+    pub async fn broadcast_bundle(
+        &self,
+        bundle: &SignedBundle,
+    ) -> Result<Vec<H256>, Box<dyn Error>> {
+        bundle.verify()?;
+        let mut hashes = Vec::with_capacity(bundle.transactions.len());
+        for transaction in &bundle.transactions {
+            hashes.push(
+                self.send_raw_transaction_tracked(transaction.raw_transaction.clone())
+                    .await?,
+            );
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::LocalSigner;
+    use std::str::FromStr;
+    use web3::types::AccessListItem;
+
+    fn signer() -> LocalSigner {
+        LocalSigner::from_str("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .unwrap()
+    }
+
+    fn unsigned(nonce: u64) -> UnsignedTransaction {
+        UnsignedTransaction {
+            from: signer().address(),
+            to: Some(H160::from_low_u64_be(1)),
+            nonce: U256::from(nonce),
+            gas: U256::from(60_000),
+            gas_price: Some(U256::from(5_000_000_000u64)),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            transaction_type: None,
+            access_list: None,
+            value: U256::from(10),
+            data: Bytes(vec![0xa9, 0x05, 0x9c, 0xbb]),
+            chain_id: 56,
+            description: Some("transfer".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn signing_hash_matches_signed_transactions() {
+        let access_list = Some(vec![AccessListItem {
+            address: H160::from_low_u64_be(2),
+            storage_keys: vec![H256::from_low_u64_be(3)],
+        }]);
+        let transactions = vec![
+            unsigned(0),
+            UnsignedTransaction {
+                to: None,
+                ..unsigned(1)
+            },
+            UnsignedTransaction {
+                transaction_type: Some(U64::from(ACCESS_LIST_TX_TYPE)),
+                access_list: access_list.clone(),
+                ..unsigned(2)
+            },
+            UnsignedTransaction {
+                transaction_type: Some(U64::from(EIP1559_TX_TYPE)),
+                gas_price: None,
+                max_fee_per_gas: Some(U256::from(30)),
+                max_priority_fee_per_gas: Some(U256::from(2)),
+                access_list,
+                ..unsigned(3)
+            },
+        ];
+        for transaction in transactions {
+            let signed = signer()
+                .sign_transaction(transaction.to_parameters())
+                .await
+                .unwrap();
+            assert_eq!(transaction.signing_hash().unwrap(), signed.message_hash);
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_signed_bundles() {
+        let bundle = TransactionBundle {
+            transactions: vec![unsigned(0), unsigned(1)],
+        };
+        let signer = signer();
+        let signed = bundle.sign_with(&[&signer]).await.unwrap();
+        assert!(signed.verify().is_ok());
+
+        // intent changed after signing
+        let mut tampered = signed.clone();
+        tampered.transactions[1].transaction.value = U256::from(1_000);
+        assert!(tampered.verify().is_err());
+
+        // raw transaction swapped
+        let mut swapped = signed;
+        swapped.transactions[0].raw_transaction = swapped.transactions[1].raw_transaction.clone();
+        assert!(swapped.verify().is_err());
+
+        let other = LocalSigner::from_str(&hex::encode(keccak256(b"other"))).unwrap();
+        assert!(bundle.sign_with(&[&other]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_files() {
+        let path = std::env::temp_dir().join(format!("bundle-{}.json", std::process::id()));
+        let bundle = TransactionBundle {
+            transactions: vec![unsigned(4)],
+        };
+        bundle.save(&path).unwrap();
+        assert_eq!(TransactionBundle::load(&path).unwrap(), bundle);
+
+        let signed = bundle.sign_with(&[&signer()]).await.unwrap();
+        signed.save(&path).unwrap();
+        assert_eq!(SignedBundle::load(&path).unwrap(), signed);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,741 @@
+//! Gasless approvals signed with EIP-712: EIP-2612 `permit` for tokens
+//! that implement it and Uniswap Permit2 `PermitSingle`/`PermitBatch`.
+//!
+//! Signatures are made with the private keys of the accounts loaded in
+//! `Web3Manager`, nothing is sent on-chain until a router function consumes
+//! them (see `remove_liquidity_with_signed_permit`).
+
+use crate::dynamic_abi::parse_function_signature;
+use crate::liquidity::LiquidityPermit;
+use crate::message_signing::{TypedData, TypedDataField};
+use crate::{AbiKind, Slippage, Web3Manager};
+use hex_literal::hex;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::str::FromStr;
+use web3::ethabi::{self, Token};
+use web3::signing::keccak256;
+use web3::types::{Bytes, CallRequest, H160, H256, U256};
+
+/// Permit2 is deployed at the same address on every chain.
+pub const PERMIT2_ADDRESS: H160 = H160(hex!("000000000022D473030F116dDEE9F6B43aC78BA3"));
+
+const EIP2612_PERMIT_TYPE: &str =
+    "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)";
+const PERMIT_DETAILS_TYPE: &str =
+    "PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)";
+const PERMIT_SINGLE_TYPE: &str = "PermitSingle(PermitDetails details,address spender,uint256 sigDeadline)PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)";
+const PERMIT_BATCH_TYPE: &str = "PermitBatch(PermitDetails[] details,address spender,uint256 sigDeadline)PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)";
+
+/// Largest value of the `uint48` Permit2 expirations and nonces.
+pub const MAX_UINT48: u64 = (1 << 48) - 1;
+
+fn type_hash(encoded_type: &str) -> Token {
+    Token::FixedBytes(keccak256(encoded_type.as_bytes()).to_vec())
+}
+
+fn hash_tokens(tokens: &[Token]) -> H256 {
+    H256(keccak256(&ethabi::encode(tokens)))
+}
+
+/// EIP-712 domain, fields left as `None` are not part of the domain type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Eip712Domain {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<H160>,
+}
+
+impl Eip712Domain {
+    pub fn separator(&self) -> H256 {
+        let mut fields = Vec::new();
+        let mut values = Vec::new();
+        if let Some(name) = &self.name {
+            fields.push("string name");
+            values.push(Token::FixedBytes(keccak256(name.as_bytes()).to_vec()));
+        }
+        if let Some(version) = &self.version {
+            fields.push("string version");
+            values.push(Token::FixedBytes(keccak256(version.as_bytes()).to_vec()));
+        }
+        if let Some(chain_id) = self.chain_id {
+            fields.push("uint256 chainId");
+            values.push(Token::Uint(chain_id.into()));
+        }
+        if let Some(verifying_contract) = self.verifying_contract {
+            fields.push("address verifyingContract");
+            values.push(Token::Address(verifying_contract));
+        }
+
+        let mut tokens = vec![type_hash(&format!("EIP712Domain({})", fields.join(",")))];
+        tokens.extend(values);
+        hash_tokens(&tokens)
+    }
+
+    /// `EIP712Domain` fields and value of a typed data document.
+    pub fn typed_data_domain(&self) -> (Vec<TypedDataField>, Value) {
+        let mut fields = Vec::new();
+        let mut domain = Map::new();
+        if let Some(name) = &self.name {
+            fields.push(field("name", "string"));
+            domain.insert("name".to_string(), json!(name));
+        }
+        if let Some(version) = &self.version {
+            fields.push(field("version", "string"));
+            domain.insert("version".to_string(), json!(version));
+        }
+        if let Some(chain_id) = self.chain_id {
+            fields.push(field("chainId", "uint256"));
+            domain.insert("chainId".to_string(), json!(chain_id));
+        }
+        if let Some(verifying_contract) = self.verifying_contract {
+            fields.push(field("verifyingContract", "address"));
+            domain.insert("verifyingContract".to_string(), json!(verifying_contract));
+        }
+        (fields, Value::Object(domain))
+    }
+
+    /// Typed data document of `message` of `primary_type` in this domain.
+    pub fn typed_data(
+        &self,
+        mut types: BTreeMap<String, Vec<TypedDataField>>,
+        primary_type: &str,
+        message: Value,
+    ) -> TypedData {
+        let (domain_fields, domain) = self.typed_data_domain();
+        types.insert("EIP712Domain".to_string(), domain_fields);
+        TypedData {
+            types,
+            primary_type: primary_type.to_string(),
+            domain,
+            message,
+        }
+    }
+}
+
+fn field(name: &str, kind: &str) -> TypedDataField {
+    TypedDataField {
+        name: name.to_string(),
+        kind: kind.to_string(),
+    }
+}
+
+fn permit2_types(primary_type: &str, details_type: &str) -> BTreeMap<String, Vec<TypedDataField>> {
+    let mut types = BTreeMap::new();
+    types.insert(
+        "PermitDetails".to_string(),
+        vec![
+            field("token", "address"),
+            field("amount", "uint160"),
+            field("expiration", "uint48"),
+            field("nonce", "uint48"),
+        ],
+    );
+    types.insert(
+        primary_type.to_string(),
+        vec![
+            field("details", details_type),
+            field("spender", "address"),
+            field("sigDeadline", "uint256"),
+        ],
+    );
+    types
+}
+
+/// Digest signed for a struct: `keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct)`.
+pub fn typed_data_hash(domain_separator: H256, struct_hash: H256) -> H256 {
+    let mut message = Vec::with_capacity(66);
+    message.extend_from_slice(b"\x19\x01");
+    message.extend_from_slice(domain_separator.as_bytes());
+    message.extend_from_slice(struct_hash.as_bytes());
+    H256(keccak256(&message))
+}
+
+/// Signed EIP-2612 `permit`, the arguments of `permit(owner, spender, value,
+/// deadline, v, r, s)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permit {
+    pub token: H160,
+    pub owner: H160,
+    pub spender: H160,
+    pub value: U256,
+    pub nonce: U256,
+    pub deadline: U256,
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+/// Typed data document of an EIP-2612 permit in the token's `domain`.
+pub fn permit_typed_data(
+    domain: &Eip712Domain,
+    owner: H160,
+    spender: H160,
+    value: U256,
+    nonce: U256,
+    deadline: U256,
+) -> TypedData {
+    let mut types = BTreeMap::new();
+    types.insert(
+        "Permit".to_string(),
+        vec![
+            field("owner", "address"),
+            field("spender", "address"),
+            field("value", "uint256"),
+            field("nonce", "uint256"),
+            field("deadline", "uint256"),
+        ],
+    );
+    domain.typed_data(
+        types,
+        "Permit",
+        json!({
+            "owner": owner,
+            "spender": spender,
+            "value": value.to_string(),
+            "nonce": nonce.to_string(),
+            "deadline": deadline.to_string(),
+        }),
+    )
+}
+
+pub fn permit_struct_hash(
+    owner: H160,
+    spender: H160,
+    value: U256,
+    nonce: U256,
+    deadline: U256,
+) -> H256 {
+    hash_tokens(&[
+        type_hash(EIP2612_PERMIT_TYPE),
+        Token::Address(owner),
+        Token::Address(spender),
+        Token::Uint(value),
+        Token::Uint(nonce),
+        Token::Uint(deadline),
+    ])
+}
+
+/// Allowance of one token granted through Permit2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PermitDetails {
+    pub token: H160,
+    /// `uint160`
+    pub amount: U256,
+    /// Unix time, `uint48`
+    pub expiration: u64,
+    /// `uint48`
+    pub nonce: u64,
+}
+
+impl PermitDetails {
+    pub fn struct_hash(&self) -> H256 {
+        hash_tokens(&[
+            type_hash(PERMIT_DETAILS_TYPE),
+            Token::Address(self.token),
+            Token::Uint(self.amount),
+            Token::Uint(self.expiration.into()),
+            Token::Uint(self.nonce.into()),
+        ])
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "token": self.token,
+            "amount": self.amount.to_string(),
+            "expiration": self.expiration,
+            "nonce": self.nonce,
+        })
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.amount.bits() > 160 {
+            return Err(format!("permit amount {} doesn't fit in uint160", self.amount).into());
+        }
+        if self.expiration > MAX_UINT48 || self.nonce > MAX_UINT48 {
+            return Err("permit expiration and nonce must fit in uint48".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PermitSingle {
+    pub details: PermitDetails,
+    pub spender: H160,
+    pub sig_deadline: U256,
+}
+
+impl PermitSingle {
+    pub fn struct_hash(&self) -> H256 {
+        hash_tokens(&[
+            type_hash(PERMIT_SINGLE_TYPE),
+            Token::FixedBytes(self.details.struct_hash().as_bytes().to_vec()),
+            Token::Address(self.spender),
+            Token::Uint(self.sig_deadline),
+        ])
+    }
+
+    pub fn typed_data(&self, chain_id: u64) -> TypedData {
+        permit2_domain(chain_id).typed_data(
+            permit2_types("PermitSingle", "PermitDetails"),
+            "PermitSingle",
+            json!({
+                "details": self.details.to_json(),
+                "spender": self.spender,
+                "sigDeadline": self.sig_deadline.to_string(),
+            }),
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermitBatch {
+    pub details: Vec<PermitDetails>,
+    pub spender: H160,
+    pub sig_deadline: U256,
+}
+
+impl PermitBatch {
+    pub fn struct_hash(&self) -> H256 {
+        // arrays are hashed as the concatenation of their members' hashes
+        let details: Vec<u8> = self
+            .details
+            .iter()
+            .flat_map(|details| details.struct_hash().0.to_vec())
+            .collect();
+        hash_tokens(&[
+            type_hash(PERMIT_BATCH_TYPE),
+            Token::FixedBytes(keccak256(&details).to_vec()),
+            Token::Address(self.spender),
+            Token::Uint(self.sig_deadline),
+        ])
+    }
+
+    pub fn typed_data(&self, chain_id: u64) -> TypedData {
+        let details: Vec<Value> = self
+            .details
+            .iter()
+            .map(|details| details.to_json())
+            .collect();
+        permit2_domain(chain_id).typed_data(
+            permit2_types("PermitBatch", "PermitDetails[]"),
+            "PermitBatch",
+            json!({
+                "details": details,
+                "spender": self.spender,
+                "sigDeadline": self.sig_deadline.to_string(),
+            }),
+        )
+    }
+}
+
+/// Domain of the Permit2 contract on `chain_id`, it has no version.
+pub fn permit2_domain(chain_id: u64) -> Eip712Domain {
+    Eip712Domain {
+        name: Some("Permit2".to_string()),
+        version: None,
+        chain_id: Some(chain_id),
+        verifying_contract: Some(PERMIT2_ADDRESS),
+    }
+}
+
+impl Web3Manager {
+    async fn signing_chain_id(&self) -> Result<u64, Box<dyn Error>> {
+        match self.chain_id {
+            Some(chain_id) => Ok(chain_id),
+            None => Ok(self.web3http.eth().chain_id().await?.as_u64()),
+        }
+    }
+
+    // eth_call of a function given by its signature, returns the decoded outputs
+    async fn call_view(
+        &self,
+        contract: H160,
+        signature: &str,
+        args: &[Token],
+    ) -> Result<Vec<Token>, Box<dyn Error>> {
+        let function = parse_function_signature(signature)?;
+        let call_request = CallRequest {
+            to: Some(contract),
+            data: Some(function.encode_input(args)?.into()),
+            ..Default::default()
+        };
+        let result = self.web3http.eth().call(call_request, None).await?;
+        Ok(function.decode_output(&result.0)?)
+    }
+
+    /// EIP-712 domain of an EIP-2612 token, built from `name()`, `version()`
+    /// (`"1"` if missing), the chain id and the token address. It is checked
+    /// against `DOMAIN_SEPARATOR()` when the token exposes it.
+    pub async fn permit_domain(&self, token: H160) -> Result<Eip712Domain, Box<dyn Error>> {
+        let name = match self.call_view(token, "name()(string)", &[]).await?.first() {
+            Some(Token::String(name)) => name.clone(),
+            _ => return Err(format!("token {:?} has no name", token).into()),
+        };
+        let version = match self.call_view(token, "version()(string)", &[]).await {
+            Ok(outputs) => match outputs.first() {
+                Some(Token::String(version)) => version.clone(),
+                _ => "1".to_string(),
+            },
+            Err(_) => "1".to_string(),
+        };
+        let domain = Eip712Domain {
+            name: Some(name),
+            version: Some(version),
+            chain_id: Some(self.signing_chain_id().await?),
+            verifying_contract: Some(token),
+        };
+
+        if let Ok(outputs) = self
+            .call_view(token, "DOMAIN_SEPARATOR()(bytes32)", &[])
+            .await
+        {
+            if let Some(Token::FixedBytes(separator)) = outputs.first() {
+                if H256::from_slice(separator) != domain.separator() {
+                    return Err(
+                        format!("token {:?} uses a domain other than {:?}", token, domain).into(),
+                    );
+                }
+            }
+        }
+        Ok(domain)
+    }
+
+    /// Next EIP-2612 nonce of `owner` on `token`.
+    pub async fn permit_nonce(&self, token: H160, owner: H160) -> Result<U256, Box<dyn Error>> {
+        match self
+            .call_view(token, "nonces(address)(uint256)", &[Token::Address(owner)])
+            .await?
+            .first()
+        {
+            Some(Token::Uint(nonce)) => Ok(*nonce),
+            _ => Err(format!("token {:?} has no permit nonces", token).into()),
+        }
+    }
+
+    /// Signs an EIP-2612 permit letting `spender` pull `value` of `token`
+    /// from `account` until `deadline`.
+    pub async fn sign_permit(
+        &self,
+        account: H160,
+        token_address: &str,
+        spender: &str,
+        value: U256,
+        deadline: U256,
+    ) -> Result<Permit, Box<dyn Error>> {
+        let token = H160::from_str(token_address)?;
+        let spender = H160::from_str(spender)?;
+        let nonce = self.permit_nonce(token, account).await?;
+        let domain = self.permit_domain(token).await?;
+
+        let typed_data = permit_typed_data(&domain, account, spender, value, nonce, deadline);
+        let signature = self.signer(account)?.sign_typed_data(&typed_data).await?;
+        Ok(Permit {
+            token,
+            owner: account,
+            spender,
+            value,
+            nonce,
+            deadline,
+            v: signature.v,
+            r: signature.r,
+            s: signature.s,
+        })
+    }
+
+    /// Signs the LP token permit the router needs to remove `liquidity`
+    /// from the `token_a`/`token_b` pair, valid for the deadline window.
+    pub async fn sign_liquidity_permit(
+        &self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+        approve_max: bool,
+    ) -> Result<LiquidityPermit, Box<dyn Error>> {
+        let pair = self
+            .router_pair(
+                H160::from_str(router_address)?,
+                H160::from_str(token_a)?,
+                H160::from_str(token_b)?,
+            )
+            .await?;
+        if pair.is_zero() {
+            return Err(format!("no pair for {} and {}", token_a, token_b).into());
+        }
+
+        let value = if approve_max { U256::MAX } else { liquidity };
+        let deadline = self.chain_deadline().await?;
+        let permit = self
+            .sign_permit(
+                account,
+                &format!("{:?}", pair),
+                router_address,
+                value,
+                deadline,
+            )
+            .await?;
+        Ok(LiquidityPermit {
+            approve_max,
+            deadline,
+            v: permit.v,
+            r: permit.r,
+            s: permit.s,
+        })
+    }
+
+    /// `remove_liquidity_with_permit` signing the LP token permit on the fly,
+    /// no approval transaction is needed.
+    pub async fn remove_liquidity_with_signed_permit(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_a: &str,
+        token_b: &str,
+        liquidity: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        let permit = self
+            .sign_liquidity_permit(account, router_address, token_a, token_b, liquidity, false)
+            .await?;
+        self.remove_liquidity_with_permit(
+            account,
+            router_address,
+            token_a,
+            token_b,
+            liquidity,
+            slippage,
+            &permit,
+        )
+        .await
+    }
+
+    /// `remove_liquidity_eth_with_permit` signing the LP token permit on the
+    /// fly, no approval transaction is needed.
+    pub async fn remove_liquidity_eth_with_signed_permit(
+        &mut self,
+        account: H160,
+        router_address: &str,
+        token_address: &str,
+        liquidity: U256,
+        slippage: Slippage,
+    ) -> Result<H256, Box<dyn Error>> {
+        let router_instance = self.cached_contract_from_str(router_address, AbiKind::Router)?;
+        let weth_address = self.get_weth_address(&router_instance).await;
+        let permit = self
+            .sign_liquidity_permit(
+                account,
+                router_address,
+                token_address,
+                weth_address.as_str(),
+                liquidity,
+                false,
+            )
+            .await?;
+        self.remove_liquidity_eth_with_permit(
+            account,
+            router_address,
+            token_address,
+            liquidity,
+            slippage,
+            &permit,
+        )
+        .await
+    }
+
+    /// Permit2 allowance of `owner` for `spender` on `token`: amount,
+    /// expiration and the nonce the next `PermitSingle` has to sign.
+    pub async fn permit2_allowance(
+        &self,
+        owner: H160,
+        token: H160,
+        spender: H160,
+    ) -> Result<(U256, u64, u64), Box<dyn Error>> {
+        let outputs = self
+            .call_view(
+                PERMIT2_ADDRESS,
+                "allowance(address,address,address)(uint160,uint48,uint48)",
+                &[
+                    Token::Address(owner),
+                    Token::Address(token),
+                    Token::Address(spender),
+                ],
+            )
+            .await?;
+        match outputs.as_slice() {
+            [Token::Uint(amount), Token::Uint(expiration), Token::Uint(nonce)] => {
+                Ok((*amount, u64::try_from(*expiration)?, u64::try_from(*nonce)?))
+            }
+            _ => Err("unexpected Permit2 allowance output".into()),
+        }
+    }
+
+    /// Builds a `PermitSingle` with the current Permit2 nonce of `account`.
+    pub async fn permit_single(
+        &self,
+        account: H160,
+        token: H160,
+        spender: H160,
+        amount: U256,
+        expiration: u64,
+        sig_deadline: U256,
+    ) -> Result<PermitSingle, Box<dyn Error>> {
+        let (_, _, nonce) = self.permit2_allowance(account, token, spender).await?;
+        Ok(PermitSingle {
+            details: PermitDetails {
+                token,
+                amount,
+                expiration,
+                nonce,
+            },
+            spender,
+            sig_deadline,
+        })
+    }
+
+    /// 65 bytes Permit2 signature of `permit` by `account`.
+    pub async fn sign_permit_single(
+        &self,
+        account: H160,
+        permit: &PermitSingle,
+    ) -> Result<Bytes, Box<dyn Error>> {
+        permit.details.validate()?;
+        let typed_data = permit.typed_data(self.signing_chain_id().await?);
+        let signature = self.signer(account)?.sign_typed_data(&typed_data).await?;
+        Ok(signature.to_bytes().to_vec().into())
+    }
+
+    /// 65 bytes Permit2 signature of `permit` by `account`.
+    pub async fn sign_permit_batch(
+        &self,
+        account: H160,
+        permit: &PermitBatch,
+    ) -> Result<Bytes, Box<dyn Error>> {
+        for details in &permit.details {
+            details.validate()?;
+        }
+        let typed_data = permit.typed_data(self.signing_chain_id().await?);
+        let signature = self.signer(account)?.sign_typed_data(&typed_data).await?;
+        Ok(signature.to_bytes().to_vec().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(value: &str) -> H256 {
+        H256::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn type_hashes_match_contracts() {
+        assert_eq!(
+            H256(keccak256(EIP2612_PERMIT_TYPE.as_bytes())),
+            hash_of("0x6e71edae12b1b97f4d1f60370fef10105fa2faae0126114a169c64845d6126c9")
+        );
+        assert_eq!(
+            H256(keccak256(PERMIT_DETAILS_TYPE.as_bytes())),
+            hash_of("0x65626cad6cb96493bf6f5ebea28756c966f023ab9e8a83a7101849d5573b3678")
+        );
+        assert_eq!(
+            H256(keccak256(PERMIT_SINGLE_TYPE.as_bytes())),
+            hash_of("0xf3841cd1ff0085026a6327b620b67997ce40f282c88a8e905a7a5626e310f3d0")
+        );
+        assert_eq!(
+            H256(keccak256(PERMIT_BATCH_TYPE.as_bytes())),
+            hash_of("0xaf1b0d30d2cab0380e68f0689007e3254993c596f2fdd0aaa7f4d04f79440863")
+        );
+    }
+
+    #[test]
+    fn builds_domain_separator() {
+        // EIP-712 example domain
+        let domain = Eip712Domain {
+            name: Some("Ether Mail".to_string()),
+            version: Some("1".to_string()),
+            chain_id: Some(1),
+            verifying_contract: Some(
+                H160::from_str("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC").unwrap(),
+            ),
+        };
+        assert_eq!(
+            domain.separator(),
+            hash_of("0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f")
+        );
+    }
+
+    fn details(token: u64, nonce: u64) -> PermitDetails {
+        PermitDetails {
+            token: H160::from_low_u64_be(token),
+            amount: U256::from(1000),
+            expiration: MAX_UINT48,
+            nonce,
+        }
+    }
+
+    // the typed data documents handed to signers hash like the contracts do
+    #[test]
+    fn typed_data_matches_struct_hashes() {
+        let single = PermitSingle {
+            details: details(1, 0),
+            spender: H160::from_low_u64_be(2),
+            sig_deadline: U256::from(1_700_000_000),
+        };
+        assert_eq!(
+            single.typed_data(1).signing_hash().unwrap(),
+            typed_data_hash(permit2_domain(1).separator(), single.struct_hash())
+        );
+
+        let batch = PermitBatch {
+            details: vec![details(1, 0), details(3, 4)],
+            spender: H160::from_low_u64_be(2),
+            sig_deadline: U256::from(1_700_000_000),
+        };
+        assert_eq!(
+            batch.typed_data(56).signing_hash().unwrap(),
+            typed_data_hash(permit2_domain(56).separator(), batch.struct_hash())
+        );
+
+        let domain = Eip712Domain {
+            name: Some("Pancake LPs".to_string()),
+            version: Some("1".to_string()),
+            chain_id: Some(56),
+            verifying_contract: Some(H160::from_low_u64_be(9)),
+        };
+        let (owner, spender) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let (value, nonce, deadline) = (U256::MAX, U256::from(3), U256::from(1_700_000_000));
+        assert_eq!(
+            permit_typed_data(&domain, owner, spender, value, nonce, deadline)
+                .signing_hash()
+                .unwrap(),
+            typed_data_hash(
+                domain.separator(),
+                permit_struct_hash(owner, spender, value, nonce, deadline)
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_permit2_fields() {
+        let details = PermitDetails {
+            token: H160::zero(),
+            amount: U256::one() << 160,
+            expiration: 0,
+            nonce: 0,
+        };
+        assert!(details.validate().is_err());
+        assert!(PermitDetails {
+            amount: (U256::one() << 160) - 1,
+            nonce: MAX_UINT48 + 1,
+            ..details
+        }
+        .validate()
+        .is_err());
+    }
+}
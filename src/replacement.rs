@@ -0,0 +1,359 @@
+//! Replace-by-fee for transactions stuck in the mempool.
+//!
+//! A pending transaction is replaced by another one from the same account
+//! with the same nonce and higher fees: `speed_up_transaction` resends the
+//! same call, `cancel_transaction` sends a zero-value transfer to the
+//! account itself. Nodes only accept the replacement when every fee is at
+//! least `MIN_FEE_BUMP_PERCENT` above the transaction it replaces (the gas
+//! price of legacy transactions, both the max fee and the priority fee of
+//! EIP-1559 ones).
+//!
+//! Only one of the transactions sharing the nonce is mined, the
+//! `ReplaceableTransaction` keeps every hash sent so `wait_for_replacement`
+//! can tell which one.
+
+use crate::gas::TransactionFees;
+use crate::{Web3Manager, RECEIPT_POLL_INTERVAL};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use web3::types::{
+    BlockNumber, Bytes, Transaction, TransactionId, TransactionParameters, TransactionReceipt,
+    H160, H256, U256, U64,
+};
+
+/// Minimum bump geth and most clients require to replace a pending
+/// transaction.
+pub const MIN_FEE_BUMP_PERCENT: u64 = 10;
+
+const TRANSFER_GAS: u64 = 21_000;
+
+// fee + percent%, rounded up so the node's threshold is always met
+fn bump(fee: U256, percent: u64) -> U256 {
+    let increase = (fee * U256::from(percent) + U256::from(99)) / U256::from(100);
+    fee + increase.max(U256::one())
+}
+
+impl TransactionFees {
+    /// Every fee raised by `percent`, never less than `MIN_FEE_BUMP_PERCENT`.
+    pub fn bumped(&self, percent: u64) -> TransactionFees {
+        let percent = percent.max(MIN_FEE_BUMP_PERCENT);
+        match *self {
+            TransactionFees::Legacy { gas_price } => TransactionFees::Legacy {
+                gas_price: bump(gas_price, percent),
+            },
+            TransactionFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => TransactionFees::Eip1559 {
+                max_fee_per_gas: bump(max_fee_per_gas, percent),
+                max_priority_fee_per_gas: bump(max_priority_fee_per_gas, percent),
+            },
+        }
+    }
+
+    /// Whether a node accepts a transaction with these fees as a replacement
+    /// of one with `replaced` fees.
+    pub fn replaces(&self, replaced: &TransactionFees) -> bool {
+        let enough = |new: U256, old: U256| {
+            new * U256::from(100) >= old * U256::from(100 + MIN_FEE_BUMP_PERCENT)
+        };
+        match (*self, *replaced) {
+            (
+                TransactionFees::Legacy { gas_price },
+                TransactionFees::Legacy {
+                    gas_price: replaced,
+                },
+            ) => enough(gas_price, replaced),
+            (
+                TransactionFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                TransactionFees::Eip1559 {
+                    max_fee_per_gas: replaced_max_fee,
+                    max_priority_fee_per_gas: replaced_priority_fee,
+                },
+            ) => {
+                enough(max_fee_per_gas, replaced_max_fee)
+                    && enough(max_priority_fee_per_gas, replaced_priority_fee)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Every transaction sent with the same account and nonce, the original
+/// first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplaceableTransaction {
+    pub account: H160,
+    pub nonce: U256,
+    pub hashes: Vec<H256>,
+}
+
+impl ReplaceableTransaction {
+    /// Last transaction sent, the one the next replacement replaces.
+    pub fn latest(&self) -> H256 {
+        *self
+            .hashes
+            .last()
+            .expect("the original hash is always kept")
+    }
+}
+
+impl Web3Manager {
+    /// Starts tracking a pending transaction so it can be replaced.
+    pub async fn replaceable_transaction(
+        &self,
+        tx_hash: H256,
+    ) -> Result<ReplaceableTransaction, Box<dyn Error>> {
+        let transaction = self.pending_transaction(tx_hash).await?;
+        Ok(ReplaceableTransaction {
+            account: transaction
+                .from
+                .ok_or_else(|| format!("transaction {:?} has no sender", tx_hash))?,
+            nonce: transaction.nonce,
+            hashes: vec![tx_hash],
+        })
+    }
+
+    async fn pending_transaction(&self, tx_hash: H256) -> Result<Transaction, Box<dyn Error>> {
+        let transaction = self
+            .web3http
+            .eth()
+            .transaction(TransactionId::Hash(tx_hash))
+            .await?
+            .ok_or_else(|| format!("transaction {:?} not found", tx_hash))?;
+        if transaction.block_number.is_some() {
+            return Err(format!("transaction {:?} is already mined", tx_hash).into());
+        }
+        Ok(transaction)
+    }
+
+    /// Resends the latest transaction of `pending` with its fees bumped by
+    /// `bump_percent`.
+    pub async fn speed_up_transaction(
+        &self,
+        pending: &mut ReplaceableTransaction,
+        bump_percent: u64,
+    ) -> Result<H256, Box<dyn Error>> {
+        let transaction = self.pending_transaction(pending.latest()).await?;
+        let parameters = TransactionParameters {
+            to: transaction.to,
+            value: transaction.value,
+            data: transaction.input.clone(),
+            gas: transaction.gas,
+            transaction_type: transaction.transaction_type,
+            access_list: transaction.access_list.clone(),
+            ..Default::default()
+        };
+        self.send_replacement(pending, &transaction, parameters, bump_percent)
+            .await
+    }
+
+    /// Replaces the latest transaction of `pending` with a zero-value
+    /// transfer to its own account, with fees bumped by `bump_percent`.
+    pub async fn cancel_transaction(
+        &self,
+        pending: &mut ReplaceableTransaction,
+        bump_percent: u64,
+    ) -> Result<H256, Box<dyn Error>> {
+        let transaction = self.pending_transaction(pending.latest()).await?;
+        let parameters = TransactionParameters {
+            to: Some(pending.account),
+            value: U256::zero(),
+            data: Bytes::default(),
+            gas: U256::from(TRANSFER_GAS),
+            transaction_type: transaction.transaction_type,
+            ..Default::default()
+        };
+        self.send_replacement(pending, &transaction, parameters, bump_percent)
+            .await
+    }
+
+    async fn send_replacement(
+        &self,
+        pending: &mut ReplaceableTransaction,
+        replaced: &Transaction,
+        mut parameters: TransactionParameters,
+        bump_percent: u64,
+    ) -> Result<H256, Box<dyn Error>> {
+        let mut fees = TransactionFees::of(replaced)?.bumped(bump_percent);
+        // a legacy replacement is pointless below the current gas price
+        if let TransactionFees::Legacy { gas_price } = fees {
+            let network_gas_price = self.web3http.eth().gas_price().await?;
+            fees = TransactionFees::Legacy {
+                gas_price: gas_price.max(network_gas_price),
+            };
+        }
+        fees.apply(&mut parameters);
+        parameters.nonce = Some(pending.nonce);
+
+        let signed = self.sign_transaction(pending.account, parameters).await?;
+        let tx_hash = self
+            .send_raw_transaction_tracked(signed.raw_transaction)
+            .await?;
+        pending.hashes.push(tx_hash);
+        Ok(tx_hash)
+    }
+
+    // Receipt of the transaction of `pending` that was mined, if any
+    async fn mined_replacement(
+        &self,
+        pending: &ReplaceableTransaction,
+    ) -> Result<Option<(H256, TransactionReceipt)>, Box<dyn Error>> {
+        let mined_nonce = self
+            .web3http
+            .eth()
+            .transaction_count(pending.account, Some(BlockNumber::Latest))
+            .await?;
+        for tx_hash in &pending.hashes {
+            if let Some(receipt) = self.web3http.eth().transaction_receipt(*tx_hash).await? {
+                self.record_gas_cost(&receipt).await;
+                if receipt.status == Some(U64::from(0)) {
+                    return Err(format!("transaction {:?} reverted", tx_hash).into());
+                }
+                return Ok(Some((*tx_hash, receipt)));
+            }
+        }
+        // the nonce is read before the receipts, so a tracked transaction
+        // mined in between is never mistaken for another one
+        if mined_nonce > pending.nonce {
+            return Err(format!(
+                "nonce {} of {:?} was used by another transaction",
+                pending.nonce, pending.account
+            )
+            .into());
+        }
+        Ok(None)
+    }
+
+    /// Waits until one of the transactions of `pending` is mined and returns
+    /// its hash and receipt. Fails on timeout, when the mined one reverted or
+    /// when the nonce was used by a transaction that isn't tracked.
+    pub async fn wait_for_replacement(
+        &self,
+        pending: &ReplaceableTransaction,
+        timeout: Duration,
+    ) -> Result<(H256, TransactionReceipt), Box<dyn Error>> {
+        let started = Instant::now();
+        loop {
+            if let Some(mined) = self.mined_replacement(pending).await? {
+                return Ok(mined);
+            }
+            if started.elapsed() > timeout {
+                return Err(
+                    format!("none of {:?} mined after {:?}", pending.hashes, timeout).into(),
+                );
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits `timeout` for `tx_hash` and speeds it up by `bump_percent` each
+    /// time it isn't mined by then, at most `max_bumps` times.
+    pub async fn wait_or_speed_up(
+        &self,
+        tx_hash: H256,
+        timeout: Duration,
+        bump_percent: u64,
+        max_bumps: usize,
+    ) -> Result<(H256, TransactionReceipt), Box<dyn Error>> {
+        let mut pending = match self.replaceable_transaction(tx_hash).await {
+            Ok(pending) => pending,
+            Err(err) => {
+                // mined before it could be tracked
+                if self
+                    .web3http
+                    .eth()
+                    .transaction_receipt(tx_hash)
+                    .await?
+                    .is_some()
+                {
+                    return Ok((tx_hash, self.wait_for_receipt(tx_hash, timeout).await?));
+                }
+                return Err(err);
+            }
+        };
+        let mut bumps = 0;
+        let mut started = Instant::now();
+        loop {
+            if let Some(mined) = self.mined_replacement(&pending).await? {
+                return Ok(mined);
+            }
+            if started.elapsed() > timeout {
+                if bumps == max_bumps {
+                    return Err(
+                        format!("none of {:?} mined after {:?}", pending.hashes, timeout).into(),
+                    );
+                }
+                if let Err(err) = self.speed_up_transaction(&mut pending, bump_percent).await {
+                    // mined while the replacement was built
+                    return match self.mined_replacement(&pending).await? {
+                        Some(mined) => Ok(mined),
+                        None => Err(err),
+                    };
+                }
+                bumps += 1;
+                started = Instant::now();
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bumps_legacy_fees_over_the_threshold() {
+        let fees = TransactionFees::Legacy {
+            gas_price: U256::from(5_000_000_001u64),
+        };
+        let bumped = fees.bumped(MIN_FEE_BUMP_PERCENT);
+        assert_eq!(
+            bumped,
+            TransactionFees::Legacy {
+                gas_price: U256::from(5_500_000_002u64)
+            }
+        );
+        assert!(bumped.replaces(&fees));
+        assert!(!fees.replaces(&fees));
+
+        // smaller bumps are raised to the minimum, zero fees still increase
+        assert_eq!(fees.bumped(1), bumped);
+        let free = TransactionFees::Legacy {
+            gas_price: U256::zero(),
+        };
+        assert!(free.bumped(10).replaces(&free));
+    }
+
+    #[test]
+    fn bumps_both_eip1559_fees() {
+        let fees = TransactionFees::Eip1559 {
+            max_fee_per_gas: U256::from(100),
+            max_priority_fee_per_gas: U256::from(3),
+        };
+        let bumped = fees.bumped(25);
+        assert_eq!(
+            bumped,
+            TransactionFees::Eip1559 {
+                max_fee_per_gas: U256::from(125),
+                max_priority_fee_per_gas: U256::from(4),
+            }
+        );
+        assert!(bumped.replaces(&fees));
+
+        // only the max fee raised
+        let tip_unchanged = TransactionFees::Eip1559 {
+            max_fee_per_gas: U256::from(200),
+            max_priority_fee_per_gas: U256::from(3),
+        };
+        assert!(!tip_unchanged.replaces(&fees));
+        assert!(!TransactionFees::Legacy {
+            gas_price: U256::from(1_000)
+        }
+        .replaces(&fees));
+    }
+}
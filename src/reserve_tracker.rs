@@ -0,0 +1,229 @@
+//! Pair reserves kept current from `Sync` logs, so prices can be read
+//! without a `getReserves`/`getAmountsOut` call per lookup.
+
+use crate::amm;
+use crate::bindings::PancakePair;
+use crate::Web3Manager;
+use futures::StreamExt;
+use std::error::Error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use web3::api::SubscriptionStream;
+use web3::transports::WebSocket;
+use web3::types::{FilterBuilder, Log, H160, U256, U64};
+
+/// Reserves of a pair as of `block_number`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReserveSnapshot {
+    pub pair: H160,
+    pub token0: H160,
+    pub token1: H160,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub block_number: Option<U64>,
+}
+
+impl ReserveSnapshot {
+    pub fn has_liquidity(&self) -> bool {
+        !self.reserve0.is_zero() && !self.reserve1.is_zero()
+    }
+
+    /// `(reserve_in, reserve_out)` when selling `token_in`, `None` if the
+    /// token isn't in the pair.
+    pub fn reserves_for(&self, token_in: H160) -> Option<(U256, U256)> {
+        if token_in == self.token0 {
+            Some((self.reserve0, self.reserve1))
+        } else if token_in == self.token1 {
+            Some((self.reserve1, self.reserve0))
+        } else {
+            None
+        }
+    }
+
+    /// Same as the router `getAmountsOut` for a single hop through this pair.
+    pub fn amount_out(&self, token_in: H160, amount_in: U256, fee_bps: u32) -> Option<U256> {
+        let (reserve_in, reserve_out) = self.reserves_for(token_in)?;
+        Some(amm::get_amount_out(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            fee_bps,
+        ))
+    }
+
+    /// Same as the router `getAmountsIn` for a single hop through this pair.
+    pub fn amount_in(&self, token_in: H160, amount_out: U256, fee_bps: u32) -> Option<U256> {
+        let (reserve_in, reserve_out) = self.reserves_for(token_in)?;
+        amm::get_amount_in(amount_out, reserve_in, reserve_out, fee_bps)
+    }
+
+    /// Applies the reserves of a `Sync` log. Logs older than the snapshot are
+    /// ignored; returns whether the snapshot changed.
+    pub fn apply_sync(
+        &mut self,
+        reserve0: U256,
+        reserve1: U256,
+        block_number: Option<U64>,
+    ) -> bool {
+        if let (Some(current), Some(block)) = (self.block_number, block_number) {
+            if block < current {
+                return false;
+            }
+        }
+        self.reserve0 = reserve0;
+        self.reserve1 = reserve1;
+        if block_number.is_some() {
+            self.block_number = block_number;
+        }
+        true
+    }
+}
+
+/// Follows the `Sync` logs of a pair in a background task. The task stops
+/// when the tracker is dropped.
+#[derive(Debug)]
+pub struct ReserveTracker {
+    updates: watch::Receiver<ReserveSnapshot>,
+    task: JoinHandle<()>,
+}
+
+impl ReserveTracker {
+    /// Latest reserves, read from memory.
+    pub fn snapshot(&self) -> ReserveSnapshot {
+        *self.updates.borrow()
+    }
+
+    /// Waits for the next reserve update and returns it. Fails once the
+    /// tracker stopped.
+    pub async fn changed(&mut self) -> Result<ReserveSnapshot, watch::error::RecvError> {
+        self.updates.changed().await?;
+        Ok(self.snapshot())
+    }
+
+    /// False once the log subscription has ended, the snapshot won't be
+    /// updated anymore.
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for ReserveTracker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn follow_sync_logs(
+    pair: PancakePair,
+    mut logs: SubscriptionStream<WebSocket, Log>,
+    updates: watch::Sender<ReserveSnapshot>,
+) {
+    while let Some(Ok(log)) = logs.next().await {
+        let mut snapshot = *updates.borrow();
+
+        // a reorg dropped this log, read the reserves again from the node
+        if log.removed == Some(true) {
+            if let Ok(reserves) = pair.get_reserves().await {
+                snapshot.reserve0 = reserves.reserve0;
+                snapshot.reserve1 = reserves.reserve1;
+                let _ = updates.send(snapshot);
+            }
+            continue;
+        }
+
+        if let Ok(sync) = pair.decode_sync(&log) {
+            if snapshot.apply_sync(sync.reserve0, sync.reserve1, log.block_number) {
+                let _ = updates.send(snapshot);
+            }
+        }
+    }
+}
+
+impl Web3Manager {
+    /// Seeds the reserves of `pair_address` once and keeps them current from
+    /// its `Sync` logs.
+    pub async fn track_reserves(
+        &self,
+        pair_address: H160,
+    ) -> Result<ReserveTracker, Box<dyn Error>> {
+        let pair = self.pair_bindings(pair_address);
+        let filter = FilterBuilder::default()
+            .address(vec![pair_address])
+            .topics(Some(vec![pair.sync_topic()]), None, None, None)
+            .build();
+        let logs = self
+            .web3web_socket
+            .eth_subscribe()
+            .subscribe_logs(filter)
+            .await?;
+
+        // seed after subscribing so no Sync is lost in between
+        let block_number = self.web3http.eth().block_number().await?;
+        let reserves = pair.get_reserves().await?;
+        let (sender, updates) = watch::channel(ReserveSnapshot {
+            pair: pair_address,
+            token0: pair.token0().await?,
+            token1: pair.token1().await?,
+            reserve0: reserves.reserve0,
+            reserve1: reserves.reserve1,
+            block_number: Some(block_number),
+        });
+
+        let task = tokio::spawn(follow_sync_logs(pair, logs, sender));
+        Ok(ReserveTracker { updates, task })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> ReserveSnapshot {
+        ReserveSnapshot {
+            pair: H160::repeat_byte(3),
+            token0: H160::repeat_byte(1),
+            token1: H160::repeat_byte(2),
+            reserve0: U256::exp10(21),
+            reserve1: U256::exp10(20),
+            block_number: Some(U64::from(100)),
+        }
+    }
+
+    #[test]
+    fn ignores_older_sync_logs() {
+        let mut snapshot = snapshot();
+        assert!(!snapshot.apply_sync(U256::one(), U256::one(), Some(U64::from(99))));
+        assert_eq!(snapshot.reserve0, U256::exp10(21));
+
+        assert!(snapshot.apply_sync(U256::from(5), U256::from(7), Some(U64::from(101))));
+        assert_eq!(
+            (snapshot.reserve0, snapshot.reserve1),
+            (U256::from(5), U256::from(7))
+        );
+        assert_eq!(snapshot.block_number, Some(U64::from(101)));
+    }
+
+    #[test]
+    fn prices_either_direction() {
+        let snapshot = snapshot();
+        let token0 = snapshot.token0;
+        let token1 = snapshot.token1;
+
+        assert_eq!(
+            snapshot.reserves_for(token1),
+            Some((U256::exp10(20), U256::exp10(21)))
+        );
+        assert_eq!(snapshot.amount_out(H160::zero(), U256::one(), 25), None);
+
+        let out = snapshot.amount_out(token0, U256::exp10(18), 25).unwrap();
+        assert_eq!(
+            out,
+            amm::get_amount_out(U256::exp10(18), U256::exp10(21), U256::exp10(20), 25)
+        );
+        assert!(snapshot.amount_in(token1, out, 25).unwrap() > U256::zero());
+    }
+}
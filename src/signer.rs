@@ -0,0 +1,610 @@
+//! Signers behind the accounts of `Web3Manager`.
+//!
+//! Every loaded account is backed by a `Signer`: a private key held in
+//! memory (`LocalSigner`), an encrypted JSON keystore (`KeystoreSigner`) or
+//! a remote signer such as Clef or a KMS gateway reached over JSON-RPC
+//! (`RemoteSigner`). Transactions handed to a signer are complete, the
+//! manager fills the nonce, gas price and chain id before dispatching.
+
+use crate::message_signing::{recover_digest, RecoverableSignature, TypedData};
+use crate::Web3Manager;
+use async_trait::async_trait;
+use jsonrpc_core::Call;
+use secp256k1::SecretKey;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use web3::signing::{hash_message, keccak256, Key};
+use web3::transports::Http;
+use web3::types::{Bytes, SignedTransaction, TransactionParameters, H160, H256, U256, U64};
+use web3::{RequestId, Transport};
+
+const EIP1559_TX_TYPE: u64 = 2;
+
+#[derive(Debug, Error)]
+pub enum SignerError {
+    #[error("no signer loaded for account {0:?}")]
+    UnknownAccount(H160),
+    #[error("invalid private key: {0}")]
+    InvalidKey(String),
+    #[error("transaction needs a nonce, a gas price and a chain id to be signed")]
+    IncompleteTransaction,
+    #[error("keystore error: {0}")]
+    Keystore(#[from] eth_keystore::KeystoreError),
+    #[error("remote signer error: {0}")]
+    Rpc(#[from] web3::Error),
+    #[error("invalid remote signer response: {0}")]
+    InvalidResponse(String),
+    #[error("signed by {actual:?} instead of {expected:?}")]
+    AddressMismatch { expected: H160, actual: H160 },
+    #[error("invalid typed data: {0}")]
+    TypedData(String),
+    #[error("signing failed: {0}")]
+    Signing(String),
+}
+
+#[async_trait]
+pub trait Signer: fmt::Debug + Send + Sync {
+    fn address(&self) -> H160;
+
+    async fn sign_transaction(
+        &self,
+        tx: TransactionParameters,
+    ) -> Result<SignedTransaction, SignerError>;
+
+    /// EIP-191 `personal_sign`.
+    async fn sign_message(&self, message: &[u8]) -> Result<RecoverableSignature, SignerError>;
+
+    /// EIP-712 `eth_signTypedData_v4`.
+    async fn sign_typed_data(
+        &self,
+        typed_data: &TypedData,
+    ) -> Result<RecoverableSignature, SignerError>;
+}
+
+fn is_complete(tx: &TransactionParameters) -> bool {
+    let has_fee = tx.gas_price.is_some()
+        || (tx.transaction_type == Some(U64::from(EIP1559_TX_TYPE))
+            && tx.max_fee_per_gas.is_some());
+    tx.nonce.is_some() && tx.chain_id.is_some() && has_fee
+}
+
+// Transport for signing complete transactions with `Accounts::sign_transaction`
+// without a node, any request it would make fails
+#[derive(Clone, Debug)]
+struct OfflineTransport;
+
+impl Transport for OfflineTransport {
+    type Out = futures::future::Ready<web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        (0, web3::helpers::build_request(0, method, params))
+    }
+
+    fn send(&self, _id: RequestId, _request: Call) -> Self::Out {
+        futures::future::ready(Err(web3::Error::Unreachable))
+    }
+}
+
+/// Private key held in memory.
+#[derive(Clone)]
+pub struct LocalSigner {
+    key: SecretKey,
+    address: H160,
+}
+
+impl LocalSigner {
+    pub fn new(key: SecretKey) -> LocalSigner {
+        LocalSigner {
+            address: (&key).address(),
+            key,
+        }
+    }
+
+    pub(crate) fn sign_digest(&self, digest: H256) -> Result<RecoverableSignature, SignerError> {
+        let signature = (&self.key)
+            .sign(digest.as_bytes(), None)
+            .map_err(|err| SignerError::Signing(err.to_string()))?;
+        Ok(signature.into())
+    }
+}
+
+impl FromStr for LocalSigner {
+    type Err = SignerError;
+
+    /// Hex private key, with or without `0x`.
+    fn from_str(plain_private_key: &str) -> Result<LocalSigner, SignerError> {
+        let key = SecretKey::from_str(plain_private_key.trim_start_matches("0x"))
+            .map_err(|err| SignerError::InvalidKey(err.to_string()))?;
+        Ok(LocalSigner::new(key))
+    }
+}
+
+// the key is never printed
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSigner")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: TransactionParameters,
+    ) -> Result<SignedTransaction, SignerError> {
+        if !is_complete(&tx) {
+            return Err(SignerError::IncompleteTransaction);
+        }
+        Ok(web3::Web3::new(OfflineTransport)
+            .accounts()
+            .sign_transaction(tx, &self.key)
+            .await?)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<RecoverableSignature, SignerError> {
+        self.sign_digest(hash_message(message))
+    }
+
+    async fn sign_typed_data(
+        &self,
+        typed_data: &TypedData,
+    ) -> Result<RecoverableSignature, SignerError> {
+        let digest = typed_data
+            .signing_hash()
+            .map_err(|err| SignerError::TypedData(err.to_string()))?;
+        self.sign_digest(digest)
+    }
+}
+
+/// Web3 Secret Storage (v3) keystore, decrypted once when opened.
+#[derive(Clone)]
+pub struct KeystoreSigner {
+    path: PathBuf,
+    signer: LocalSigner,
+}
+
+impl KeystoreSigner {
+    pub fn open<P: AsRef<Path>>(path: P, password: &str) -> Result<KeystoreSigner, SignerError> {
+        let key = eth_keystore::decrypt_key(path.as_ref(), password)?;
+        let key =
+            SecretKey::from_slice(&key).map_err(|err| SignerError::InvalidKey(err.to_string()))?;
+        Ok(KeystoreSigner {
+            path: path.as_ref().to_path_buf(),
+            signer: LocalSigner::new(key),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl fmt::Debug for KeystoreSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeystoreSigner")
+            .field("path", &self.path)
+            .field("address", &self.signer.address)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Signer for KeystoreSigner {
+    fn address(&self) -> H160 {
+        self.signer.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: TransactionParameters,
+    ) -> Result<SignedTransaction, SignerError> {
+        self.signer.sign_transaction(tx).await
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<RecoverableSignature, SignerError> {
+        self.signer.sign_message(message).await
+    }
+
+    async fn sign_typed_data(
+        &self,
+        typed_data: &TypedData,
+    ) -> Result<RecoverableSignature, SignerError> {
+        self.signer.sign_typed_data(typed_data).await
+    }
+}
+
+/// Account held by a remote signer speaking JSON-RPC (`eth_signTransaction`,
+/// `personal_sign`, `eth_signTypedData_v4`), such as Clef.
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    address: H160,
+    transport: Http,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, address: H160) -> Result<RemoteSigner, SignerError> {
+        Ok(RemoteSigner {
+            address,
+            transport: Http::new(url)?,
+        })
+    }
+
+    fn parse_signature(&self, value: Value) -> Result<RecoverableSignature, SignerError> {
+        let signature = value
+            .as_str()
+            .ok_or_else(|| SignerError::InvalidResponse(value.to_string()))?;
+        RecoverableSignature::from_str(signature)
+            .map_err(|err| SignerError::InvalidResponse(err.to_string()))
+    }
+
+    // the remote signer must not sign with another account
+    fn check_signer(
+        &self,
+        digest: H256,
+        signature: &RecoverableSignature,
+    ) -> Result<(), SignerError> {
+        let actual = recover_digest(digest, signature)
+            .map_err(|err| SignerError::InvalidResponse(err.to_string()))?;
+        if actual != self.address {
+            return Err(SignerError::AddressMismatch {
+                expected: self.address,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: TransactionParameters,
+    ) -> Result<SignedTransaction, SignerError> {
+        if !is_complete(&tx) {
+            return Err(SignerError::IncompleteTransaction);
+        }
+        let mut request = json!({
+            "from": self.address,
+            "gas": tx.gas,
+            "value": tx.value,
+            "data": tx.data,
+            "nonce": tx.nonce,
+            "chainId": tx.chain_id.map(U256::from),
+        });
+        if let Some(to) = tx.to {
+            request["to"] = json!(to);
+        }
+        if let Some(transaction_type) = tx.transaction_type {
+            request["type"] = json!(transaction_type);
+        }
+        if let Some(gas_price) = tx.gas_price {
+            request["gasPrice"] = json!(gas_price);
+        }
+        if let Some(max_fee_per_gas) = tx.max_fee_per_gas {
+            request["maxFeePerGas"] = json!(max_fee_per_gas);
+        }
+        if let Some(max_priority_fee_per_gas) = tx.max_priority_fee_per_gas {
+            request["maxPriorityFeePerGas"] = json!(max_priority_fee_per_gas);
+        }
+        if let Some(access_list) = tx.access_list {
+            request["accessList"] = json!(access_list);
+        }
+
+        let response = self
+            .transport
+            .execute("eth_signTransaction", vec![request])
+            .await?;
+        // geth and clef answer `{raw, tx}`, other signers only the raw bytes
+        let raw = match &response {
+            Value::String(raw) => raw,
+            Value::Object(object) => object
+                .get("raw")
+                .and_then(Value::as_str)
+                .ok_or_else(|| SignerError::InvalidResponse(response.to_string()))?,
+            _ => return Err(SignerError::InvalidResponse(response.to_string())),
+        };
+        let raw = hex::decode(raw.trim_start_matches("0x"))
+            .map_err(|err| SignerError::InvalidResponse(err.to_string()))?;
+
+        let signed = signed_transaction_from_raw(raw)?;
+        self.check_signer(
+            signed.message_hash,
+            &RecoverableSignature {
+                r: signed.r,
+                s: signed.s,
+                v: recovery_id(signed.v)? + 27,
+            },
+        )?;
+        Ok(signed)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<RecoverableSignature, SignerError> {
+        let response = self
+            .transport
+            .execute(
+                "personal_sign",
+                vec![json!(Bytes(message.to_vec())), json!(self.address)],
+            )
+            .await?;
+        let signature = self.parse_signature(response)?;
+        self.check_signer(hash_message(message), &signature)?;
+        Ok(signature)
+    }
+
+    async fn sign_typed_data(
+        &self,
+        typed_data: &TypedData,
+    ) -> Result<RecoverableSignature, SignerError> {
+        let digest = typed_data
+            .signing_hash()
+            .map_err(|err| SignerError::TypedData(err.to_string()))?;
+        let document = serde_json::to_string(typed_data)
+            .map_err(|err| SignerError::TypedData(err.to_string()))?;
+        let response = self
+            .transport
+            .execute(
+                "eth_signTypedData_v4",
+                vec![json!(self.address), json!(document)],
+            )
+            .await?;
+        let signature = self.parse_signature(response)?;
+        self.check_signer(digest, &signature)?;
+        Ok(signature)
+    }
+}
+
+// 0 or 1 from a legacy (27/28 or EIP-155) or typed transaction `v`
+fn recovery_id(v: u64) -> Result<u8, SignerError> {
+    match v {
+        0 | 1 => Ok(v as u8),
+        27 | 28 => Ok((v - 27) as u8),
+        35.. => Ok(((v - 35) % 2) as u8),
+        _ => Err(SignerError::InvalidResponse(format!(
+            "invalid signature v {}",
+            v
+        ))),
+    }
+}
+
+/// Signature, signing hash and hash of a raw signed transaction, legacy or
+/// EIP-2718 typed.
+pub fn signed_transaction_from_raw(raw: Vec<u8>) -> Result<SignedTransaction, SignerError> {
+    let invalid = |err: rlp::DecoderError| SignerError::InvalidResponse(err.to_string());
+    let first = *raw
+        .first()
+        .ok_or_else(|| SignerError::InvalidResponse("empty transaction".to_string()))?;
+
+    let (payload, transaction_type) = if first >= 0xc0 {
+        (&raw[..], None)
+    } else {
+        (&raw[1..], Some(first))
+    };
+    let rlp = rlp::Rlp::new(payload);
+    let item_count = rlp.item_count().map_err(invalid)?;
+    if item_count < 9 {
+        return Err(SignerError::InvalidResponse(format!(
+            "transaction has {} fields",
+            item_count
+        )));
+    }
+    let v: u64 = rlp.val_at(item_count - 3).map_err(invalid)?;
+    let r: U256 = rlp.val_at(item_count - 2).map_err(invalid)?;
+    let s: U256 = rlp.val_at(item_count - 1).map_err(invalid)?;
+
+    // signing payload: the fields before the signature, legacy EIP-155
+    // transactions append (chainId, 0, 0)
+    let mut unsigned = rlp::RlpStream::new();
+    let replay_protected = transaction_type.is_none() && v >= 35;
+    let field_count = item_count - 3;
+    unsigned.begin_list(if replay_protected {
+        field_count + 3
+    } else {
+        field_count
+    });
+    for index in 0..field_count {
+        unsigned.append_raw(rlp.at(index).map_err(invalid)?.as_raw(), 1);
+    }
+    if replay_protected {
+        unsigned.append(&((v - 35) / 2));
+        unsigned.append_empty_data();
+        unsigned.append_empty_data();
+    }
+    let mut signing_payload = transaction_type.map(|t| vec![t]).unwrap_or_default();
+    signing_payload.extend_from_slice(&unsigned.out());
+
+    let mut r_bytes = [0u8; 32];
+    r.to_big_endian(&mut r_bytes);
+    let mut s_bytes = [0u8; 32];
+    s.to_big_endian(&mut s_bytes);
+    Ok(SignedTransaction {
+        message_hash: H256(keccak256(&signing_payload)),
+        v,
+        r: H256(r_bytes),
+        s: H256(s_bytes),
+        transaction_hash: H256(keccak256(&raw)),
+        raw_transaction: Bytes(raw),
+    })
+}
+
+/// Address that signed a transaction.
+pub fn transaction_sender(signed: &SignedTransaction) -> Result<H160, SignerError> {
+    let signature = RecoverableSignature {
+        r: signed.r,
+        s: signed.s,
+        v: recovery_id(signed.v)? + 27,
+    };
+    recover_digest(signed.message_hash, &signature)
+        .map_err(|err| SignerError::Signing(err.to_string()))
+}
+
+impl Web3Manager {
+    /// Adds `signer` as a loaded account and syncs the nonce with it.
+    pub async fn load_signer<S: Signer + 'static>(&mut self, signer: S) -> H160 {
+        let account = signer.address();
+        self.signers.insert(account, Arc::new(signer));
+        if !self.accounts.contains(&account) {
+            self.accounts.push(account);
+        }
+
+        let nonce = self.last_nonce(account).await;
+        self.set_current_nonce(nonce);
+        account
+    }
+
+    pub fn signer(&self, account: H160) -> Result<Arc<dyn Signer>, SignerError> {
+        self.signers
+            .get(&account)
+            .cloned()
+            .ok_or(SignerError::UnknownAccount(account))
+    }
+
+    pub fn has_signer(&self, account: H160) -> bool {
+        self.signers.contains_key(&account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::mock_rpc_server;
+
+    fn local_signer() -> LocalSigner {
+        LocalSigner::from_str("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .unwrap()
+    }
+
+    fn transaction() -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(U256::from(3)),
+            to: Some(H160::from_low_u64_be(1)),
+            gas: U256::from(21_000),
+            gas_price: Some(U256::from(5_000_000_000u64)),
+            value: U256::from(10),
+            chain_id: Some(56),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_what_it_signs() {
+        let signer = local_signer();
+        let eip1559 = TransactionParameters {
+            transaction_type: Some(U64::from(EIP1559_TX_TYPE)),
+            max_fee_per_gas: Some(U256::from(30)),
+            max_priority_fee_per_gas: Some(U256::from(2)),
+            gas_price: None,
+            ..transaction()
+        };
+        for tx in [transaction(), eip1559] {
+            let signed = signer.sign_transaction(tx).await.unwrap();
+            let decoded = signed_transaction_from_raw(signed.raw_transaction.0.clone()).unwrap();
+            assert_eq!(decoded, signed);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_v() {
+        assert_eq!(recovery_id(1).unwrap(), 1);
+        assert_eq!(recovery_id(28).unwrap(), 1);
+        // EIP-155 on chain 56
+        assert_eq!(recovery_id(147).unwrap(), 0);
+        for v in [2, 26, 29, 34] {
+            assert!(matches!(
+                recovery_id(v),
+                Err(SignerError::InvalidResponse(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_incomplete_transactions() {
+        let tx = TransactionParameters {
+            nonce: None,
+            ..transaction()
+        };
+        assert!(matches!(
+            local_signer().sign_transaction(tx).await,
+            Err(SignerError::IncompleteTransaction)
+        ));
+    }
+
+    #[test]
+    fn opens_keystores() {
+        let dir = std::env::temp_dir().join(format!("keystore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = hex::decode("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .unwrap();
+        let name =
+            eth_keystore::encrypt_key(&dir, &mut rand::thread_rng(), &key, "secret", None).unwrap();
+
+        let signer = KeystoreSigner::open(dir.join(&name), "secret").unwrap();
+        assert_eq!(signer.address(), local_signer().address());
+        assert!(KeystoreSigner::open(dir.join(&name), "wrong").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn signs_through_remote_signer() {
+        let local = local_signer();
+        let signed = local.sign_transaction(transaction()).await.unwrap();
+        let message_signature = local.sign_message(b"hello").await.unwrap();
+
+        let raw = format!("0x{}", hex::encode(&signed.raw_transaction.0));
+        let message_signature_hex = message_signature.to_string();
+        let (url, requests) = mock_rpc_server(move |method, _| {
+            Ok(match method {
+                "eth_signTransaction" => json!({ "raw": raw, "tx": {} }),
+                "personal_sign" => json!(message_signature_hex),
+                _ => Value::Null,
+            })
+        })
+        .await;
+
+        let remote = RemoteSigner::new(&url, local.address()).unwrap();
+        assert_eq!(
+            remote.sign_transaction(transaction()).await.unwrap(),
+            signed
+        );
+        assert_eq!(
+            remote.sign_message(b"hello").await.unwrap(),
+            message_signature
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0]["method"], "eth_signTransaction");
+        assert_eq!(requests[0]["params"][0]["from"], json!(local.address()));
+        assert_eq!(requests[0]["params"][0]["nonce"], "0x3");
+        assert_eq!(requests[1]["params"][0], "0x68656c6c6f");
+    }
+
+    #[tokio::test]
+    async fn rejects_remote_signature_of_another_account() {
+        let signature = local_signer()
+            .sign_message(b"hello")
+            .await
+            .unwrap()
+            .to_string();
+        let (url, _) = mock_rpc_server(move |_, _| Ok(json!(signature))).await;
+
+        let remote = RemoteSigner::new(&url, H160::from_low_u64_be(7)).unwrap();
+        assert!(matches!(
+            remote.sign_message(b"hello").await,
+            Err(SignerError::AddressMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+//! Slippage tolerance in basis points (1 bps = 0.01%).
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use thiserror::Error;
+use web3::types::U256;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SlippageError {
+    #[error("slippage of {0} bps is over 10000 bps (100%)")]
+    OutOfRange(u32),
+    #[error("invalid slippage percent {0}")]
+    InvalidPercent(f64),
+}
+
+/// A validated slippage tolerance, at most 10000 bps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Slippage(u32);
+
+impl Slippage {
+    pub const MAX_BPS: u32 = 10_000;
+    pub const DEFAULT_BPS: u32 = 50;
+
+    pub fn from_bps(bps: u32) -> Result<Slippage, SlippageError> {
+        if bps > Slippage::MAX_BPS {
+            return Err(SlippageError::OutOfRange(bps));
+        }
+        Ok(Slippage(bps))
+    }
+
+    /// `0.5` is 0.5% (50 bps). Precision below one bps is rounded.
+    pub fn from_percent(percent: f64) -> Result<Slippage, SlippageError> {
+        if !percent.is_finite() || percent < 0.0 {
+            return Err(SlippageError::InvalidPercent(percent));
+        }
+        let bps = (percent * 100.0).round();
+        if bps > Slippage::MAX_BPS as f64 {
+            return Err(SlippageError::InvalidPercent(percent));
+        }
+        Ok(Slippage(bps as u32))
+    }
+
+    pub fn bps(self) -> u32 {
+        self.0
+    }
+
+    /// Lowest acceptable output for an expected `amount`.
+    pub fn min_out(self, amount: U256) -> U256 {
+        amount - amount * self.0 / Slippage::MAX_BPS
+    }
+
+    /// Highest acceptable input for an expected `amount`.
+    pub fn max_in(self, amount: U256) -> U256 {
+        amount + amount * self.0 / Slippage::MAX_BPS
+    }
+}
+
+impl Default for Slippage {
+    fn default() -> Slippage {
+        Slippage(Slippage::DEFAULT_BPS)
+    }
+}
+
+impl TryFrom<u32> for Slippage {
+    type Error = SlippageError;
+
+    fn try_from(bps: u32) -> Result<Slippage, SlippageError> {
+        Slippage::from_bps(bps)
+    }
+}
+
+impl From<Slippage> for u32 {
+    fn from(slippage: Slippage) -> u32 {
+        slippage.0
+    }
+}
+
+impl fmt::Display for Slippage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}%", self.0 / 100, self.0 % 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_range() {
+        assert_eq!(Slippage::from_bps(10_000).unwrap().bps(), 10_000);
+        assert_eq!(
+            Slippage::from_bps(10_001),
+            Err(SlippageError::OutOfRange(10_001))
+        );
+        assert_eq!(Slippage::from_percent(0.5).unwrap().bps(), 50);
+        assert!(Slippage::from_percent(-1.0).is_err());
+        assert!(Slippage::from_percent(f64::NAN).is_err());
+        assert!(Slippage::from_percent(100.5).is_err());
+    }
+
+    #[test]
+    fn applies_bounds() {
+        let slippage = Slippage::from_bps(50).unwrap();
+        assert_eq!(slippage.min_out(U256::from(10_000)), U256::from(9_950));
+        assert_eq!(slippage.max_in(U256::from(10_000)), U256::from(10_050));
+        assert_eq!(slippage.to_string(), "0.50%");
+    }
+
+    #[test]
+    fn serializes_as_bps() {
+        let slippage: Slippage = serde_json::from_str("75").unwrap();
+        assert_eq!(slippage.bps(), 75);
+        assert_eq!(serde_json::to_string(&slippage).unwrap(), "75");
+        assert!(serde_json::from_str::<Slippage>("20000").is_err());
+    }
+}
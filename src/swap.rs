@@ -0,0 +1,540 @@
+//! One entry point for router swaps: a `SwapRequest` describes the trade,
+//! `quote_swap` turns it into a `Quote` (amounts, bounds, price impact, gas)
+//! that can be executed, simulated with `eth_call`, or serialized.
+
+use crate::amm::{self, PANCAKE_V2_FEE_BPS};
+use crate::{AbiKind, Slippage, Web3Manager};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Duration;
+use web3::ethabi::Token;
+use web3::types::{Bytes, CallRequest, H160, H256, U256};
+
+/// Side of a swap, `Native` is the chain coin (ETH, BNB) wrapped by the
+/// router's `WETH()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Asset {
+    Native,
+    Token(H160),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapKind {
+    /// `amount` is spent, the output is at least the quote less slippage.
+    ExactIn,
+    /// `amount` is received, the input is at most the quote plus slippage.
+    ExactOut,
+}
+
+/// A router swap, built with `SwapRequest::exact_in`/`exact_out` and the
+/// setters below.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SwapRequest {
+    pub router: H160,
+    pub input: Asset,
+    pub output: Asset,
+    pub kind: SwapKind,
+    pub amount: U256,
+    /// Token path including both ends, `None` picks the best of the direct
+    /// pair and the route through WETH.
+    pub path: Option<Vec<H160>>,
+    /// The manager's `default_slippage` when not set.
+    pub slippage: Option<Slippage>,
+    /// Receiver of the output, the sending account by default.
+    pub recipient: Option<H160>,
+    /// Unix timestamp, overrides `deadline_window`.
+    pub deadline: Option<U256>,
+    /// Seconds after the latest block timestamp, the manager's
+    /// `deadline_window` when not set.
+    pub deadline_window: Option<Duration>,
+    /// Use the `SupportingFeeOnTransferTokens` router functions.
+    pub fee_on_transfer: bool,
+    /// Pool fee used for the price impact.
+    pub pool_fee_bps: u32,
+}
+
+impl SwapRequest {
+    pub fn exact_in(router: H160, input: Asset, output: Asset, amount_in: U256) -> SwapRequest {
+        SwapRequest::new(router, input, output, SwapKind::ExactIn, amount_in)
+    }
+
+    pub fn exact_out(router: H160, input: Asset, output: Asset, amount_out: U256) -> SwapRequest {
+        SwapRequest::new(router, input, output, SwapKind::ExactOut, amount_out)
+    }
+
+    fn new(router: H160, input: Asset, output: Asset, kind: SwapKind, amount: U256) -> SwapRequest {
+        SwapRequest {
+            router,
+            input,
+            output,
+            kind,
+            amount,
+            path: None,
+            slippage: None,
+            recipient: None,
+            deadline: None,
+            deadline_window: None,
+            fee_on_transfer: false,
+            pool_fee_bps: PANCAKE_V2_FEE_BPS,
+        }
+    }
+
+    pub fn path(mut self, path: Vec<H160>) -> SwapRequest {
+        self.path = Some(path);
+        self
+    }
+
+    pub fn slippage(mut self, slippage: Slippage) -> SwapRequest {
+        self.slippage = Some(slippage);
+        self
+    }
+
+    pub fn recipient(mut self, recipient: H160) -> SwapRequest {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: U256) -> SwapRequest {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn deadline_window(mut self, window: Duration) -> SwapRequest {
+        self.deadline_window = Some(window);
+        self
+    }
+
+    pub fn fee_on_transfer(mut self, fee_on_transfer: bool) -> SwapRequest {
+        self.fee_on_transfer = fee_on_transfer;
+        self
+    }
+
+    pub fn pool_fee_bps(mut self, pool_fee_bps: u32) -> SwapRequest {
+        self.pool_fee_bps = pool_fee_bps;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.amount.is_zero() {
+            return Err("swap amount is zero".into());
+        }
+        if self.input == self.output {
+            return Err("input and output are the same asset".into());
+        }
+        if self.pool_fee_bps >= Slippage::MAX_BPS {
+            return Err("pool fee must be under 10000 bps".into());
+        }
+        if let Some(path) = &self.path {
+            if path.len() < 2 {
+                return Err("swap path needs at least two tokens".into());
+            }
+        }
+        router_function(self.input, self.output, self.kind, self.fee_on_transfer)?;
+        Ok(())
+    }
+}
+
+/// Router function for a swap between `input` and `output`.
+pub fn router_function(
+    input: Asset,
+    output: Asset,
+    kind: SwapKind,
+    fee_on_transfer: bool,
+) -> Result<&'static str, Box<dyn Error>> {
+    let function = match (input, output, kind, fee_on_transfer) {
+        (Asset::Native, Asset::Native, _, _) => {
+            return Err("can't swap the native coin for itself".into())
+        }
+        (_, _, SwapKind::ExactOut, true) => {
+            return Err("fee on transfer tokens can only be swapped with an exact input".into())
+        }
+        (Asset::Native, _, SwapKind::ExactIn, false) => "swapExactETHForTokens",
+        (Asset::Native, _, SwapKind::ExactIn, true) => {
+            "swapExactETHForTokensSupportingFeeOnTransferTokens"
+        }
+        (Asset::Native, _, SwapKind::ExactOut, false) => "swapETHForExactTokens",
+        (_, Asset::Native, SwapKind::ExactIn, false) => "swapExactTokensForETH",
+        (_, Asset::Native, SwapKind::ExactIn, true) => {
+            "swapExactTokensForETHSupportingFeeOnTransferTokens"
+        }
+        (_, Asset::Native, SwapKind::ExactOut, false) => "swapTokensForExactETH",
+        (_, _, SwapKind::ExactIn, false) => "swapExactTokensForTokens",
+        (_, _, SwapKind::ExactIn, true) => "swapExactTokensForTokensSupportingFeeOnTransferTokens",
+        (_, _, SwapKind::ExactOut, false) => "swapTokensForExactTokens",
+    };
+    Ok(function)
+}
+
+/// A priced swap, ready to be sent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub router: H160,
+    pub function: String,
+    pub kind: SwapKind,
+    pub path: Vec<H160>,
+    /// Expected amounts at the current reserves.
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Bounds sent to the router after slippage.
+    pub amount_in_max: U256,
+    pub amount_out_min: U256,
+    pub price_impact_bps: u32,
+    /// `None` when the node can't estimate it yet, usually for a missing
+    /// allowance.
+    pub gas_estimate: Option<U256>,
+    pub recipient: H160,
+    pub deadline: U256,
+    /// `msg.value` of the transaction.
+    pub value: U256,
+    /// Whether the router pulls the first token of the path with
+    /// `transferFrom`, so it needs an allowance.
+    pub needs_allowance: bool,
+}
+
+impl Quote {
+    /// Router function arguments, in ABI order.
+    pub fn call_parameters(&self) -> Vec<Token> {
+        let path = Token::Array(self.path.iter().copied().map(Token::Address).collect());
+        let to = Token::Address(self.recipient);
+        let deadline = Token::Uint(self.deadline);
+
+        let native_in = !self.needs_allowance;
+        match (self.kind, native_in) {
+            (SwapKind::ExactIn, true) => vec![Token::Uint(self.amount_out_min), path, to, deadline],
+            (SwapKind::ExactIn, false) => vec![
+                Token::Uint(self.amount_in),
+                Token::Uint(self.amount_out_min),
+                path,
+                to,
+                deadline,
+            ],
+            (SwapKind::ExactOut, true) => vec![Token::Uint(self.amount_out), path, to, deadline],
+            (SwapKind::ExactOut, false) => vec![
+                Token::Uint(self.amount_out),
+                Token::Uint(self.amount_in_max),
+                path,
+                to,
+                deadline,
+            ],
+        }
+    }
+
+    /// Calldata of the router call.
+    pub fn transaction_data(&self) -> Result<Bytes, Box<dyn Error>> {
+        Ok(AbiKind::Router
+            .abi()
+            .function(&self.function)?
+            .encode_input(&self.call_parameters())?
+            .into())
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Quote, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+impl Web3Manager {
+    // Router amounts along `path`, `None` if the router can't price it
+    async fn amounts_along(&self, request: &SwapRequest, path: &[H160]) -> Option<Vec<U256>> {
+        let router = self.router_bindings(request.router);
+        let amounts = match request.kind {
+            SwapKind::ExactIn => router.get_amounts_out(request.amount, path.to_vec()).await,
+            SwapKind::ExactOut => router.get_amounts_in(request.amount, path.to_vec()).await,
+        };
+        amounts.ok().filter(|amounts| amounts.len() == path.len())
+    }
+
+    // (reserve_in, reserve_out) of every hop of `path`
+    async fn path_reserves(
+        &self,
+        router: H160,
+        path: &[H160],
+    ) -> Result<Vec<(U256, U256)>, Box<dyn Error>> {
+        let factory = self.factory_bindings(self.router_bindings(router).factory().await?);
+        let mut reserves = Vec::with_capacity(path.len() - 1);
+        for hop in path.windows(2) {
+            let pair = self.pair_bindings(factory.get_pair(hop[0], hop[1]).await?);
+            let pair_reserves = pair.get_reserves().await?;
+            if pair.token0().await? == hop[0] {
+                reserves.push((pair_reserves.reserve0, pair_reserves.reserve1));
+            } else {
+                reserves.push((pair_reserves.reserve1, pair_reserves.reserve0));
+            }
+        }
+        Ok(reserves)
+    }
+
+    /// Prices `request` for `account`.
+    pub async fn quote_swap(
+        &self,
+        account: H160,
+        request: &SwapRequest,
+    ) -> Result<Quote, Box<dyn Error>> {
+        request.validate()?;
+        let function = router_function(
+            request.input,
+            request.output,
+            request.kind,
+            request.fee_on_transfer,
+        )?;
+
+        let weth = self.router_bindings(request.router).weth().await?;
+        let resolve = |asset: Asset| match asset {
+            Asset::Native => weth,
+            Asset::Token(token) => token,
+        };
+        let (token_in, token_out) = (resolve(request.input), resolve(request.output));
+
+        let candidates = match &request.path {
+            Some(path) => {
+                if path.first() != Some(&token_in) || path.last() != Some(&token_out) {
+                    return Err("swap path doesn't start and end with the swapped assets".into());
+                }
+                vec![path.clone()]
+            }
+            None if token_in == weth || token_out == weth => vec![vec![token_in, token_out]],
+            None => vec![vec![token_in, token_out], vec![token_in, weth, token_out]],
+        };
+
+        // the best route gives the most output, or takes the least input
+        let mut best: Option<(Vec<H160>, Vec<U256>)> = None;
+        for path in candidates {
+            if let Some(amounts) = self.amounts_along(request, &path).await {
+                let better = match (&best, request.kind) {
+                    (None, _) => true,
+                    (Some((_, current)), SwapKind::ExactIn) => amounts.last() > current.last(),
+                    (Some((_, current)), SwapKind::ExactOut) => amounts[0] < current[0],
+                };
+                if better {
+                    best = Some((path, amounts));
+                }
+            }
+        }
+        let (path, amounts) = best.ok_or("no route with liquidity for this swap")?;
+
+        let amount_in = amounts[0];
+        let amount_out = amounts[amounts.len() - 1];
+        let slippage = request.slippage.unwrap_or(self.default_slippage);
+        let (amount_in_max, amount_out_min) = match request.kind {
+            SwapKind::ExactIn => (amount_in, slippage.min_out(amount_out)),
+            SwapKind::ExactOut => (slippage.max_in(amount_in), amount_out),
+        };
+        let deadline = match request.deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.chain_deadline_in(request.deadline_window.unwrap_or(self.deadline_window))
+                    .await?
+            }
+        };
+
+        let reserves = self.path_reserves(request.router, &path).await?;
+        let price_impact_bps =
+            amm::route_price_impact_bps(amount_in, amount_out, &reserves, request.pool_fee_bps);
+
+        let native_in = request.input == Asset::Native;
+        let mut quote = Quote {
+            router: request.router,
+            function: function.to_string(),
+            kind: request.kind,
+            path,
+            amount_in,
+            amount_out,
+            amount_in_max,
+            amount_out_min,
+            price_impact_bps,
+            gas_estimate: None,
+            recipient: request.recipient.unwrap_or(account),
+            deadline,
+            value: if native_in {
+                amount_in_max
+            } else {
+                U256::zero()
+            },
+            needs_allowance: !native_in,
+        };
+
+        quote.gas_estimate = self
+            .web3http
+            .eth()
+            .estimate_gas(swap_call_request(account, &quote)?, None)
+            .await
+            .ok();
+        Ok(quote)
+    }
+
+    /// Runs the quoted swap with `eth_call` and returns the router amounts,
+    /// empty for the fee on transfer functions that return nothing.
+    pub async fn simulate_swap(
+        &self,
+        account: H160,
+        quote: &Quote,
+    ) -> Result<Vec<U256>, Box<dyn Error>> {
+        let output = self
+            .web3http
+            .eth()
+            .call(swap_call_request(account, quote)?, None)
+            .await?;
+
+        let abi = AbiKind::Router.abi();
+        let tokens = abi.function(&quote.function)?.decode_output(&output.0)?;
+        Ok(match tokens.into_iter().next() {
+            Some(Token::Array(amounts)) => {
+                amounts.into_iter().filter_map(Token::into_uint).collect()
+            }
+            _ => Vec::new(),
+        })
+    }
+
+    /// Sends the quoted swap, approving the router first if needed.
+    pub async fn execute_swap(
+        &mut self,
+        account: H160,
+        quote: &Quote,
+    ) -> Result<H256, Box<dyn Error>> {
+        let router_address = format!("{:?}", quote.router);
+        if quote.needs_allowance {
+            let token_address = format!("{:?}", quote.path[0]);
+            self.ensure_allowance(
+                account,
+                &token_address,
+                &router_address,
+                quote.amount_in_max,
+            )
+            .await?;
+        }
+
+        let router_instance = self.cached_contract(quote.router, AbiKind::Router);
+        let parameters = quote.call_parameters();
+        self.sign_and_send_tx(
+            account,
+            &router_instance,
+            &quote.function,
+            &parameters.as_slice(),
+            quote.value,
+        )
+        .await
+    }
+
+    /// Quotes and sends `request` in one go.
+    pub async fn swap(
+        &mut self,
+        account: H160,
+        request: &SwapRequest,
+    ) -> Result<(Quote, H256), Box<dyn Error>> {
+        let quote = self.quote_swap(account, request).await?;
+        let tx_hash = self.execute_swap(account, &quote).await?;
+        Ok((quote, tx_hash))
+    }
+}
+
+fn swap_call_request(account: H160, quote: &Quote) -> Result<CallRequest, Box<dyn Error>> {
+    Ok(CallRequest {
+        from: Some(account),
+        to: Some(quote.router),
+        value: Some(quote.value),
+        data: Some(quote.transaction_data()?),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(byte: u8) -> Asset {
+        Asset::Token(H160::repeat_byte(byte))
+    }
+
+    fn quote(kind: SwapKind, needs_allowance: bool) -> Quote {
+        Quote {
+            router: H160::repeat_byte(9),
+            function: "swapExactTokensForTokens".to_string(),
+            kind,
+            path: vec![H160::repeat_byte(1), H160::repeat_byte(2)],
+            amount_in: U256::from(100),
+            amount_out: U256::from(200),
+            amount_in_max: U256::from(101),
+            amount_out_min: U256::from(199),
+            price_impact_bps: 12,
+            gas_estimate: None,
+            recipient: H160::repeat_byte(7),
+            deadline: U256::from(1000),
+            value: U256::zero(),
+            needs_allowance,
+        }
+    }
+
+    #[test]
+    fn picks_router_functions() {
+        let router = |input, output, kind, fee| router_function(input, output, kind, fee).ok();
+
+        assert_eq!(
+            router(Asset::Native, token(1), SwapKind::ExactIn, false),
+            Some("swapExactETHForTokens")
+        );
+        assert_eq!(
+            router(token(1), Asset::Native, SwapKind::ExactOut, false),
+            Some("swapTokensForExactETH")
+        );
+        assert_eq!(
+            router(token(1), token(2), SwapKind::ExactIn, true),
+            Some("swapExactTokensForTokensSupportingFeeOnTransferTokens")
+        );
+        assert_eq!(router(token(1), token(2), SwapKind::ExactOut, true), None);
+        assert_eq!(
+            router(Asset::Native, Asset::Native, SwapKind::ExactIn, false),
+            None
+        );
+    }
+
+    #[test]
+    fn validates_requests() {
+        let router = H160::repeat_byte(9);
+        let request = SwapRequest::exact_in(router, token(1), token(2), U256::from(10));
+        assert!(request.validate().is_ok());
+        assert!(request
+            .clone()
+            .path(vec![H160::repeat_byte(1)])
+            .validate()
+            .is_err());
+        assert!(
+            SwapRequest::exact_in(router, token(1), token(1), U256::one())
+                .validate()
+                .is_err()
+        );
+        assert!(
+            SwapRequest::exact_out(router, token(1), token(2), U256::zero())
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn call_parameters_follow_router_abi() {
+        let exact_in = quote(SwapKind::ExactIn, true).call_parameters();
+        assert_eq!(exact_in.len(), 5);
+        assert_eq!(exact_in[0], Token::Uint(U256::from(100)));
+        assert_eq!(exact_in[1], Token::Uint(U256::from(199)));
+
+        let exact_out = quote(SwapKind::ExactOut, true).call_parameters();
+        assert_eq!(exact_out[0], Token::Uint(U256::from(200)));
+        assert_eq!(exact_out[1], Token::Uint(U256::from(101)));
+
+        // native input: the amount in is msg.value
+        let native = quote(SwapKind::ExactIn, false).call_parameters();
+        assert_eq!(native.len(), 4);
+        assert_eq!(native[0], Token::Uint(U256::from(199)));
+
+        assert!(quote(SwapKind::ExactIn, true).transaction_data().is_ok());
+    }
+
+    #[test]
+    fn quotes_round_trip_through_json() {
+        let quote = quote(SwapKind::ExactOut, true);
+        assert_eq!(Quote::from_json(&quote.to_json().unwrap()).unwrap(), quote);
+    }
+}
@@ -0,0 +1,381 @@
+//! Decoding of raw signed transactions, ours before they are broadcast or
+//! anyone's fetched from the node.
+//!
+//! Legacy (with or without EIP-155), EIP-2930 and EIP-1559 transactions are
+//! decoded into their fields, the sender is recovered from the signature and
+//! the calldata can be decoded against the bundled router and ERC20 ABIs or
+//! any other `ethabi::Contract`.
+
+use crate::contract_cache::AbiKind;
+use crate::dynamic_abi::token_to_json;
+use crate::signer::{signed_transaction_from_raw, transaction_sender};
+use crate::Web3Manager;
+use rlp::Rlp;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use web3::ethabi;
+use web3::types::{AccessList, AccessListItem, Bytes, H160, H256, U256};
+use web3::Transport;
+
+/// Fields of a raw signed transaction.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransaction {
+    /// 0 for legacy transactions.
+    pub transaction_type: u8,
+    /// `None` for legacy transactions signed without EIP-155.
+    pub chain_id: Option<u64>,
+    pub from: H160,
+    /// `None` for contract deployments.
+    pub to: Option<H160>,
+    pub nonce: U256,
+    pub gas: U256,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub value: U256,
+    pub data: Bytes,
+    pub access_list: AccessList,
+    pub v: u64,
+    pub r: H256,
+    pub s: H256,
+    pub hash: H256,
+}
+
+/// A function call decoded from calldata.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DecodedCall {
+    /// Full signature, e.g. `transfer(address,uint256)`.
+    pub signature: String,
+    pub name: String,
+    /// (parameter name, value) in declaration order, integers as decimal
+    /// strings.
+    pub arguments: Vec<(String, Value)>,
+}
+
+impl DecodedCall {
+    pub fn to_json(&self) -> Value {
+        let arguments: serde_json::Map<String, Value> = self.arguments.iter().cloned().collect();
+        json!({
+            "signature": self.signature,
+            "name": self.name,
+            "arguments": arguments,
+        })
+    }
+}
+
+/// Decodes a raw signed transaction given as hex, with or without `0x`.
+pub fn decode_raw_transaction_hex(raw: &str) -> Result<DecodedTransaction, Box<dyn Error>> {
+    decode_raw_transaction(&hex::decode(raw.trim().trim_start_matches("0x"))?)
+}
+
+/// Decodes a raw signed transaction and recovers its sender.
+pub fn decode_raw_transaction(raw: &[u8]) -> Result<DecodedTransaction, Box<dyn Error>> {
+    let signed = signed_transaction_from_raw(raw.to_vec())?;
+    let from = transaction_sender(&signed)?;
+
+    let (transaction_type, rlp) = match raw[0] {
+        first if first >= 0xc0 => (0, Rlp::new(raw)),
+        first @ (1 | 2) => (first, Rlp::new(&raw[1..])),
+        first => return Err(format!("unsupported transaction type {}", first).into()),
+    };
+    let expected_fields = match transaction_type {
+        0 => 9,
+        1 => 11,
+        _ => 12,
+    };
+    if rlp.item_count()? != expected_fields {
+        return Err(format!(
+            "type {} transaction with {} fields",
+            transaction_type,
+            rlp.item_count()?
+        )
+        .into());
+    }
+
+    // index of the nonce, typed transactions start with the chain id
+    let base = if transaction_type == 0 { 0 } else { 1 };
+    let chain_id = match transaction_type {
+        0 if signed.v >= 35 => Some((signed.v - 35) / 2),
+        0 => None,
+        _ => Some(rlp.val_at(0)?),
+    };
+    let (gas_price, max_priority_fee_per_gas, max_fee_per_gas) = if transaction_type == 2 {
+        (
+            None,
+            Some(rlp.val_at(base + 1)?),
+            Some(rlp.val_at(base + 2)?),
+        )
+    } else {
+        (Some(rlp.val_at(base + 1)?), None, None)
+    };
+    // gas, to, value and data follow the fees
+    let fields = if transaction_type == 2 {
+        base + 3
+    } else {
+        base + 2
+    };
+    let to = if rlp.at(fields + 1)?.is_empty() {
+        None
+    } else {
+        Some(rlp.val_at(fields + 1)?)
+    };
+    let access_list = if transaction_type == 0 {
+        Vec::new()
+    } else {
+        decode_access_list(&rlp.at(fields + 4)?)?
+    };
+
+    Ok(DecodedTransaction {
+        transaction_type,
+        chain_id,
+        from,
+        to,
+        nonce: rlp.val_at(base)?,
+        gas: rlp.val_at(fields)?,
+        gas_price,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        value: rlp.val_at(fields + 2)?,
+        data: Bytes(rlp.val_at(fields + 3)?),
+        access_list,
+        v: signed.v,
+        r: signed.r,
+        s: signed.s,
+        hash: signed.transaction_hash,
+    })
+}
+
+fn decode_access_list(rlp: &Rlp) -> Result<AccessList, Box<dyn Error>> {
+    let mut access_list = Vec::new();
+    for item in rlp.iter() {
+        access_list.push(AccessListItem {
+            address: item.val_at(0)?,
+            storage_keys: item.list_at(1)?,
+        });
+    }
+    Ok(access_list)
+}
+
+/// Decodes `data` as a call to one of the functions of `abi`, `None` when no
+/// function has its selector or the arguments don't decode.
+pub fn decode_calldata(abi: &ethabi::Contract, data: &[u8]) -> Option<DecodedCall> {
+    if data.len() < 4 {
+        return None;
+    }
+    let function = abi
+        .functions()
+        .find(|function| function.short_signature() == data[..4])?;
+    let tokens = function.decode_input(&data[4..]).ok()?;
+    Some(DecodedCall {
+        signature: function.signature().split(':').next()?.to_string(),
+        name: function.name.clone(),
+        arguments: function
+            .inputs
+            .iter()
+            .zip(&tokens)
+            .map(|(param, token)| (param.name.clone(), token_to_json(token)))
+            .collect(),
+    })
+}
+
+impl DecodedTransaction {
+    /// Decodes the calldata with the first of `kinds` that knows its
+    /// selector.
+    pub fn decode_call(&self, kinds: &[AbiKind]) -> Option<DecodedCall> {
+        kinds
+            .iter()
+            .find_map(|kind| decode_calldata(&kind.abi(), &self.data.0))
+    }
+
+    /// Calldata decoded as a router, ERC20 or wrapped native token call.
+    pub fn decode_known_call(&self) -> Option<DecodedCall> {
+        self.decode_call(&[AbiKind::Router, AbiKind::Token, AbiKind::WrappedNative])
+    }
+}
+
+impl Web3Manager {
+    /// Fetches and decodes a transaction as it was signed, the node must
+    /// support `eth_getRawTransactionByHash`.
+    pub async fn inspect_transaction(
+        &self,
+        hash: H256,
+    ) -> Result<(DecodedTransaction, Option<DecodedCall>), Box<dyn Error>> {
+        let raw = self
+            .web3http
+            .transport()
+            .execute("eth_getRawTransactionByHash", vec![json!(hash)])
+            .await?;
+        let raw: Option<Bytes> = serde_json::from_value(raw)?;
+        let raw = raw.ok_or_else(|| format!("transaction {:?} not found", hash))?;
+
+        let transaction = decode_raw_transaction(&raw.0)?;
+        let call = transaction.decode_known_call();
+        Ok((transaction, call))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{LocalSigner, Signer};
+    use std::str::FromStr;
+    use web3::types::{TransactionParameters, U64};
+
+    fn signer() -> LocalSigner {
+        LocalSigner::from_str("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+            .unwrap()
+    }
+
+    fn transfer_data(to: H160, amount: U256) -> Vec<u8> {
+        AbiKind::Token
+            .abi()
+            .function("transfer")
+            .unwrap()
+            .encode_input(&[ethabi::Token::Address(to), ethabi::Token::Uint(amount)])
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn decodes_every_transaction_type() {
+        let token = H160::from_low_u64_be(1);
+        let data = transfer_data(H160::from_low_u64_be(2), U256::from(1_000));
+        let access_list = vec![AccessListItem {
+            address: token,
+            storage_keys: vec![H256::from_low_u64_be(3)],
+        }];
+        let legacy = TransactionParameters {
+            nonce: Some(U256::from(7)),
+            to: Some(token),
+            gas: U256::from(60_000),
+            gas_price: Some(U256::from(5_000_000_000u64)),
+            value: U256::from(9),
+            data: Bytes(data.clone()),
+            chain_id: Some(56),
+            ..Default::default()
+        };
+        let access_list_tx = TransactionParameters {
+            transaction_type: Some(U64::from(1)),
+            access_list: Some(access_list.clone()),
+            ..legacy.clone()
+        };
+        let eip1559 = TransactionParameters {
+            transaction_type: Some(U64::from(2)),
+            gas_price: None,
+            max_fee_per_gas: Some(U256::from(30)),
+            max_priority_fee_per_gas: Some(U256::from(2)),
+            access_list: Some(access_list.clone()),
+            ..legacy.clone()
+        };
+
+        for (kind, tx) in [(0, legacy), (1, access_list_tx), (2, eip1559)] {
+            let signed = signer().sign_transaction(tx.clone()).await.unwrap();
+            let decoded = decode_raw_transaction_hex(&format!(
+                "0x{}",
+                hex::encode(&signed.raw_transaction.0)
+            ))
+            .unwrap();
+
+            assert_eq!(decoded.transaction_type, kind);
+            assert_eq!(decoded.chain_id, Some(56));
+            assert_eq!(decoded.from, signer().address());
+            assert_eq!(decoded.to, Some(token));
+            assert_eq!(decoded.nonce, U256::from(7));
+            assert_eq!(decoded.gas, U256::from(60_000));
+            assert_eq!(decoded.gas_price, tx.gas_price);
+            assert_eq!(decoded.max_fee_per_gas, tx.max_fee_per_gas);
+            assert_eq!(
+                decoded.max_priority_fee_per_gas,
+                tx.max_priority_fee_per_gas
+            );
+            assert_eq!(decoded.value, U256::from(9));
+            assert_eq!(decoded.data.0, data);
+            assert_eq!(decoded.access_list, tx.access_list.unwrap_or_default());
+            assert_eq!(decoded.hash, signed.transaction_hash);
+        }
+    }
+
+    #[test]
+    fn decodes_pre_eip155_transactions() {
+        // contract deployment without a chain id, v is 27 or 28
+        let fields = |stream: &mut rlp::RlpStream| {
+            stream.append(&0u8);
+            stream.append(&U256::from(1_000_000_000u64));
+            stream.append(&21_000u64);
+            stream.append_empty_data();
+            stream.append(&0u8);
+            stream.append(&Vec::<u8>::new());
+        };
+        let signer = signer();
+        let mut unsigned = rlp::RlpStream::new_list(6);
+        fields(&mut unsigned);
+        let digest = H256(web3::signing::keccak256(&unsigned.out()));
+        let signature = signer.sign_digest(digest).unwrap();
+
+        let mut raw = rlp::RlpStream::new_list(9);
+        fields(&mut raw);
+        raw.append(&(signature.v as u64));
+        raw.append(&U256::from(signature.r.as_bytes()));
+        raw.append(&U256::from(signature.s.as_bytes()));
+
+        let decoded = decode_raw_transaction(&raw.out()).unwrap();
+        assert_eq!(decoded.chain_id, None);
+        assert_eq!(decoded.to, None);
+        assert_eq!(decoded.from, signer.address());
+    }
+
+    #[test]
+    fn decodes_router_and_token_calldata() {
+        let recipient = H160::from_low_u64_be(2);
+        let transfer = DecodedTransaction {
+            transaction_type: 0,
+            chain_id: Some(56),
+            from: H160::zero(),
+            to: Some(H160::from_low_u64_be(1)),
+            nonce: U256::zero(),
+            gas: U256::zero(),
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            value: U256::zero(),
+            data: Bytes(transfer_data(recipient, U256::from(1_000))),
+            access_list: Vec::new(),
+            v: 0,
+            r: H256::zero(),
+            s: H256::zero(),
+            hash: H256::zero(),
+        };
+        let call = transfer.decode_known_call().unwrap();
+        assert_eq!(call.signature, "transfer(address,uint256)");
+        assert_eq!(call.arguments[0].1, json!(format!("{:?}", recipient)));
+        assert_eq!(call.arguments[1].1, json!("1000"));
+
+        let path = vec![ethabi::Token::Address(H160::from_low_u64_be(3)); 2];
+        let swap = AbiKind::Router
+            .abi()
+            .function("swapExactETHForTokens")
+            .unwrap()
+            .encode_input(&[
+                ethabi::Token::Uint(U256::from(5)),
+                ethabi::Token::Array(path),
+                ethabi::Token::Address(recipient),
+                ethabi::Token::Uint(U256::from(1_700_000_000u64)),
+            ])
+            .unwrap();
+        let call = DecodedTransaction {
+            data: Bytes(swap),
+            ..transfer.clone()
+        }
+        .decode_known_call()
+        .unwrap();
+        assert_eq!(call.name, "swapExactETHForTokens");
+        assert_eq!(call.to_json()["arguments"]["amountOutMin"], json!("5"));
+
+        let unknown = DecodedTransaction {
+            data: Bytes(vec![0xde, 0xad, 0xbe, 0xef]),
+            ..transfer
+        };
+        assert_eq!(unknown.decode_known_call(), None);
+    }
+}
@@ -0,0 +1,454 @@
+//! Tracking of the transactions sent by the manager until they settle.
+//!
+//! Every raw transaction sent through `send_raw_transaction_tracked` (so
+//! every `sign_and_send_tx`, `sent_eth`, prebuilt buy, replacement and bundle
+//! broadcast) is recorded with its account and nonce. Polling, or watching
+//! new blocks, moves each one out of `Pending`:
+//!
+//! - `Mined` or `Reverted` once it has a receipt,
+//! - `Replaced` when its nonce was used by another transaction, pointing at
+//!   the tracked one that was mined if any,
+//! - `Dropped` when its nonce is still free but the node forgot about it,
+//!   which leaves a gap that blocks the later nonces of the account.
+//!
+//! The gas paid by the mined and reverted ones goes to the gas ledger.
+
+use crate::tx_inspector::decode_raw_transaction;
+use crate::Web3Manager;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use web3::types::{
+    BlockNumber, Bytes, TransactionId, TransactionParameters, TransactionReceipt, H160, H256, U256,
+    U64,
+};
+
+/// Time the node is given to know a sent transaction before it is reported
+/// as dropped, load balanced RPC endpoints don't share their mempool
+/// instantly.
+pub const DROPPED_AFTER: Duration = Duration::from_secs(60);
+
+const TRANSFER_GAS: u64 = 21_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Pending,
+    Mined {
+        block_number: Option<U64>,
+        gas_used: Option<U256>,
+    },
+    Reverted {
+        block_number: Option<U64>,
+    },
+    /// Nonce used by another transaction, `by` the tracked one if known.
+    Replaced {
+        by: Option<H256>,
+    },
+    Dropped,
+}
+
+impl TransactionStatus {
+    pub(crate) fn from_receipt(receipt: &TransactionReceipt) -> TransactionStatus {
+        if receipt.status == Some(U64::from(0)) {
+            TransactionStatus::Reverted {
+                block_number: receipt.block_number,
+            }
+        } else {
+            TransactionStatus::Mined {
+                block_number: receipt.block_number,
+                gas_used: receipt.gas_used,
+            }
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        *self == TransactionStatus::Pending
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedTransaction {
+    pub hash: H256,
+    pub account: H160,
+    pub nonce: U256,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub gas: U256,
+    /// Gas price, or max fee per gas of EIP-1559 transactions.
+    pub max_price_per_gas: U256,
+    /// Gas run it was sent in.
+    pub run: Option<String>,
+    pub sent_at: Instant,
+    pub status: TransactionStatus,
+}
+
+impl TrackedTransaction {
+    /// Most it can cost in gas, if all of its gas is used at its highest
+    /// price.
+    pub fn max_gas_cost(&self) -> U256 {
+        self.gas * self.max_price_per_gas
+    }
+}
+
+type TransactionMap = HashMap<H256, TrackedTransaction>;
+
+/// Transactions sent by a manager, shared between its clones like the
+/// contract cache.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionTracker {
+    transactions: Arc<RwLock<TransactionMap>>,
+}
+
+impl TransactionTracker {
+    pub fn new() -> TransactionTracker {
+        TransactionTracker::default()
+    }
+
+    pub fn record(&self, transaction: TrackedTransaction) {
+        self.transactions
+            .write()
+            .unwrap()
+            .insert(transaction.hash, transaction);
+    }
+
+    pub fn get(&self, hash: H256) -> Option<TrackedTransaction> {
+        self.transactions.read().unwrap().get(&hash).cloned()
+    }
+
+    /// Every tracked transaction of `account`, by nonce.
+    pub fn transactions(&self, account: H160) -> Vec<TrackedTransaction> {
+        let mut transactions: Vec<TrackedTransaction> = self
+            .transactions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|transaction| transaction.account == account)
+            .cloned()
+            .collect();
+        transactions.sort_by_key(|transaction| (transaction.nonce, transaction.sent_at));
+        transactions
+    }
+
+    /// Pending transactions of `account`, by nonce.
+    pub fn in_flight(&self, account: H160) -> Vec<TrackedTransaction> {
+        let mut transactions = self.transactions(account);
+        transactions.retain(|transaction| transaction.status.is_pending());
+        transactions
+    }
+
+    /// Pending transactions of `account` whose nonce no tracked transaction
+    /// was mined with yet, the ones that can still cost gas.
+    pub fn unmined_in_flight(&self, account: H160) -> Vec<TrackedTransaction> {
+        let transactions = self.transactions(account);
+        let mined = mined_by_nonce(&transactions);
+        transactions
+            .into_iter()
+            .filter(|transaction| {
+                transaction.status.is_pending() && !mined.contains_key(&transaction.nonce)
+            })
+            .collect()
+    }
+
+    /// Accounts with pending transactions.
+    pub fn accounts_in_flight(&self) -> Vec<H160> {
+        let mut accounts: Vec<H160> = self
+            .transactions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|transaction| transaction.status.is_pending())
+            .map(|transaction| transaction.account)
+            .collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+
+    /// Updates the status of a transaction, returns it when it changed.
+    pub fn set_status(&self, hash: H256, status: TransactionStatus) -> Option<TrackedTransaction> {
+        let mut transactions = self.transactions.write().unwrap();
+        let transaction = transactions.get_mut(&hash)?;
+        if transaction.status == status {
+            return None;
+        }
+        transaction.status = status;
+        Some(transaction.clone())
+    }
+
+    /// Marks as replaced the pending transactions of `account` below
+    /// `next_nonce`, the first nonce not mined yet. Returns the ones updated.
+    pub fn mark_replaced(&self, account: H160, next_nonce: U256) -> Vec<TrackedTransaction> {
+        let transactions = self.transactions(account);
+        let mined_by_nonce = mined_by_nonce(&transactions);
+
+        transactions
+            .iter()
+            .filter(|transaction| transaction.status.is_pending() && transaction.nonce < next_nonce)
+            .filter_map(|transaction| {
+                let by = mined_by_nonce.get(&transaction.nonce).copied();
+                self.set_status(transaction.hash, TransactionStatus::Replaced { by })
+            })
+            .collect()
+    }
+
+    /// Nonces from `next_nonce` up to the last in-flight one that no pending
+    /// transaction of `account` uses. Transactions after a gap can't be
+    /// mined until it is filled.
+    pub fn nonce_gaps(&self, account: H160, next_nonce: U256) -> Vec<U256> {
+        let in_flight = self.in_flight(account);
+        let last_nonce = match in_flight.last() {
+            Some(transaction) => transaction.nonce,
+            None => return Vec::new(),
+        };
+        let mut gaps = Vec::new();
+        let mut nonce = next_nonce;
+        while nonce < last_nonce {
+            if !in_flight
+                .iter()
+                .any(|transaction| transaction.nonce == nonce)
+            {
+                gaps.push(nonce);
+            }
+            nonce += U256::one();
+        }
+        gaps
+    }
+
+    /// Forgets every transaction that is no longer pending.
+    pub fn prune_settled(&self) {
+        self.transactions
+            .write()
+            .unwrap()
+            .retain(|_, transaction| transaction.status.is_pending());
+    }
+}
+
+// Hash of the mined or reverted transaction of each nonce
+fn mined_by_nonce(transactions: &[TrackedTransaction]) -> HashMap<U256, H256> {
+    transactions
+        .iter()
+        .filter(|transaction| {
+            matches!(
+                transaction.status,
+                TransactionStatus::Mined { .. } | TransactionStatus::Reverted { .. }
+            )
+        })
+        .map(|transaction| (transaction.nonce, transaction.hash))
+        .collect()
+}
+
+impl Web3Manager {
+    pub fn tx_tracker(&self) -> &TransactionTracker {
+        &self.tx_tracker
+    }
+
+    /// Sends a raw signed transaction and tracks it.
+    pub async fn send_raw_transaction_tracked(&self, raw: Bytes) -> Result<H256, Box<dyn Error>> {
+        let decoded = decode_raw_transaction(&raw.0)?;
+        let hash = self.web3http.eth().send_raw_transaction(raw).await?;
+        self.tx_tracker.record(TrackedTransaction {
+            hash,
+            account: decoded.from,
+            nonce: decoded.nonce,
+            to: decoded.to,
+            value: decoded.value,
+            gas: decoded.gas,
+            max_price_per_gas: decoded
+                .max_fee_per_gas
+                .or(decoded.gas_price)
+                .unwrap_or_default(),
+            run: self.gas_ledger().current_run(),
+            sent_at: Instant::now(),
+            status: TransactionStatus::Pending,
+        });
+        Ok(hash)
+    }
+
+    /// Checks every pending transaction once and returns the ones whose
+    /// status changed.
+    pub async fn poll_tracked_transactions(
+        &self,
+    ) -> Result<Vec<TrackedTransaction>, Box<dyn Error>> {
+        let eth = self.web3http.eth();
+        let mut changed = Vec::new();
+        for account in self.tx_tracker.accounts_in_flight() {
+            // read before the receipts, a transaction mined in between is
+            // then seen with its receipt and never reported as replaced
+            let next_nonce = eth
+                .transaction_count(account, Some(BlockNumber::Latest))
+                .await?;
+
+            for transaction in self.tx_tracker.in_flight(account) {
+                if let Some(receipt) = eth.transaction_receipt(transaction.hash).await? {
+                    // before recording the gas, which settles it as well
+                    changed.extend(
+                        self.tx_tracker.set_status(
+                            transaction.hash,
+                            TransactionStatus::from_receipt(&receipt),
+                        ),
+                    );
+                    self.record_gas_cost(&receipt).await;
+                }
+            }
+            changed.extend(self.tx_tracker.mark_replaced(account, next_nonce));
+
+            for transaction in self.tx_tracker.in_flight(account) {
+                if transaction.sent_at.elapsed() < DROPPED_AFTER {
+                    continue;
+                }
+                let known = eth
+                    .transaction(TransactionId::Hash(transaction.hash))
+                    .await?
+                    .is_some();
+                if !known {
+                    changed.extend(
+                        self.tx_tracker
+                            .set_status(transaction.hash, TransactionStatus::Dropped),
+                    );
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Polls the tracked transactions on every new block until none is
+    /// pending or `timeout` expires, and returns every status change seen.
+    pub async fn watch_tracked_transactions(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<TrackedTransaction>, Box<dyn Error>> {
+        let mut new_heads = self
+            .web3web_socket
+            .eth_subscribe()
+            .subscribe_new_heads()
+            .await?;
+        let deadline = Instant::now() + timeout;
+        let mut changed = self.poll_tracked_transactions().await?;
+        while !self.tx_tracker.accounts_in_flight().is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match tokio::time::timeout(remaining, new_heads.next()).await {
+                Ok(Some(head)) => {
+                    head?;
+                    changed.extend(self.poll_tracked_transactions().await?);
+                }
+                Ok(None) => return Err("new heads subscription closed".into()),
+                Err(_) => break,
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Sends a zero-value transfer to `account` itself for each nonce gap,
+    /// so the in-flight transactions after it can be mined.
+    pub async fn fill_nonce_gaps(&self, account: H160) -> Result<Vec<H256>, Box<dyn Error>> {
+        let next_nonce = self
+            .web3http
+            .eth()
+            .transaction_count(account, Some(BlockNumber::Latest))
+            .await?;
+        let mut hashes = Vec::new();
+        for nonce in self.tx_tracker.nonce_gaps(account, next_nonce) {
+            let parameters = TransactionParameters {
+                nonce: Some(nonce),
+                to: Some(account),
+                value: U256::zero(),
+                gas: U256::from(TRANSFER_GAS),
+                ..Default::default()
+            };
+            let signed = self.sign_transaction(account, parameters).await?;
+            hashes.push(
+                self.send_raw_transaction_tracked(signed.raw_transaction)
+                    .await?,
+            );
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(hash: u64, nonce: u64) -> TrackedTransaction {
+        TrackedTransaction {
+            hash: H256::from_low_u64_be(hash),
+            account: H160::from_low_u64_be(1),
+            nonce: U256::from(nonce),
+            to: None,
+            value: U256::zero(),
+            gas: U256::from(TRANSFER_GAS),
+            max_price_per_gas: U256::from(10),
+            run: None,
+            sent_at: Instant::now(),
+            status: TransactionStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn marks_replaced_transactions() {
+        let tracker = TransactionTracker::new();
+        let account = H160::from_low_u64_be(1);
+        tracker.record(tracked(1, 5));
+        tracker.record(tracked(2, 5));
+        tracker.record(tracked(3, 6));
+        tracker.record(tracked(4, 7));
+        tracker.set_status(
+            H256::from_low_u64_be(2),
+            TransactionStatus::Mined {
+                block_number: None,
+                gas_used: None,
+            },
+        );
+
+        // the replaced one can't cost gas anymore
+        let unmined: Vec<H256> = tracker
+            .unmined_in_flight(account)
+            .iter()
+            .map(|transaction| transaction.hash)
+            .collect();
+        assert_eq!(
+            unmined,
+            vec![H256::from_low_u64_be(3), H256::from_low_u64_be(4)]
+        );
+
+        // nonce 5 and 6 are mined, 6 by a transaction that isn't tracked
+        let replaced = tracker.mark_replaced(account, U256::from(7));
+        assert_eq!(replaced.len(), 2);
+        assert_eq!(
+            tracker.get(H256::from_low_u64_be(1)).unwrap().status,
+            TransactionStatus::Replaced {
+                by: Some(H256::from_low_u64_be(2))
+            }
+        );
+        assert_eq!(
+            tracker.get(H256::from_low_u64_be(3)).unwrap().status,
+            TransactionStatus::Replaced { by: None }
+        );
+
+        let in_flight = tracker.in_flight(account);
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(in_flight[0].nonce, U256::from(7));
+        assert_eq!(tracker.accounts_in_flight(), vec![account]);
+
+        tracker.prune_settled();
+        assert_eq!(tracker.transactions(account).len(), 1);
+    }
+
+    #[test]
+    fn finds_nonce_gaps() {
+        let tracker = TransactionTracker::new();
+        let account = H160::from_low_u64_be(1);
+        assert!(tracker.nonce_gaps(account, U256::from(3)).is_empty());
+
+        tracker.record(tracked(1, 4));
+        tracker.record(tracked(2, 5));
+        tracker.record(tracked(3, 7));
+        tracker.set_status(H256::from_low_u64_be(2), TransactionStatus::Dropped);
+        assert_eq!(
+            tracker.nonce_gaps(account, U256::from(3)),
+            vec![U256::from(3), U256::from(5), U256::from(6)]
+        );
+    }
+}
@@ -6,12 +6,19 @@ use std::env;
 use std::process::exit;
 use std::str::FromStr;
 use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use textplots::{Chart, Plot, Shape};
 use web3::ethabi::Uint;
 use web3::helpers as w3h;
 use web3::types::{Address, H160, H256, U256};
-use web3_rust_wrapper::Web3Manager;
+use web3_rust_wrapper::amm::PANCAKE_V2_FEE_BPS;
+use web3_rust_wrapper::reserve_tracker::ReserveTracker;
+use web3_rust_wrapper::{Slippage, Web3Manager};
+
+const BUY_PENDING_TIMEOUT: Duration = Duration::from_secs(15);
+const BUY_SPEED_UP_PERCENT: u64 = 20;
+const BUY_MAX_SPEED_UPS: usize = 3;
+const GAS_RECEIPT_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub fn clear_screen() {
     print!("{}[2J", 27 as char);
@@ -30,29 +37,36 @@ pub fn open_tx_on_browser(tx_result: Result<H256, web3::Error>) {
     }
 }
 
-pub async fn check_has_liquidity(web3m: &mut Web3Manager, token_lp_address: &str) -> bool {
-    let mut has_liquidity: bool = false;
-    while !has_liquidity {
-        let lp_pair_instance = web3m.init_pair(token_lp_address).await;
-        has_liquidity = web3m.token_has_liquidity(lp_pair_instance).await;
-
-        let now = Utc::now();
-        let (_, hour) = now.hour12();
+pub async fn check_has_liquidity(
+    web3m: &mut Web3Manager,
+    factory_address: &str,
+    weth_address: &str,
+    token_address: &str,
+) -> bool {
+    let liquidity = web3m
+        .watch_for_liquidity(factory_address, weth_address, token_address, |event| {
+            let now = Utc::now();
+            let (_, hour) = now.hour12();
+
+            println!(
+                "{}{:02}:{:02}:{:02}{}{}{}{}{:?} {} {} {}",
+                "[".yellow(),
+                hour.to_string().cyan(),
+                now.minute().to_string().cyan(),
+                now.second().to_string().cyan(),
+                "]".yellow(),
+                "[".yellow(),
+                "HAS LIQUIDITY".cyan(),
+                "]".yellow(),
+                event.pair,
+                event.reserve0,
+                event.reserve1,
+                event.block_number.unwrap_or_default(),
+            );
+        })
+        .await;
 
-        println!(
-            "{}{:02}:{:02}:{:02}{}{}{}{}{}",
-            "[".yellow(),
-            hour.to_string().cyan(),
-            now.minute().to_string().cyan(),
-            now.second().to_string().cyan(),
-            "]".yellow(),
-            "[".yellow(),
-            "HAS LIQUIDITY".cyan(),
-            "]".yellow(),
-            has_liquidity,
-        );
-    }
-    has_liquidity
+    liquidity.is_ok()
 }
 
 pub async fn get_token_price_info(
@@ -68,6 +82,25 @@ pub async fn get_token_price_info(
     (token_price, price_change_percent)
 }
 
+pub fn get_tracked_token_price_info(
+    reserve_tracker: &ReserveTracker,
+    token_address: &str,
+    buy_price: U256,
+) -> (U256, f64) {
+    let token_price = reserve_tracker
+        .snapshot()
+        .amount_out(
+            H160::from_str(token_address).unwrap(),
+            U256::exp10(18),
+            PANCAKE_V2_FEE_BPS,
+        )
+        .unwrap_or_default();
+    let price_change_percent =
+        calc_price_change_percent(wei_to_eth(buy_price, 18), wei_to_eth(token_price, 18));
+
+    (token_price, price_change_percent)
+}
+
 pub async fn hit_take_profit_or_stop_loss(
     price_change_percent: f64,
     take_profit_pencent: f64,
@@ -89,16 +122,14 @@ pub async fn check_before_buy(
     let factory_address = web3m.get_factory_address(&router_contract).await;
     let weth_address = web3m.get_weth_address(&router_contract).await;
 
-    let token_lp_address = web3m
-        .find_lp_pair(
-            weth_address.as_str(),
-            factory_address.as_str(),
-            token_address,
-        )
-        .await;
-
     // 1. CHECK IF TOKEN HAS LIQUIDITY
-    check_has_liquidity(web3m, token_lp_address.as_str()).await;
+    check_has_liquidity(
+        web3m,
+        factory_address.as_str(),
+        weth_address.as_str(),
+        token_address,
+    )
+    .await;
 
     // 2. CHECK TRADING ENABLE
     check_trading_enable(web3m, account, router_address, token_address).await;
@@ -120,6 +151,7 @@ pub async fn check_trading_enable(
     let mut slippage = 1usize;
     let max_slippage = 99usize;
 
+    web3m.start_gas_run("trading check");
     while !is_enabled {
         let now = Utc::now();
         let (_, hour) = now.hour12();
@@ -145,26 +177,32 @@ pub async fn check_trading_enable(
                     router_address,
                     token_address,
                     U256::from_str("1000000000").unwrap(), // try buy 10 GWei 10000000000 -> 0.00000001 BNB
-                    slippage,
+                    Slippage::from_percent(slippage as f64).unwrap(),
                 )
                 .await;
 
-            if tx_result.is_ok() {
-                is_enabled = true;
-                println!("{}", "BUY OK".green());
-                println!(
-                    "Tx Hash {}",
-                    w3h::to_string(&tx_result.unwrap())
-                        .replace("\"", "")
-                        .as_str()
-                );
-            } else {
-                println!("{}", tx_result.err().unwrap().to_string().red());
-                slippage += 1;
-
-                if slippage == max_slippage {
-                    println!("{}", "Max slipagge".red());
-                    exit(0);
+            match tx_result {
+                Ok(tx_hash) => {
+                    is_enabled = true;
+                    println!("{}", "BUY OK".green());
+                    println!(
+                        "Tx Hash {}",
+                        w3h::to_string(&tx_hash).replace("\"", "").as_str()
+                    );
+                    // the receipt records the gas paid for the check
+                    if let Err(err) = web3m.wait_for_receipt(tx_hash, GAS_RECEIPT_TIMEOUT).await {
+                        println!("{}", err.to_string().red());
+                    }
+                }
+                Err(err) => {
+                    println!("{}", err.to_string().red());
+                    slippage += 1;
+
+                    if slippage == max_slippage {
+                        println!("{}", "Max slipagge".red());
+                        print_gas_spent(web3m);
+                        exit(0);
+                    }
                 }
             }
 
@@ -172,9 +210,16 @@ pub async fn check_trading_enable(
             //thread::sleep(ten_millis);
         }
     }
+    print_gas_spent(web3m);
     is_enabled
 }
 
+// Ends the gas run of the manager and prints what it spent
+fn print_gas_spent(web3m: &Web3Manager) {
+    let spent = web3m.end_gas_run();
+    println!("{} {}", "GAS SPENT".cyan(), wei_to_eth(spent, 18));
+}
+
 pub async fn do_real_sell(
     web3m: &mut Web3Manager,
     account: H160,
@@ -188,9 +233,25 @@ pub async fn do_real_sell(
 ) -> bool {
     let mut sell_tx_ok: bool = false;
 
+    // prices come from the pair Sync logs instead of a router call per loop
+    let router_contract = web3m.init_router(router_address).await;
+    let factory_address = web3m.get_factory_address(&router_contract).await;
+    let weth_address = web3m.get_weth_address(&router_contract).await;
+    let token_lp_address = web3m
+        .find_lp_pair(
+            weth_address.as_str(),
+            factory_address.as_str(),
+            token_address,
+        )
+        .await;
+    let mut reserve_tracker = web3m
+        .track_reserves(H160::from_str(token_lp_address.as_str()).unwrap())
+        .await
+        .unwrap();
+
     //let token_balance = web3m.get_token_balance(token_address, account).await;
     let (mut token_price, mut price_change_percent) =
-        get_token_price_info(web3m, router_address, token_address, buy_price).await;
+        get_tracked_token_price_info(&reserve_tracker, token_address, buy_price);
     let mut last_token_price = token_price;
     let mut price_hit_take_profit_ath = false;
     let mut token_ath_price = token_price;
@@ -199,7 +260,7 @@ pub async fn do_real_sell(
 
         // GET TOKEN PRICE AND CHANGE PERCENTAGE
         (token_price, price_change_percent) =
-            get_token_price_info(web3m, router_address, token_address, buy_price).await;
+            get_tracked_token_price_info(&reserve_tracker, token_address, buy_price);
 
         let ath_price_change_percent =
             calc_price_change_percent(wei_to_eth(token_ath_price, 18), wei_to_eth(token_price, 18));
@@ -290,6 +351,14 @@ pub async fn do_real_sell(
             println!("sell_stop_loss_price: {:?}", token_price);
             sell_tx_ok = true;
         }
+
+        // WAIT FOR THE NEXT RESERVES UPDATE
+        if !sell_tx_ok && reserve_tracker.changed().await.is_err() {
+            reserve_tracker = web3m
+                .track_reserves(H160::from_str(token_lp_address.as_str()).unwrap())
+                .await
+                .unwrap();
+        }
     }
     sell_tx_ok
 }
@@ -314,10 +383,26 @@ pub async fn do_real_buy(
                 router_address,
                 token_address,
                 invest_amount,
-                slippage,
+                Slippage::from_percent(slippage as f64).unwrap(),
             )
             .await;
 
+        // a buy left pending by a gas price spike is sped up instead of
+        // sending a second one
+        let tx_result = match tx_result {
+            Ok(tx_hash) => {
+                web3m
+                    .wait_or_speed_up(
+                        tx_hash,
+                        BUY_PENDING_TIMEOUT,
+                        BUY_SPEED_UP_PERCENT,
+                        BUY_MAX_SPEED_UPS,
+                    )
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
         if tx_result.is_ok() {
             is_purchased = true;
         } else {
@@ -374,20 +459,10 @@ pub async fn check_honeypot(
         token_balance = web3m.get_token_balance(token_address, account).await;
     }
 
-    let router_address_h160: H160 = H160::from_str(router_address).unwrap();
-    let mut token_allowance = web3m
-        .get_token_allowance(token_address, account, router_address_h160)
-        .await;
-
-    if token_allowance == U256::from_str("0").unwrap() {
-        do_approve(web3m, token_address, router_address, account).await;
-
-        while token_allowance == U256::from_str("0").unwrap() {
-            token_allowance = web3m
-                .get_token_allowance(token_address, account, router_address_h160)
-                .await;
-        }
-    }
+    web3m
+        .ensure_allowance(account, token_address, router_address, token_balance)
+        .await
+        .unwrap();
 
     while is_honey_pot {
         let slipagge = 10usize;
@@ -398,7 +473,7 @@ pub async fn check_honeypot(
                 router_address,
                 token_address,
                 token_balance, // 1 token,
-                slipagge,
+                Slippage::from_percent(slipagge as f64).unwrap(),
             )
             .await;
 
@@ -443,6 +518,7 @@ pub async fn sell_all(
 
     let slippage = 10usize;
 
+    web3m.start_gas_run("sell all");
     while !sell_ok {
         let token_balance = web3m.get_token_balance(token_address, account).await;
 
@@ -452,18 +528,24 @@ pub async fn sell_all(
                 router_address,
                 token_address,
                 token_balance,
-                slippage,
+                Slippage::from_percent(slippage as f64).unwrap(),
             )
             .await;
 
-        if tx_result.is_ok() {
-            println!("{}", "Sell Tx Completed Successfully".green());
+        match tx_result {
+            Ok(tx_hash) => {
+                println!("{}", "Sell Tx Completed Successfully".green());
+                // the receipt records the gas paid for the sell
+                if let Err(err) = web3m.wait_for_receipt(tx_hash, GAS_RECEIPT_TIMEOUT).await {
+                    println!("{}", err.to_string().red());
+                }
 
-            sell_ok = true;
-        } else {
-            println!("{}", tx_result.err().unwrap().to_string().red());
+                sell_ok = true;
+            }
+            Err(err) => println!("{}", err.to_string().red()),
         }
     }
+    print_gas_spent(web3m);
 }
 
 pub async fn get_env_variables() -> (String, String, String, f64, f64, f64, f64, f64) {
@@ -507,24 +589,6 @@ pub fn calc_price_change_percent(old_price: f64, new_price: f64) -> f64 {
     return -1.0 * ((old_price - new_price) / new_price * 100.0);
 }
 
-pub async fn do_approve(
-    web3m: &mut Web3Manager,
-    token_address: &str,
-    router_address: &str,
-    account: H160,
-) {
-    let approve_tx = web3m
-        .approve_erc20_token(
-            account,
-            token_address,
-            router_address,
-            "1000000000000000000000000000000",
-        )
-        .await
-        .unwrap();
-    println!("approve_tx {:?}", approve_tx);
-}
-
 pub fn split_vector_in_chunks(data: Vec<Uint>, chunk_size: usize) -> Vec<Vec<Uint>> {
     let mut results = vec![];
     let mut current = vec![];
@@ -0,0 +1,156 @@
+//! Wrapping the native currency into its WETH9 style token (WETH on
+//! Ethereum, WBNB on BSC) with `deposit` and back with `withdraw`, so routes
+//! between tokens can start from the wrapped token.
+//!
+//! The wrapped token is the `WETH()` of a router when one is given, it is the
+//! same token the router swaps native currency through. Without a router it
+//! is the canonical wrapped token of the chain of the manager.
+
+use crate::amount::Amount;
+use crate::Web3Manager;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+use web3::types::{H160, H256, U256};
+
+const WRAP_RECEIPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Canonical wrapped native token of the chains with a `Network`.
+pub fn wrapped_native_address(chain_id: u64) -> Option<H160> {
+    let address = match chain_id {
+        // WETH
+        1 => "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        5 => "0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6",
+        11155111 => "0xfFf9976782d46CC05630D1f6eBAb18b2324d6B14",
+        // WBNB
+        56 => "0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c",
+        97 => "0xae13d989daC2f0dEbFf460aC112a837C89BAa7cd",
+        _ => return None,
+    };
+    Some(H160::from_str(address).expect("known addresses are valid"))
+}
+
+/// A mined wrap or unwrap and the balances it left in the account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrapOutcome {
+    pub transaction_hash: H256,
+    pub wrapped_token: H160,
+    pub native_balance: U256,
+    pub wrapped_balance: U256,
+}
+
+impl Web3Manager {
+    /// The `WETH()` of `router_address`, or the wrapped native token of the
+    /// chain without a router.
+    pub async fn wrapped_native(
+        &self,
+        router_address: Option<&str>,
+    ) -> Result<H160, Box<dyn Error>> {
+        if let Some(router_address) = router_address {
+            let router = self.router_bindings(H160::from_str(router_address)?);
+            return Ok(router.weth().await?);
+        }
+        self.chain_id
+            .and_then(wrapped_native_address)
+            .ok_or_else(|| {
+                format!("no known wrapped native token on chain {:?}", self.chain_id).into()
+            })
+    }
+
+    /// Wraps `amount` of native currency of `account` and waits until it is
+    /// mined.
+    pub async fn wrap_native(
+        &mut self,
+        account: H160,
+        router_address: Option<&str>,
+        amount: Amount,
+    ) -> Result<WrapOutcome, Box<dyn Error>> {
+        let wrapped_token = self.wrapped_native(router_address).await?;
+        let balance = self.web3http.eth().balance(account, None).await?;
+        if balance < amount.as_wei() {
+            return Err(
+                format!("can't wrap {}, the balance is {}", amount, Amount(balance)).into(),
+            );
+        }
+
+        let tx_hash = self
+            .wrapped_native_bindings(wrapped_token)
+            .deposit(self, account, amount.as_wei())
+            .await?;
+        self.wrap_outcome(account, wrapped_token, tx_hash).await
+    }
+
+    /// Unwraps `amount` of the wrapped native token of `account` and waits
+    /// until it is mined.
+    pub async fn unwrap_native(
+        &mut self,
+        account: H160,
+        router_address: Option<&str>,
+        amount: Amount,
+    ) -> Result<WrapOutcome, Box<dyn Error>> {
+        let wrapped_token = self.wrapped_native(router_address).await?;
+        let wrapped = self.wrapped_native_bindings(wrapped_token);
+        let balance = wrapped.balance_of(account).await?;
+        if balance < amount.as_wei() {
+            return Err(format!(
+                "can't unwrap {}, the wrapped balance is {}",
+                amount,
+                Amount(balance)
+            )
+            .into());
+        }
+
+        let tx_hash = wrapped.withdraw(self, account, amount.as_wei()).await?;
+        self.wrap_outcome(account, wrapped_token, tx_hash).await
+    }
+
+    async fn wrap_outcome(
+        &self,
+        account: H160,
+        wrapped_token: H160,
+        tx_hash: H256,
+    ) -> Result<WrapOutcome, Box<dyn Error>> {
+        // fails when the transaction reverted
+        self.wait_for_receipt(tx_hash, WRAP_RECEIPT_TIMEOUT).await?;
+        Ok(WrapOutcome {
+            transaction_hash: tx_hash,
+            wrapped_token,
+            native_balance: self.web3http.eth().balance(account, None).await?,
+            wrapped_balance: self
+                .wrapped_native_bindings(wrapped_token)
+                .balance_of(account)
+                .await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract_cache::AbiKind;
+    use crate::Network;
+
+    #[test]
+    fn knows_the_wrapped_token_of_each_network() {
+        assert_eq!(
+            wrapped_native_address(Network::BSCMainnet as u64),
+            Some(H160::from_str("0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c").unwrap())
+        );
+        assert!(wrapped_native_address(Network::ETHMainnet as u64).is_some());
+        assert!(wrapped_native_address(Network::BSCTestnet as u64).is_some());
+        assert_eq!(wrapped_native_address(1337), None);
+    }
+
+    #[test]
+    fn wrapped_native_abi_is_weth9() {
+        let abi = AbiKind::WrappedNative.abi();
+        let deposit = abi.function("deposit").unwrap();
+        assert!(deposit.inputs.is_empty());
+        assert_eq!(deposit.short_signature(), [0xd0, 0xe3, 0x0d, 0xb0]);
+        // withdraw(uint256)
+        assert_eq!(
+            abi.function("withdraw").unwrap().short_signature(),
+            [0x2e, 0x1a, 0x7d, 0x4d]
+        );
+    }
+}